@@ -73,6 +73,7 @@ pub fn compressed_len_d_sink(b: &[u8]) -> usize {
     enc.total_out() as usize
 }
 
+#[allow(clippy::unbuffered_bytes)]
 pub fn compressed_len_d_legacy(b: &[u8]) -> usize {
     use flate2::{Compression, bufread::DeflateEncoder};
     use std::io::Read;
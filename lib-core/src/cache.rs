@@ -0,0 +1,283 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    cfg::{ConfigMap, acfg},
+    min::{Minifier, VerifyMinified},
+};
+
+acfg!(
+    /// A persistent cache of optimized entries. It is disabled (`None`) by default.
+    EntryCache: Option<Cache>
+);
+
+const MAGIC: &[u8; 4] = b"MCRC";
+const FLAG_COMPRESS: u8 = 1;
+const FLAG_FAILED: u8 = 2;
+
+/// A key of a cached entry. It is a hash of entry data, a minifier and its config.
+pub struct CacheKey([u8; 32]);
+impl CacheKey {
+    /// Creates a key for data optimized by a minifier. Verified results (see [`VerifyMinified`]) have different keys.
+    /// Returns `None` if results of the minifier cannot be cached (like nested archives).
+    #[must_use]
+    pub fn new(m: &Minifier, cfgmap: &ConfigMap, data: &[u8]) -> Option<Self> {
+        let fp = m.fingerprint(cfgmap)?;
+        let mut h = Sha256::new();
+        h.update(env!("CARGO_PKG_VERSION"));
+        h.update([0]);
+        h.update(fp);
+        h.update([0, u8::from(*cfgmap.fetch::<VerifyMinified>())]);
+        h.update(data);
+        Some(Self(h.finalize().into()))
+    }
+
+    fn hex(&self) -> String {
+        self.0.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+/// A directory with results of optimized entries. Each result is stored in a file named after its key.
+///
+/// A result contains optimized data (or an error message) and a decision if the data should be compressed.
+/// Files are touched when they are read, so the least recently used ones are removed first by [`Cache::prune`].
+pub struct Cache {
+    dir: PathBuf,
+}
+impl Cache {
+    /// Opens a cache directory (it is created if it does not exist).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Returns the cache directory.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        let hex = key.hex();
+        self.dir.join(&hex[..2]).join(&hex[2..])
+    }
+
+    /// Reads a cached result. `Ok` contains optimized data, `Err` contains an error message.
+    /// The second value is `true` if the data should be compressed.
+    ///
+    /// Invalid or unreadable files are treated as missing.
+    #[must_use]
+    pub fn get(&self, key: &CacheKey) -> Option<(Result<Vec<u8>, String>, bool)> {
+        let p = self.path(key);
+        let mut v = fs::read(&p).ok()?;
+        let [m0, m1, m2, m3, flags, ..] = v[..] else {
+            return None;
+        };
+        if &[m0, m1, m2, m3] != MAGIC {
+            return None;
+        }
+        v.drain(..5);
+        if let Ok(f) = fs::File::options().write(true).open(&p) {
+            let _ = f.set_modified(SystemTime::now());
+        }
+        let r = if flags & FLAG_FAILED == 0 { Ok(v) } else { Err(String::from_utf8(v).ok()?) };
+        Some((r, flags & FLAG_COMPRESS != 0))
+    }
+
+    /// Stores a result of an optimized entry (see [`Cache::get`]).
+    /// The file is written atomically, so the cache can be shared by concurrent processes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn put(&self, key: &CacheKey, r: Result<&[u8], &str>, compress: bool) -> io::Result<()> {
+        static TMP_ID: AtomicU64 = AtomicU64::new(0);
+        let p = self.path(key);
+        if let Some(parent) = p.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let (flags, data) = match r {
+            Ok(data) => (0, data),
+            Err(e) => (FLAG_FAILED, e.as_bytes()),
+        };
+        let flags = flags | if compress { FLAG_COMPRESS } else { 0 };
+        let mut v = Vec::with_capacity(data.len() + 5);
+        v.extend_from_slice(MAGIC);
+        v.push(flags);
+        v.extend_from_slice(data);
+        let tmp = p.with_extension(format!("{}-{}.tmp", std::process::id(), TMP_ID.fetch_add(1, Ordering::Relaxed)));
+        fs::write(&tmp, v)?;
+        fs::rename(&tmp, &p).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+    }
+
+    fn files(&self) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let mut files = Vec::new();
+        for de in walkdir::WalkDir::new(&self.dir).min_depth(2).max_depth(2) {
+            let de = de?;
+            let md = de.metadata()?;
+            // Temporary files are being written by other processes
+            if md.is_file() && de.path().extension().is_none_or(|e| e != "tmp") {
+                files.push((de.into_path(), md.len(), md.modified()?));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Returns statistics of the cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read.
+    pub fn stats(&self) -> io::Result<CacheStats> {
+        Ok(self.files()?.iter().collect())
+    }
+
+    /// Removes files older than `max_age`, then removes the least recently used files
+    /// until the cache size is not larger than `max_size`. Returns statistics of removed files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache directory cannot be read or a file cannot be removed.
+    pub fn prune(&self, max_size: Option<u64>, max_age: Option<Duration>) -> io::Result<CacheStats> {
+        let mut files = self.files()?;
+        files.sort_unstable_by_key(|&(_, _, mtime)| std::cmp::Reverse(mtime));
+        let deadline = max_age.and_then(|age| SystemTime::now().checked_sub(age));
+        let (mut size, mut full) = (0u64, false);
+        let mut removed = Vec::new();
+        for f in &files {
+            full = full || max_size.is_some_and(|ms| size + f.1 > ms);
+            if full || deadline.is_some_and(|d| f.2 < d) {
+                fs::remove_file(&f.0)?;
+                removed.push(f);
+            } else {
+                size += f.1;
+            }
+        }
+        Ok(removed.into_iter().collect())
+    }
+}
+
+/// Statistics of cached files.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    /// A number of files
+    pub entries: usize,
+    /// A total size of files
+    pub bytes: u64,
+    /// The time when the least recently used file was used
+    pub oldest: Option<SystemTime>,
+}
+impl<'a> FromIterator<&'a (PathBuf, u64, SystemTime)> for CacheStats {
+    fn from_iter<T: IntoIterator<Item = &'a (PathBuf, u64, SystemTime)>>(iter: T) -> Self {
+        iter.into_iter().fold(Self::default(), |s, &(_, len, mtime)| Self {
+            entries: s.entries + 1,
+            bytes: s.bytes + len,
+            oldest: Some(s.oldest.map_or(mtime, |o| o.min(mtime))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cache in a temporary directory which is removed when dropped.
+    struct TempCache(Cache);
+    impl TempCache {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("mc-repack-cache-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            Self(Cache::open(dir).unwrap())
+        }
+    }
+    impl Drop for TempCache {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.dir());
+        }
+    }
+
+    fn key(data: &[u8]) -> CacheKey {
+        CacheKey::new(&Minifier::JSON, &ConfigMap::default(), data).unwrap()
+    }
+
+    fn set_age(cache: &Cache, key: &CacheKey, secs: u64) {
+        let f = fs::File::options().write(true).open(cache.path(key)).unwrap();
+        f.set_modified(SystemTime::now() - Duration::from_secs(secs)).unwrap();
+    }
+
+    #[test]
+    fn put_and_get() {
+        let tc = TempCache::new("get");
+        let cache = &tc.0;
+        let (k1, k2, k3) = (key(b"1"), key(b"2"), key(b"3"));
+        assert!(cache.get(&k1).is_none());
+        cache.put(&k1, Ok(b"data"), true).unwrap();
+        cache.put(&k2, Err("invalid"), false).unwrap();
+        cache.put(&k3, Ok(b""), false).unwrap();
+        assert_eq!(cache.get(&k1), Some((Ok(b"data".to_vec()), true)));
+        assert_eq!(cache.get(&k2), Some((Err("invalid".to_string()), false)));
+        assert_eq!(cache.get(&k3), Some((Ok(Vec::new()), false)));
+        // Results are replaced
+        cache.put(&k1, Ok(b"new"), false).unwrap();
+        assert_eq!(cache.get(&k1), Some((Ok(b"new".to_vec()), false)));
+        assert_eq!(cache.stats().unwrap().entries, 3);
+    }
+
+    #[test]
+    fn invalid_files() {
+        let tc = TempCache::new("invalid");
+        let cache = &tc.0;
+        let k = key(b"1");
+        cache.put(&k, Ok(b"data"), true).unwrap();
+        for data in [&b"MCRX\0data"[..], b"MCR", b"", b"MCRC\x02\xff"] {
+            fs::write(cache.path(&k), data).unwrap();
+            assert!(cache.get(&k).is_none(), "{data:?}");
+        }
+    }
+
+    #[test]
+    fn prune() {
+        let tc = TempCache::new("prune");
+        let cache = &tc.0;
+        let keys = [key(b"1"), key(b"2"), key(b"3"), key(b"4")];
+        for (i, k) in keys.iter().enumerate() {
+            cache.put(k, Ok(&[0; 95]), false).unwrap();
+            set_age(cache, k, 1000 * (i as u64 + 1));
+        }
+        // Reading a file makes it the most recently used one
+        assert!(cache.get(&keys[3]).is_some());
+        let removed = cache.prune(None, Some(Duration::from_secs(2500))).unwrap();
+        assert_eq!((removed.entries, removed.bytes), (1, 100));
+        assert!(cache.get(&keys[2]).is_none());
+        let removed = cache.prune(Some(250), None).unwrap();
+        assert_eq!(removed.entries, 1);
+        assert!(cache.get(&keys[1]).is_none());
+        assert!(cache.get(&keys[0]).is_some() && cache.get(&keys[3]).is_some());
+        assert_eq!(cache.stats().unwrap().bytes, 200);
+    }
+
+    #[test]
+    fn key_depends_on_verification() {
+        let cfgmap = ConfigMap::default();
+        let verified = cfgmap.child();
+        verified.set::<VerifyMinified>(true);
+        let k1 = CacheKey::new(&Minifier::JSON, &cfgmap, b"{}").unwrap();
+        let k2 = CacheKey::new(&Minifier::JSON, &verified, b"{}").unwrap();
+        assert_ne!(k1.0, k2.0);
+        assert_eq!(k2.0, CacheKey::new(&Minifier::JSON, &verified, b"{}").unwrap().0);
+        assert_ne!(k2.0, CacheKey::new(&Minifier::JSON, &verified, b"[]").unwrap().0);
+    }
+}
@@ -3,7 +3,9 @@ use std::sync::{Arc, RwLock};
 use state::TypeMap;
 
 /// A configuration map.
-pub struct ConfigMap(Arc<RwLock<TypeMap![Sync + Send]>>);
+///
+/// A child map (see [`ConfigMap::child`]) uses configs of its parent unless they are set in the child.
+pub struct ConfigMap(Arc<RwLock<TypeMap![Sync + Send]>>, Option<Arc<Self>>);
 impl ConfigMap {
     /// Fetches a configuration holder for a type that accepts stored config.
     /// # Panics
@@ -11,11 +13,14 @@ impl ConfigMap {
     #[must_use]
     pub fn fetch<AC: AcceptsConfig>(&self) -> ConfigHolder<AC> {
         let ch = self.0.read().unwrap().try_get::<ConfigHolder<AC>>().cloned();
-        ch.map_or_else(move || {
+        ch.unwrap_or_else(move || {
+            if let Some(parent) = &self.1 {
+                return parent.fetch::<AC>();
+            }
             let cfg = ConfigHolder(Arc::new(AC::Cfg::default()));
             self.0.write().unwrap().set::<ConfigHolder<AC>>(ConfigHolder::clone(&cfg));
             cfg
-        }, |cfg| cfg)
+        })
     }
 
     /// Sets a config for a type that accepts it. It should be used before any configurable operation.
@@ -24,15 +29,21 @@ impl ConfigMap {
     pub fn set<AC: AcceptsConfig>(&self, cfg: AC::Cfg) {
         self.0.write().unwrap().set::<ConfigHolder<AC>>(ConfigHolder(Arc::new(cfg)));
     }
+
+    /// Creates an empty map which uses configs of this map unless they are set in the new one.
+    #[must_use]
+    pub fn child(&self) -> Self {
+        Self(Arc::default(), Some(Arc::new(self.clone())))
+    }
 }
 impl Clone for ConfigMap {
     fn clone(&self) -> Self {
-        Self(self.0.clone())
+        Self(self.0.clone(), self.1.clone())
     }
 }
 impl Default for ConfigMap {
     fn default() -> Self {
-        Self(Arc::new(RwLock::new(<TypeMap![Sync + Send]>::new())))
+        Self(Arc::new(RwLock::new(<TypeMap![Sync + Send]>::new())), None)
     }
 }
 
@@ -71,6 +82,7 @@ pub(crate) use acfg;
 #[cfg(feature = "_any-zopfli")]
 /// Universal configuration for Zopfli.
 /// It determines if Zopfli will be enabled and how many iterations will be used.
+#[derive(Clone, Copy)]
 #[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize), serde(untagged))]
 pub enum CfgZopfli {
     /// A switch value (`true` or `false`).
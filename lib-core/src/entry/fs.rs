@@ -90,7 +90,9 @@ impl EntrySaver for FSEntrySaver {
         np.push(name);
         match entry {
             SavingEntry::Directory => fs::create_dir(np),
-            SavingEntry::File(buf, _) => fs::write(np, buf),
+            SavingEntry::File(buf, _) | SavingEntry::Compressed(buf, _) | SavingEntry::Checked(buf, _) => {
+                fs::write(np, buf)
+            }
         }?;
         Ok(())
     }
@@ -6,8 +6,9 @@ pub mod zip;
 use crate::{
     ProgressState, cfg,
     errors::ErrorCollector,
-    fop::{FileOp, TypeBlacklist},
+    fop::{FileOp, Rules},
 };
+use crate::min::{Minifier, VerifyMinified};
 use std::sync::Arc;
 
 use bytes::Bytes;
@@ -35,7 +36,7 @@ pub trait EntryReader {
         ReadEntryIter(self)
     }
 
-    /// Reads entries, checks if they are not blacklisted and sends them via `tx`.
+    /// Reads entries, checks if they are not ignored by `rules` and sends them via `tx`.
     ///
     /// # Errors
     ///
@@ -43,13 +44,13 @@ pub trait EntryReader {
     fn read_entries(
         mut self,
         mut tx: impl FnMut(NamedEntry) -> crate::Result_<()>,
-        blacklist: &TypeBlacklist,
+        rules: &Rules,
     ) -> crate::Result_<()>
     where
         Self: Sized,
     {
         for re in self.read_iter() {
-            let Some(ne) = read_entry::<Self>(re, blacklist)? else {
+            let Some(ne) = read_entry::<Self>(re, rules)? else {
                 continue;
             };
             tx(ne)?;
@@ -65,7 +66,7 @@ pub trait EntryReader {
 /// Returns an error if an entry is blacklisted or if an error occurs while reading an entry.
 pub fn read_entry<R: EntryReader>(
     re: R::RE<'_>,
-    blacklist: &TypeBlacklist,
+    rules: &Rules,
 ) -> crate::Result_<Option<NamedEntry>> {
     let (is_dir, name) = re.meta();
     let Some(is_dir) = is_dir else {
@@ -74,11 +75,14 @@ pub fn read_entry<R: EntryReader>(
     let et = if is_dir {
         NamedEntry::dir(name)
     } else {
-        let fop = FileOp::by_name(&name, blacklist);
-        if let FileOp::Ignore(_) = fop {
-            return Ok(None);
+        match rules.file_op(&name) {
+            FileOp::Ignore(_) => return Ok(None),
+            FileOp::Pass => match re.data_compressed()? {
+                (data, Some(ce)) => NamedEntry(name.into(), EntryType::Compressed(data, ce)),
+                (data, None) => NamedEntry::file(name, data, FileOp::Pass),
+            },
+            fop => NamedEntry::file(name, re.data()?, fop),
         }
-        NamedEntry::file(name, re.data()?, fop)
     };
     Ok(Some(et))
 }
@@ -107,6 +111,18 @@ pub trait ReadEntry {
     ///
     /// Returns an error if an error occurs while reading the entry data.
     fn data(self) -> crate::Result_<Bytes>;
+
+    /// Reads the entry data with its original compressed form (if it is available).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an error occurs while reading the entry data.
+    fn data_compressed(self) -> crate::Result_<(Bytes, Option<zip::CompressedEntry>)>
+    where
+        Self: Sized,
+    {
+        Ok((self.data()?, None))
+    }
 }
 
 /// Saves entries in a file-based system. Typically used with `EntryReader`.
@@ -118,8 +134,44 @@ pub trait EntrySaver {
     /// Returns an error if an error occurs while saving the entry.
     fn save(&mut self, name: &str, entry: SavingEntry) -> crate::Result_<()>;
 
+    /// Prepares an entry for saving (for example, compresses it). This method can be called from multiple threads,
+    /// then prepared entries are saved in order with [`EntrySaver::save_prepared`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an error occurs while preparing the entry.
+    fn prepare(&self, _name: &str, entry: SavingEntry) -> crate::Result_<PreparedEntry> {
+        Ok(match entry {
+            SavingEntry::Directory => PreparedEntry::Directory,
+            SavingEntry::File(data, compress_min) => PreparedEntry::File(data.to_vec(), compress_min),
+            SavingEntry::Compressed(data, _) | SavingEntry::Checked(data, _) => PreparedEntry::File(data.to_vec(), 24),
+        })
+    }
+
+    /// Saves an entry prepared by [`EntrySaver::prepare`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an error occurs while saving the entry.
+    fn save_prepared(&mut self, name: &str, entry: PreparedEntry) -> crate::Result_<()> {
+        match entry {
+            PreparedEntry::Directory => self.save(name, SavingEntry::Directory),
+            PreparedEntry::File(data, compress_min) => self.save(name, SavingEntry::File(&data, compress_min)),
+            PreparedEntry::Compressed(_) => anyhow::bail!("compressed entries are not supported by this saver"),
+        }
+    }
+
+    /// Writes entries buffered by the saver. It should be called after all entries are saved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an error occurs while saving the entries.
+    fn flush(&mut self) -> crate::Result_<()> {
+        Ok(())
+    }
+
     /// Receives entries from `rx`, optimizes, sends progress (via `ps`), and saves them.
-    /// Errors are collected with entry names.
+    /// Errors are collected with entry names. Entries are optimized in parallel (see [`process_entries`]).
     ///
     /// # Errors
     ///
@@ -129,55 +181,267 @@ pub trait EntrySaver {
         rx: impl IntoIterator<Item = NamedEntry>,
         ev: &mut ErrorCollector,
         cfgmap: &cfg::ConfigMap,
-        mut ps: impl FnMut(ProgressState) -> crate::Result_<()>,
+        rules: &Rules,
+        ps: impl FnMut(ProgressState) -> crate::Result_<()>,
     ) -> crate::Result_<()>
     where
-        Self: Sized,
+        Self: Sized + Sync,
     {
-        let mut cv = Vec::new();
-        for (n, ne) in rx.into_iter().enumerate() {
-            ps(ProgressState::Push(n, ne.0.clone()))?;
-            if let Some(se) = process_entry(&mut cv, &ne, ev, cfgmap) {
-                self.save(&ne.0, se)?;
+        process_entries(&mut self, rx, ev, cfgmap, rules, ps)
+    }
+}
+
+/// Optimizes entries from `rx` in parallel (using the current rayon thread pool) and saves them in their original order.
+///
+/// The output is the same as if entries were processed one by one.
+/// Entries are processed in batches to limit memory usage. Errors are collected in the same order as entries.
+///
+/// # Errors
+///
+/// Returns an error if an error occurs while saving the entries.
+pub fn process_entries<S: EntrySaver + Sync>(
+    saver: &mut S,
+    rx: impl IntoIterator<Item = NamedEntry>,
+    ev: &mut ErrorCollector,
+    cfgmap: &cfg::ConfigMap,
+    rules: &Rules,
+    ps: impl FnMut(ProgressState) -> crate::Result_<()>,
+) -> crate::Result_<()> {
+    process_entries_nested(saver, rx, ev, cfgmap, rules, ps, Batch { depth: 0, max_bytes: usize::MAX })
+}
+
+/// Works like [`process_entries`], but with a limit of data size in a batch.
+///
+/// A batch of entries is processed as soon as their data size reaches `max_bytes`. It limits memory usage when entries are large (an entry larger than `max_bytes` is processed alone).
+///
+/// # Errors
+///
+/// Returns an error if an error occurs while saving the entries.
+pub fn process_entries_bounded<S: EntrySaver + Sync>(
+    saver: &mut S,
+    rx: impl IntoIterator<Item = NamedEntry>,
+    ev: &mut ErrorCollector,
+    cfgmap: &cfg::ConfigMap,
+    rules: &Rules,
+    ps: impl FnMut(ProgressState) -> crate::Result_<()>,
+    max_bytes: usize,
+) -> crate::Result_<()> {
+    process_entries_nested(saver, rx, ev, cfgmap, rules, ps, Batch { depth: 0, max_bytes })
+}
+
+/// Limits of processed entries. Nested archives are processed with the same limits.
+#[derive(Clone, Copy)]
+pub(crate) struct Batch {
+    /// Depth of a nested archive
+    #[cfg_attr(not(feature = "jar"), allow(dead_code))]
+    pub depth: u8,
+    /// Maximum size of entries data in a batch
+    pub max_bytes: usize,
+}
+
+pub(crate) fn process_entries_nested<S: EntrySaver + Sync>(
+    saver: &mut S,
+    rx: impl IntoIterator<Item = NamedEntry>,
+    ev: &mut ErrorCollector,
+    cfgmap: &cfg::ConfigMap,
+    rules: &Rules,
+    mut ps: impl FnMut(ProgressState) -> crate::Result_<()>,
+    limits: Batch,
+) -> crate::Result_<()> {
+    use rayon::prelude::*;
+    let batch_len = rayon::current_num_threads() * 16;
+    let mut iter = rx.into_iter();
+    let mut batch = Vec::with_capacity(batch_len);
+    let mut n = 0;
+    loop {
+        let mut bytes = 0;
+        while batch.len() < batch_len && bytes < limits.max_bytes {
+            let Some(ne) = iter.next() else {
+                break;
+            };
+            bytes += ne.data_len();
+            batch.push(ne);
+        }
+        if batch.is_empty() {
+            break;
+        }
+        let base = ev.fork();
+        let sv = &*saver;
+        let prepared: Vec<_> = batch
+            .par_drain(..)
+            .map(|ne| {
+                let mut cv = Vec::new();
+                let mut ev = base.fork();
+                let r = process_entry_nested(&mut cv, &ne, &mut ev, cfgmap, rules, limits)
+                    .map(|se| sv.prepare(&ne.0, se))
+                    .transpose();
+                (ne.0, r, ev)
+            })
+            .collect();
+        for (name, r, errs) in prepared {
+            ps(ProgressState::Push(n, name.clone()))?;
+            n += 1;
+            ev.merge(errs);
+            if let Some(pe) = r? {
+                saver.save_prepared(&name, pe)?;
             }
         }
-        ps(ProgressState::Finish)
     }
+    saver.flush()?;
+    ps(ProgressState::Finish)
 }
 
 /// Saves an entry with the [`EntrySaver`].
+/// Nested archives are processed with the same `cfgmap` and `rules`.
+#[inline]
 pub fn process_entry<'a>(
+    cbuf: &'a mut Vec<u8>,
+    ne: &'a NamedEntry,
+    ev: &mut ErrorCollector,
+    cfgmap: &cfg::ConfigMap,
+    rules: &Rules,
+) -> Option<SavingEntry<'a>> {
+    process_entry_nested(cbuf, ne, ev, cfgmap, rules, Batch { depth: 0, max_bytes: usize::MAX })
+}
+
+#[cfg_attr(not(feature = "jar"), allow(unused_variables))]
+pub(crate) fn process_entry_nested<'a>(
     cbuf: &'a mut Vec<u8>,
     NamedEntry(name, et): &'a NamedEntry,
     ev: &mut ErrorCollector,
     cfgmap: &cfg::ConfigMap,
+    rules: &Rules,
+    limits: Batch,
 ) -> Option<SavingEntry<'a>> {
-    let se = match et {
-        EntryType::Directory => SavingEntry::Directory,
-        EntryType::File(buf, fop) => match fop {
-            FileOp::Ignore(e) => {
-                ev.collect(name.clone(), e.clone().into());
-                return None;
+    let (buf, fop, ce) = match et {
+        EntryType::Directory => return Some(SavingEntry::Directory),
+        EntryType::File(buf, fop) => (buf, fop, None),
+        EntryType::Compressed(buf, ce) => (buf, &FileOp::Pass, Some(ce)),
+    };
+    let cfgmap = rules.config(name).unwrap_or(cfgmap);
+    // Registered minifiers are checked before the built-in ones (but not before actions set by rules).
+    // Names are already checked by `Rules::file_op`, but sniffers need data.
+    let reg = rules.registry();
+    let ruled = rules.action(name).is_some();
+    let custom = match fop {
+        FileOp::Ignore(_) | FileOp::Minify(Minifier::Custom(_)) => None,
+        _ if reg.is_empty() || ruled => None,
+        _ => reg.find(name, buf).map(|m| FileOp::Minify(Minifier::Custom(m.clone()))),
+    };
+    // Files without an extension (or with a conflicting one) are checked by their content
+    let sniffed = match fop {
+        FileOp::Pass | FileOp::Minify(_) if custom.is_none() && !ruled => FileOp::by_content(name, buf),
+        _ => None,
+    };
+    let fop = match sniffed {
+        Some((ref op, ref mismatch)) => {
+            if let Some(m) = mismatch {
+                ev.collect(name.clone(), m.clone().into());
             }
-            FileOp::Minify(m) => {
-                let buf: &[u8] = match m.minify(cfgmap, buf, cbuf) {
-                    Ok(()) => cbuf,
-                    Err(e) => {
+            op
+        }
+        None => custom.as_ref().unwrap_or(fop),
+    };
+    let se = match fop {
+        FileOp::Ignore(e) => {
+            ev.collect(name.clone(), e.clone().into());
+            return None;
+        }
+        FileOp::Minify(m) => {
+            #[cfg(feature = "cache")]
+            if let Some(cache) = &*cfgmap.fetch::<crate::cache::EntryCache>() {
+                if let Some(key) = crate::cache::CacheKey::new(m, cfgmap, buf) {
+                    let (se, err) = minify_cached(cache, &key, cbuf, buf, m, cfgmap);
+                    if let Some(e) = err {
                         ev.collect(name.clone(), e);
-                        buf
                     }
-                };
-                SavingEntry::File(buf, m.compress_min())
+                    return Some(se);
+                }
             }
-            FileOp::Recompress(x) => SavingEntry::File(buf, u16::from(*x)),
-            FileOp::Pass => SavingEntry::File(buf, 24),
-        },
+            let r = match m {
+                #[cfg(feature = "jar")]
+                Minifier::JAR => crate::min::jar::repack_nested(cfgmap, buf, cbuf, ev, rules, name, limits),
+                _ => m.minify(cfgmap, buf, cbuf),
+            };
+            let mut warning = None;
+            let r = r.or_else(|e| take_warning(e, &mut warning));
+            if let Some(w) = warning {
+                ev.collect(name.clone(), w);
+            }
+            let buf: &[u8] = match r.and_then(|()| verify_minified(m, cfgmap, buf, cbuf)) {
+                Ok(()) => cbuf,
+                Err(e) => {
+                    ev.collect(name.clone(), e);
+                    buf
+                }
+            };
+            SavingEntry::File(buf, m.compress_min())
+        }
+        FileOp::Recompress(x) => SavingEntry::File(buf, u16::from(*x)),
+        FileOp::Pass => ce.map_or(SavingEntry::File(buf, 24), |ce| SavingEntry::Compressed(buf, ce)),
     };
     Some(se)
     //saver.save(&name, se)?;
     //Ok(())
 }
 
+/// Moves a warning (see [`MinifyWarning`](crate::errors::MinifyWarning)) into `warning`, so the minified data is used.
+/// Other errors are returned.
+fn take_warning(e: anyhow::Error, warning: &mut Option<anyhow::Error>) -> crate::Result_<()> {
+    match e.downcast::<crate::errors::MinifyWarning>() {
+        Ok(w) => {
+            *warning = Some(w.into());
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Checks minified data if verifying is enabled (see [`VerifyMinified`]).
+fn verify_minified(m: &Minifier, cfgmap: &cfg::ConfigMap, buf: &[u8], out: &[u8]) -> crate::Result_<()> {
+    if *cfgmap.fetch::<VerifyMinified>() {
+        m.verify(cfgmap, buf, out)?;
+    }
+    Ok(())
+}
+
+/// Minifies a file using a result stored in the cache. A new result is stored with a decision if the data should be compressed.
+/// An error is returned if minifying has failed (now or when the result was stored).
+#[cfg(feature = "cache")]
+fn minify_cached<'a>(
+    cache: &crate::cache::Cache,
+    key: &crate::cache::CacheKey,
+    cbuf: &'a mut Vec<u8>,
+    buf: &'a [u8],
+    m: &Minifier,
+    cfgmap: &cfg::ConfigMap,
+) -> (SavingEntry<'a>, Option<anyhow::Error>) {
+    if let Some((r, compress)) = cache.get(key) {
+        return match r {
+            Ok(v) => {
+                *cbuf = v;
+                (SavingEntry::Checked(cbuf, compress), None)
+            }
+            Err(e) => (SavingEntry::Checked(buf, compress), Some(anyhow::Error::msg(e))),
+        };
+    }
+    let mut warning = None;
+    let r = m.minify(cfgmap, buf, cbuf)
+        .or_else(|e| take_warning(e, &mut warning))
+        .and_then(|()| verify_minified(m, cfgmap, buf, cbuf));
+    let data = if r.is_ok() { &cbuf[..] } else { buf };
+    let compress = zip::compress_check(data, m.compress_min() as usize);
+    // The cache is optional, so failed writes are ignored.
+    // Failed verifications and results with warnings are not stored, so they are checked (and reported) again.
+    let _ = match &r {
+        Ok(()) if warning.is_some() => Ok(()),
+        Ok(()) => cache.put(key, Ok(data), compress),
+        Err(e) if e.is::<crate::errors::VerifyError>() => Ok(()),
+        Err(e) => cache.put(key, Err(&e.to_string()), compress),
+    };
+    (SavingEntry::Checked(data, compress), r.err().or(warning))
+}
+
 /// An entry with its name and type.
 pub struct NamedEntry(pub Arc<str>, pub EntryType);
 impl NamedEntry {
@@ -187,6 +451,16 @@ impl NamedEntry {
         Self(name.into(), EntryType::Directory)
     }
 
+    /// Returns the size of entry data (including its compressed form).
+    #[must_use]
+    pub const fn data_len(&self) -> usize {
+        match &self.1 {
+            EntryType::Directory => 0,
+            EntryType::File(data, _) => data.len(),
+            EntryType::Compressed(data, ce) => data.len() + ce.len(),
+        }
+    }
+
     /// A shorthand function for creating a file entry
     #[inline]
     pub fn file(name: impl Into<Arc<str>>, data: impl Into<Bytes>, fop: FileOp) -> Self {
@@ -201,6 +475,45 @@ pub enum EntryType {
     Directory,
     /// A file with its path, data and file operation
     File(Bytes, FileOp),
+    /// A file which is not changed, with its data and the original compressed form
+    Compressed(Bytes, zip::CompressedEntry),
+}
+
+/// An order of entries written by a saver.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EntryOrder {
+    /// Entries are written in the same order as they are saved.
+    Preserve,
+    /// The manifest (`META-INF/MANIFEST.MF`) is written first, other entries are written in the same order as they are saved.
+    /// Entries before the manifest are buffered only if the archive has it (see [`ZipEntrySaver::with_manifest`](crate::entry::ZipEntrySaver::with_manifest)).
+    #[default]
+    ManifestFirst,
+    /// The manifest is written first, other entries are sorted by name.
+    Sorted,
+    /// The manifest is written first, other entries are grouped by type (extension) and sorted by name.
+    Grouped,
+}
+impl std::str::FromStr for EntryOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "preserve" => Self::Preserve,
+            "manifest-first" => Self::ManifestFirst,
+            "sorted" => Self::Sorted,
+            "grouped" => Self::Grouped,
+            _ => return Err(format!("unknown entry order: {s}")),
+        })
+    }
+}
+
+/// An entry prepared for saving (owning its data).
+pub enum PreparedEntry {
+    /// A directory
+    Directory,
+    /// A file with data and a minimum compression constraint
+    File(Vec<u8>, u16),
+    /// A file compressed by [`ZipEntrySaver`]
+    Compressed(zip::CompressedEntry),
 }
 
 /// A type for saving entries.
@@ -209,4 +522,8 @@ pub enum SavingEntry<'a> {
     Directory,
     /// A file with data and a minimum compression constraint
     File(&'a [u8], u16),
+    /// A file with data and its original compressed form
+    Compressed(&'a [u8], &'a zip::CompressedEntry),
+    /// A file with data and a known decision if it should be compressed (see [`zip::compress_check`])
+    Checked(&'a [u8], bool),
 }
@@ -1,17 +1,24 @@
 use bytes::Bytes;
-use std::io::{BufReader, BufWriter, Cursor, Read, Seek, Write};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use zip::{
-    CompressionMethod, ZipArchive, ZipWriter,
+    CompressionMethod, DateTime, ZipArchive, ZipWriter,
     write::{FileOptions, SimpleFileOptions},
 };
 
-use super::{EntryReader, EntrySaver, ReadEntry, SavingEntry};
-use crate::Result_;
+use super::{EntryOrder, EntryReader, EntrySaver, PreparedEntry, ReadEntry, SavingEntry};
+use crate::{Result_, sign};
 
 /// An entry reader implementation for ZIP archive. It reads its contents from a provided reader (with seeking).
 pub struct ZipEntryReader<R: Read + Seek> {
     za: ZipArchive<R>,
     cur: usize,
+    strip_sign: bool,
+    keep_compressed: bool,
 }
 impl<R: Read + Seek> ZipEntryReader<R> {
     /// Creates an entry reader with a specified reader.
@@ -23,8 +30,48 @@ impl<R: Read + Seek> ZipEntryReader<R> {
         Ok(Self {
             za: ZipArchive::new(r)?,
             cur: 0,
+            strip_sign: false,
+            keep_compressed: false,
+        })
+    }
+
+    /// Checks if the archive contains a manifest (`META-INF/MANIFEST.MF`). See [`ZipEntrySaver::with_manifest`].
+    #[must_use]
+    pub fn has_manifest(&self) -> bool {
+        self.za.index_for_name(sign::MANIFEST).is_some()
+    }
+
+    /// Checks if the archive is signed and verifies its signatures.
+    /// Certificates of signers are not checked against any trust store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if signatures (or digests of signed entries) are invalid.
+    pub fn signature(&mut self) -> Result_<Option<sign::JarSignature>> {
+        let za = &mut self.za;
+        let names: Vec<Box<str>> = za.file_names().map(Box::from).collect();
+        let names: Vec<&str> = names.iter().map(|n| &**n).collect();
+        sign::find_signers(&names, |name| {
+            let Ok(mut zf) = za.by_name(name) else {
+                return Ok(None);
+            };
+            let mut v = Vec::with_capacity(zf.size() as usize);
+            zf.read_to_end(&mut v)?;
+            Ok(Some(v))
         })
     }
+
+    /// Removes digests of entries from the manifest while reading it.
+    /// Signature files are always ignored, so the repacked archive becomes unsigned.
+    pub const fn strip_signature(&mut self) {
+        self.strip_sign = true;
+    }
+
+    /// Keeps compressed data of entries which are not changed while repacking (see [`FileOp::Pass`](crate::fop::FileOp::Pass)),
+    /// so they can be copied without compressing them again (see [`RawCopy`]).
+    pub const fn keep_compressed(&mut self) {
+        self.keep_compressed = true;
+    }
 }
 impl<R: Read + Seek> ZipEntryReader<BufReader<R>> {
     /// Creates an entry reader wrapping a specified reader with a [`BufReader`].
@@ -61,7 +108,12 @@ impl<R: Read + Seek> EntryReader for ZipEntryReader<R> {
         } else {
             let idx = self.cur;
             self.cur += 1;
-            Some(ReadZipFileEntry { zip: za, idx })
+            Some(ReadZipFileEntry {
+                zip: za,
+                idx,
+                strip_sign: self.strip_sign,
+                keep_compressed: self.keep_compressed,
+            })
         }
     }
     #[inline]
@@ -74,6 +126,8 @@ impl<R: Read + Seek> EntryReader for ZipEntryReader<R> {
 pub struct ReadZipFileEntry<'a, RS: Read + Seek> {
     zip: &'a mut ZipArchive<RS>,
     idx: usize,
+    strip_sign: bool,
+    keep_compressed: bool,
 }
 impl<RS: Read + Seek> ReadEntry for ReadZipFileEntry<'_, RS> {
     fn meta(&self) -> (Option<bool>, Box<str>) {
@@ -86,8 +140,28 @@ impl<RS: Read + Seek> ReadEntry for ReadZipFileEntry<'_, RS> {
         let mut jf = self.zip.by_index(self.idx)?;
         obuf.reserve_exact(jf.size() as usize);
         jf.read_to_end(&mut obuf)?;
+        if self.strip_sign && jf.name() == sign::MANIFEST {
+            obuf = sign::strip_manifest(&obuf);
+        }
         Ok(obuf.into())
     }
+    fn data_compressed(self) -> crate::Result_<(Bytes, Option<CompressedEntry>)> {
+        if !self.keep_compressed {
+            return Ok((self.data()?, None));
+        }
+        let zf = self.zip.by_index_raw(self.idx)?;
+        let ce = if matches!(zf.compression(), CompressionMethod::Stored | CompressionMethod::Deflated)
+            && !(self.strip_sign && zf.name() == sign::MANIFEST)
+        {
+            let mut z = ZipWriter::new(Cursor::new(Vec::with_capacity(zf.compressed_size() as usize + 2 * zf.name().len() + 128)));
+            z.raw_copy_file(zf)?;
+            Some(CompressedEntry(z.finish()?.into_inner().into()))
+        } else {
+            drop(zf);
+            None
+        };
+        Ok((self.data()?, ce))
+    }
 }
 
 #[cfg(feature = "zip-zopfli")]
@@ -96,12 +170,140 @@ const MAX_LEVEL: i64 = 24;
 #[cfg(not(feature = "zip-zopfli"))]
 const MAX_LEVEL: i64 = 9;
 
+/// A compression level of trial compression (see [`compress_check`]).
+const TRIAL_LEVEL: i64 = 9;
+
+/// A compressed file (by [`ZipEntrySaver`] or from a source archive). It is kept as a single-entry ZIP archive,
+/// so it can be copied into the output archive without compressing it again.
+#[derive(Clone)]
+pub struct CompressedEntry(Bytes);
+impl CompressedEntry {
+    /// Returns the size of compressed data (with ZIP headers).
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Checks if there is no compressed data.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the size of compressed data (without ZIP headers).
+    fn data_len(&self) -> Result_<u64> {
+        let mut za = ZipArchive::new(Cursor::new(&self.0))?;
+        let zf = za.by_index_raw(0)?;
+        Ok(zf.compressed_size())
+    }
+
+    /// Creates an entry from `data` and its compressed form (with Deflate).
+    /// CRC is computed here, so the data is not read again. Returns `None` if the data is too large (ZIP64 is needed).
+    fn deflated(name: &str, data: &[u8], deflated: &[u8], mtime: DateTime) -> Option<Self> {
+        let size = u32::try_from(data.len()).ok()?;
+        let csize = u32::try_from(deflated.len()).ok()?;
+        let name_len = u16::try_from(name.len()).ok()?;
+        let crc = crc32fast::hash(data);
+        let flags: u16 = if name.is_ascii() { 0 } else { 1 << 11 };
+        // Fields shared by local and central headers: version needed, flags, method, time, date, CRC, sizes, name length
+        let mut common = Vec::with_capacity(24);
+        for x in [20, flags, 8, mtime.timepart(), mtime.datepart()] {
+            common.extend_from_slice(&x.to_le_bytes());
+        }
+        for x in [crc, csize, size] {
+            common.extend_from_slice(&x.to_le_bytes());
+        }
+        common.extend_from_slice(&name_len.to_le_bytes());
+
+        let mut v = Vec::with_capacity(deflated.len() + 2 * name.len() + 128);
+        v.extend_from_slice(&0x0403_4b50_u32.to_le_bytes());
+        v.extend_from_slice(&common);
+        v.extend_from_slice(&0u16.to_le_bytes());
+        v.extend_from_slice(name.as_bytes());
+        v.extend_from_slice(deflated);
+        let cd_offset = u32::try_from(v.len()).ok()?;
+        v.extend_from_slice(&0x0201_4b50_u32.to_le_bytes());
+        // Version made by (Unix)
+        v.extend_from_slice(&0x0314_u16.to_le_bytes());
+        v.extend_from_slice(&common);
+        // Extra field, comment, disk number and internal attributes
+        v.extend_from_slice(&[0; 8]);
+        v.extend_from_slice(&(0o100_644_u32 << 16).to_le_bytes());
+        v.extend_from_slice(&0u32.to_le_bytes());
+        v.extend_from_slice(name.as_bytes());
+        let cd_size = u32::try_from(v.len()).ok()? - cd_offset;
+        v.extend_from_slice(&0x0605_4b50_u32.to_le_bytes());
+        for x in [0u16, 0, 1, 1] {
+            v.extend_from_slice(&x.to_le_bytes());
+        }
+        for x in [cd_size, cd_offset] {
+            v.extend_from_slice(&x.to_le_bytes());
+        }
+        v.extend_from_slice(&0u16.to_le_bytes());
+        Some(Self(v.into()))
+    }
+
+    /// Copies the entry with a new modification time.
+    fn touch(&self, mtime: DateTime) -> Result_<Self> {
+        let mut za = ZipArchive::new(Cursor::new(&self.0))?;
+        let zf = za.by_index_raw(0)?;
+        let mut z = ZipWriter::new(Cursor::new(Vec::with_capacity(self.0.len())));
+        z.raw_copy_file_touch(zf, mtime, Some(0o644))?;
+        Ok(Self(z.finish()?.into_inner().into()))
+    }
+}
+
+/// A policy for files which are not changed and were already compressed in the source archive
+/// (see [`ZipEntryReader::keep_compressed`]).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RawCopy {
+    /// Files are always compressed again.
+    Never,
+    /// Files are compressed again, but the original compressed data is copied if it is not bigger.
+    #[default]
+    Smaller,
+    /// The original compressed data is always copied (faster, but the archive may be bigger).
+    Always,
+}
+
+/// A modification time of entries written to an archive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EntryMtime {
+    /// The current time is used.
+    #[default]
+    Now,
+    /// The same time is used for all entries (see [`ZipEntrySaver::with_mtime`]).
+    Fixed(Option<u64>),
+}
+impl EntryMtime {
+    pub(crate) fn dos_time(self) -> DateTime {
+        match self {
+            Self::Now => DateTime::default_for_write(),
+            Self::Fixed(epoch) => epoch.map_or_else(DateTime::default, dos_time),
+        }
+    }
+}
+
 /// An entry saver implementation for ZIP archive. It writes entries to it using a provided writer.
+///
+/// Entries are buffered if they need to be reordered (see [`EntryOrder`] and [`Self::with_manifest`]).
+/// Buffered data above a limit (see [`Self::with_buffer_limit`]) is moved to a temporary file.
+/// Files are compressed in [`EntrySaver::prepare`], which can be called from multiple threads.
 pub struct ZipEntrySaver<W: Write + Seek> {
     w: ZipWriter<BufWriter<W>>,
     keep_dirs: bool,
     opts_deflated: SimpleFileOptions,
     opts_stored: SimpleFileOptions,
+    order: EntryOrder,
+    buf: Vec<(Box<str>, Buffered)>,
+    buf_size: usize,
+    buf_limit: usize,
+    spill: Option<SpillFile>,
+    streaming: bool,
+    manifest: bool,
+    mtime: DateTime,
+    raw_copy: RawCopy,
+    reuse_trial: bool,
 }
 impl<W: Write + Seek> ZipEntrySaver<W> {
     /// Creates an entry saver with a seekable writer.
@@ -113,6 +315,16 @@ impl<W: Write + Seek> ZipEntrySaver<W> {
                 .compression_method(CompressionMethod::Deflated)
                 .compression_level(Some(MAX_LEVEL)),
             opts_stored: FileOptions::default().compression_method(CompressionMethod::Stored),
+            order: EntryOrder::default(),
+            buf: Vec::new(),
+            buf_size: 0,
+            buf_limit: usize::MAX,
+            spill: None,
+            streaming: false,
+            manifest: false,
+            mtime: DateTime::default_for_write(),
+            raw_copy: RawCopy::default(),
+            reuse_trial: MAX_LEVEL == TRIAL_LEVEL,
         }
     }
     /// Creates an entry saver with custom file options for ZIP archive and a seekable writer.
@@ -127,59 +339,341 @@ impl<W: Write + Seek> ZipEntrySaver<W> {
             keep_dirs,
             opts_deflated,
             opts_stored,
+            order: EntryOrder::default(),
+            buf: Vec::new(),
+            buf_size: 0,
+            buf_limit: usize::MAX,
+            spill: None,
+            streaming: false,
+            manifest: false,
+            mtime: DateTime::default_for_write(),
+            raw_copy: RawCopy::default(),
+            reuse_trial: false,
         }
     }
     /// Creates an entry saver with custom compression level for deflated entries of ZIP archive and a seekable writer.
     pub fn custom_compress(w: W, keep_dirs: bool, compress: impl Into<i64>) -> Self {
+        let compress = compress.into();
         Self {
             w: ZipWriter::new(BufWriter::new(w)),
             keep_dirs,
             opts_deflated: FileOptions::default()
                 .compression_method(CompressionMethod::Deflated)
-                .compression_level(Some(compress.into())),
+                .compression_level(Some(compress)),
             opts_stored: FileOptions::default().compression_method(CompressionMethod::Stored),
+            order: EntryOrder::default(),
+            buf: Vec::new(),
+            buf_size: 0,
+            buf_limit: usize::MAX,
+            spill: None,
+            streaming: false,
+            manifest: false,
+            mtime: DateTime::default_for_write(),
+            raw_copy: RawCopy::default(),
+            reuse_trial: compress == TRIAL_LEVEL,
         }
     }
-}
-impl<W: Write + Seek> EntrySaver for ZipEntrySaver<W> {
-    fn save(&mut self, name: &str, entry: SavingEntry) -> crate::Result_<()> {
-        let z = &mut self.w;
+    /// Sets an order of written entries.
+    #[must_use]
+    pub const fn with_order(mut self, order: EntryOrder) -> Self {
+        self.order = order;
+        self
+    }
+    /// Tells the saver that the manifest will be saved. Entries are buffered for [`EntryOrder::ManifestFirst`]
+    /// only until the manifest arrives, so without a manifest they are written in the same order as they are saved.
+    #[must_use]
+    pub const fn with_manifest(mut self, present: bool) -> Self {
+        self.manifest = present;
+        self
+    }
+    /// Sets a limit of entry data buffered in memory while entries are reordered (it is not limited by default).
+    /// Entries which do not fit are compressed and moved to a temporary file until they are written.
+    #[must_use]
+    pub const fn with_buffer_limit(mut self, bytes: usize) -> Self {
+        self.buf_limit = bytes;
+        self
+    }
+    /// Sets the same modification time for all entries.
+    /// The time is provided as seconds since Unix epoch (UTC). If it is `None` (or out of range), 1980-01-01 is used.
+    #[must_use]
+    pub fn with_mtime(mut self, epoch: Option<u64>) -> Self {
+        let mtime = EntryMtime::Fixed(epoch).dos_time();
+        self.mtime = mtime;
+        self.opts_deflated = self.opts_deflated.last_modified_time(mtime);
+        self.opts_stored = self.opts_stored.last_modified_time(mtime);
+        self
+    }
+    /// Sets a policy for files already compressed in the source archive.
+    #[must_use]
+    pub const fn with_raw_copy(mut self, raw_copy: RawCopy) -> Self {
+        self.raw_copy = raw_copy;
+        self
+    }
+    /// Makes the output reproducible: all entries have the same modification time (see [`Self::with_mtime`])
+    /// and they are sorted by name. No extra fields are written and the compression settings stay the same,
+    /// so the same input always results in byte-identical archives.
+    #[must_use]
+    pub fn reproducible(self, epoch: Option<u64>) -> Self {
+        self.with_mtime(epoch).with_order(EntryOrder::Sorted)
+    }
+    /// Writes buffered entries, finishes writing the archive and returns the inner writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the buffered entries or the central directory cannot be written.
+    pub fn finish(mut self) -> Result_<W> {
+        self.flush()?;
+        Ok(self.w.finish()?.into_inner().map_err(std::io::IntoInnerError::into_error)?)
+    }
+
+    /// Keeps an entry in memory if it fits in the buffer limit. Otherwise, it is compressed and moved to the spill file.
+    fn buffer(&mut self, name: &str, entry: PreparedEntry) -> Result_<Buffered> {
+        let size = match &entry {
+            PreparedEntry::Directory => 0,
+            PreparedEntry::File(v, _) => v.len(),
+            PreparedEntry::Compressed(c) => c.len(),
+        };
+        if self.buf_size.saturating_add(size) <= self.buf_limit {
+            self.buf_size += size;
+            return Ok(Buffered::Entry(entry));
+        }
+        let ce = match entry {
+            PreparedEntry::Directory => return Ok(Buffered::Entry(entry)),
+            PreparedEntry::File(data, compress_min) => match self.prepare(name, SavingEntry::File(&data, compress_min))? {
+                PreparedEntry::Compressed(ce) => ce,
+                e => return self.buffer(name, e),
+            },
+            PreparedEntry::Compressed(ce) => ce,
+        };
+        let sf = match &mut self.spill {
+            Some(sf) => sf,
+            None => self.spill.insert(SpillFile::create()?),
+        };
+        Ok(Buffered::Spilled(sf.push(&ce.0)?, ce.len()))
+    }
+
+    fn write(&mut self, name: &str, entry: PreparedEntry) -> Result_<()> {
         match entry {
-            SavingEntry::Directory => {
+            PreparedEntry::Directory => {
                 if self.keep_dirs && name != ".cache/" {
-                    z.add_directory(name, self.opts_stored)?;
+                    self.w.add_directory(name, self.opts_stored)?;
                 }
             }
-            SavingEntry::File(data, compress_min) => {
-                z.start_file(
-                    name,
-                    if compress_check(data, compress_min as usize) {
-                        self.opts_deflated
-                    } else {
-                        self.opts_stored
-                    },
-                )?;
-                z.write_all(data)?;
+            PreparedEntry::File(data, compress_min) => {
+                let entry = self.prepare(name, SavingEntry::File(&data, compress_min))?;
+                self.write(name, entry)?;
+            }
+            PreparedEntry::Compressed(CompressedEntry(v)) => {
+                let mut za = ZipArchive::new(Cursor::new(&v))?;
+                self.w.raw_copy_file_rename(za.by_index_raw(0)?, name)?;
             }
         }
         Ok(())
     }
 }
+impl<W: Write + Seek> EntrySaver for ZipEntrySaver<W> {
+    fn save(&mut self, name: &str, entry: SavingEntry) -> crate::Result_<()> {
+        let entry = self.prepare(name, entry)?;
+        self.save_prepared(name, entry)
+    }
+
+    fn prepare(&self, name: &str, entry: SavingEntry) -> crate::Result_<PreparedEntry> {
+        let (data, trial) = match entry {
+            SavingEntry::Directory => return Ok(PreparedEntry::Directory),
+            SavingEntry::File(data, compress_min) => (data, compress_trial(data, compress_min as usize, Vec::new())),
+            SavingEntry::Checked(data, compress) => (data, if compress { Trial::Deflate } else { Trial::Stored }),
+            SavingEntry::Compressed(data, ce) => match self.raw_copy {
+                RawCopy::Never => (data, compress_trial(data, 24, Vec::new())),
+                RawCopy::Smaller => {
+                    let raw_len = ce.data_len()?;
+                    let raw = ce.touch(self.mtime)?;
+                    // Only compressed data is compared (headers may have different extra fields)
+                    return Ok(match self.prepare(name, SavingEntry::File(data, 24))? {
+                        PreparedEntry::Compressed(c) if c.data_len()? < raw_len => PreparedEntry::Compressed(c),
+                        _ => PreparedEntry::Compressed(raw),
+                    });
+                }
+                RawCopy::Always => return Ok(PreparedEntry::Compressed(ce.touch(self.mtime)?)),
+            },
+        };
+        let opts = match trial {
+            Trial::Stored => self.opts_stored,
+            Trial::Deflated(v) if self.reuse_trial => {
+                // The trial uses the same level, so its output is written as is
+                if let Some(ce) = CompressedEntry::deflated(name, data, &v, self.mtime) {
+                    return Ok(PreparedEntry::Compressed(ce));
+                }
+                self.opts_deflated
+            }
+            Trial::Deflate | Trial::Deflated(_) => self.opts_deflated,
+        };
+        let mut z = ZipWriter::new(Cursor::new(Vec::with_capacity(data.len() + 2 * name.len() + 128)));
+        z.start_file(name, opts)?;
+        z.write_all(data)?;
+        Ok(PreparedEntry::Compressed(CompressedEntry(z.finish()?.into_inner().into())))
+    }
+
+    fn save_prepared(&mut self, name: &str, entry: PreparedEntry) -> crate::Result_<()> {
+        let no_manifest = self.order == EntryOrder::ManifestFirst && !self.manifest;
+        if self.order == EntryOrder::Preserve || self.streaming || no_manifest {
+            return self.write(name, entry);
+        }
+        let entry = self.buffer(name, entry)?;
+        self.buf.push((name.into(), entry));
+        if self.order == EntryOrder::ManifestFirst && name == sign::MANIFEST {
+            // Entries after the manifest can be written directly
+            self.flush()?;
+            self.streaming = true;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> crate::Result_<()> {
+        let mut buf = std::mem::take(&mut self.buf);
+        self.buf_size = 0;
+        match self.order {
+            EntryOrder::Preserve => {}
+            EntryOrder::ManifestFirst => buf.sort_by_key(|(n, _)| manifest_rank(n)),
+            EntryOrder::Sorted => buf.sort_by(|(n1, _), (n2, _)| {
+                manifest_rank(n1).cmp(&manifest_rank(n2)).then_with(|| n1.cmp(n2))
+            }),
+            EntryOrder::Grouped => buf.sort_by(|(n1, d1), (n2, d2)| {
+                let is_file = |d: &Buffered| !matches!(d, Buffered::Entry(PreparedEntry::Directory));
+                manifest_rank(n1)
+                    .cmp(&manifest_rank(n2))
+                    .then_with(|| is_file(d1).cmp(&is_file(d2)))
+                    .then_with(|| entry_type(n1).cmp(entry_type(n2)))
+                    .then_with(|| n1.cmp(n2))
+            }),
+        }
+        let mut spill = self.spill.take();
+        for (name, entry) in buf {
+            let entry = match (entry, &mut spill) {
+                (Buffered::Entry(e), _) => e,
+                (Buffered::Spilled(offset, len), Some(sf)) => PreparedEntry::Compressed(CompressedEntry(sf.read(offset, len)?.into())),
+                (Buffered::Spilled(..), None) => unreachable!("spilled entry without a file"),
+            };
+            self.write(&name, entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// An entry waiting to be written by [`ZipEntrySaver`].
+enum Buffered {
+    Entry(PreparedEntry),
+    /// Compressed data moved to the spill file (an offset and a length)
+    Spilled(u64, usize),
+}
+
+/// A temporary file for compressed entries which do not fit in the buffer. It is removed when dropped.
+struct SpillFile {
+    path: PathBuf,
+    f: File,
+    len: u64,
+}
+impl SpillFile {
+    fn create() -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir();
+        loop {
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("mc-repack-{}-{n}.tmp", std::process::id()));
+            match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+                Ok(f) => return Ok(Self { path, f, len: 0 }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Appends data and returns its offset.
+    fn push(&mut self, b: &[u8]) -> io::Result<u64> {
+        let offset = self.len;
+        self.f.seek(SeekFrom::Start(offset))?;
+        self.f.write_all(b)?;
+        self.len += b.len() as u64;
+        Ok(offset)
+    }
+
+    fn read(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut v = vec![0; len];
+        self.f.seek(SeekFrom::Start(offset))?;
+        self.f.read_exact(&mut v)?;
+        Ok(v)
+    }
+}
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Converts seconds since Unix epoch to a DOS date and time.
+fn dos_time(epoch: u64) -> DateTime {
+    let (days, secs) = (epoch / 86400, epoch % 86400);
+    // Civil date from days (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let (era, doe) = (z / 146_097, z % 146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    let (Ok(year), Ok(month), Ok(day)) = (u16::try_from(year), u8::try_from(month), u8::try_from(day)) else {
+        return DateTime::default();
+    };
+    DateTime::from_date_and_time(year, month, day, (secs / 3600) as u8, (secs % 3600 / 60) as u8, (secs % 60) as u8)
+        .unwrap_or_default()
+}
+
+/// Returns a rank which puts the manifest (and its directory) before other entries.
+fn manifest_rank(name: &str) -> u8 {
+    match name {
+        "META-INF/" => 0,
+        sign::MANIFEST => 1,
+        _ => 2,
+    }
+}
+
+/// Returns a type of entry based on its extension.
+fn entry_type(name: &str) -> &str {
+    let fname = name.rsplit_once('/').map_or(name, |(_, f)| f);
+    fname.rsplit_once('.').map_or("", |(_, ext)| ext)
+}
 
 /// Check if data should be compressed. If the compressed size is smaller than original, then the compression should be chosen.
 pub fn compress_check(b: &[u8], compress_min: usize) -> bool {
+    !matches!(compress_trial(b, compress_min, std::io::sink()), Trial::Stored)
+}
+
+/// A result of trial compression.
+enum Trial<W> {
+    /// Data should be stored
+    Stored,
+    /// Data should be compressed (it is not compressed yet)
+    Deflate,
+    /// Data is compressed (with the best level) into a writer
+    Deflated(W),
+}
+
+/// Decides if data should be compressed (see [`compress_check`]). Compressed data is written into `w`, so it can be reused.
+fn compress_trial<W: Write>(b: &[u8], compress_min: usize, w: W) -> Trial<W> {
     let lb = b.len();
     if lb > compress_min {
         if calc_entropy(b) < 7.0 {
-            return true;
+            return Trial::Deflate;
         }
-        let mut d =
-            flate2::write::DeflateEncoder::new(std::io::sink(), flate2::Compression::best());
+        let mut d = flate2::write::DeflateEncoder::new(w, flate2::Compression::best());
         if d.write_all(b).and_then(|_| d.try_finish()).is_ok() && d.total_out() as usize + 8 < lb {
-            return true;
+            if let Ok(w) = d.finish() {
+                return Trial::Deflated(w);
+            }
+            return Trial::Deflate;
         }
     }
-    false
+    Trial::Stored
 }
 
 fn calc_entropy(b: &[u8]) -> f64 {
@@ -206,3 +700,89 @@ fn calc_entropy(b: &[u8]) -> f64 {
     assert!((0.0..=8.0).contains(&e), "Invalid entropy: {e}");
     e
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text which is compressed better with higher levels.
+    fn text() -> Vec<u8> {
+        let words = ["block", "item", "minecraft", "stone", "texture", "model", "recipe", "tag", "loot", "table"];
+        let mut x = 7u32;
+        let mut v = Vec::new();
+        for _ in 0..20000 {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            v.extend_from_slice(words[(x >> 16) as usize % words.len()].as_bytes());
+            v.push(if x & 0x100 == 0 { b' ' } else { b'\n' });
+        }
+        v
+    }
+
+    fn source_archive(data: &[u8], levels: &[i64]) -> Vec<u8> {
+        let mut z = ZipWriter::new(Cursor::new(Vec::new()));
+        for &l in levels {
+            let opts = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated).compression_level(Some(l));
+            z.start_file(format!("level{l}.txt"), opts).unwrap();
+            z.write_all(data).unwrap();
+        }
+        z.finish().unwrap().into_inner()
+    }
+
+    /// Returns compressed data of an entry (without ZIP headers).
+    fn raw_data(ce: &CompressedEntry) -> Vec<u8> {
+        let mut za = ZipArchive::new(Cursor::new(&ce.0)).unwrap();
+        let mut v = Vec::new();
+        za.by_index_raw(0).unwrap().read_to_end(&mut v).unwrap();
+        v
+    }
+
+    #[test]
+    fn raw_copy_smaller() {
+        let data = text();
+        let mut reader = ZipEntryReader::new_mem(source_archive(&data, &[1, 9])).unwrap();
+        reader.keep_compressed();
+        let saver = ZipEntrySaver::custom_compress(Cursor::new(Vec::new()), false, 9);
+        let mut outs = Vec::new();
+        while let Some(re) = reader.read_next() {
+            let (_, name) = re.meta();
+            let (b, ce) = re.data_compressed().unwrap();
+            let ce = ce.unwrap();
+            let PreparedEntry::Compressed(out) = saver.prepare(&name, SavingEntry::Compressed(&b, &ce)).unwrap() else {
+                panic!("{name} is not compressed");
+            };
+            outs.push((raw_data(&ce), raw_data(&out)));
+            let mut za = ZipArchive::new(Cursor::new(&out.0)).unwrap();
+            let mut v = Vec::new();
+            za.by_index(0).unwrap().read_to_end(&mut v).unwrap();
+            assert_eq!(v, data);
+        }
+        // A stream deflated with level 1 is compressed again, a level 9 stream is copied
+        assert!(outs[0].1.len() < outs[0].0.len());
+        assert_eq!(outs[1].1, outs[1].0);
+    }
+
+    #[test]
+    fn spilled_entries() {
+        let data = text();
+        let mut saver = ZipEntrySaver::custom_compress(Cursor::new(Vec::new()), true, 9)
+            .with_order(EntryOrder::Sorted)
+            .with_buffer_limit(1);
+        for name in ["d.txt", "c/", "b.txt", "a.txt"] {
+            let entry = if name.ends_with('/') { SavingEntry::Directory } else { SavingEntry::File(&data, 24) };
+            saver.save(name, entry).unwrap();
+        }
+        // Only the directory fits in the buffer
+        assert_eq!(saver.buf.iter().filter(|(_, e)| matches!(e, Buffered::Spilled(..))).count(), 3);
+        let path = saver.spill.as_ref().map(|sf| sf.path.clone()).unwrap();
+        let mut za = ZipArchive::new(Cursor::new(saver.finish().unwrap().into_inner())).unwrap();
+        assert!(!path.exists());
+        assert_eq!(za.len(), 4);
+        for (i, name) in ["a.txt", "b.txt", "c/", "d.txt"].into_iter().enumerate() {
+            let mut zf = za.by_index(i).unwrap();
+            assert_eq!(zf.name(), name);
+            let mut v = Vec::new();
+            zf.read_to_end(&mut v).unwrap();
+            assert_eq!(v, if zf.is_dir() { &[][..] } else { &data[..] });
+        }
+    }
+}
@@ -1,16 +1,19 @@
 use std::{error::Error, fmt::Display, sync::Arc};
 
+use crate::ext::KnownFmt;
+
 pub(crate) type Error_ = anyhow::Error;
 
 /// A struct for collecting errors.
 pub struct ErrorCollector {
     vec: Option<Vec<EntryRepackError>>,
     name: Arc<str>,
+    prefix: String,
 }
 impl ErrorCollector {
     /// Creates a new `ErrorCollector` with a `silent` option.
     #[must_use]
-    pub fn new(silent: bool) -> Self { Self { vec: (!silent).then(Vec::new), name: "".into() } }
+    pub fn new(silent: bool) -> Self { Self { vec: (!silent).then(Vec::new), name: "".into(), prefix: String::new() } }
 
     /// Sets the new prefix name for collected entries. 
     pub fn rename(&mut self, name: &str)  {
@@ -20,14 +23,39 @@ impl ErrorCollector {
     /// Collects errors for files based on their name (path).
     pub fn collect(&mut self, name: impl Into<Arc<str>>, e: Error_) {
         if let Some(vec) = self.vec.as_mut() {
+            let name = name.into();
             vec.push(EntryRepackError {
                 parent: self.name.clone(),
-                name: name.into(),
+                name: if self.prefix.is_empty() { name } else { format!("{}{name}", self.prefix).into() },
                 inner: e
             });
         }
     }
 
+    /// Collects errors of entries inside a nested archive. Their names are prefixed with `name!/`.
+    pub fn nested<T>(&mut self, name: &str, f: impl FnOnce(&mut Self) -> T) -> T {
+        let len = self.prefix.len();
+        self.prefix.push_str(name);
+        self.prefix.push_str("!/");
+        let r = f(self);
+        self.prefix.truncate(len);
+        r
+    }
+
+    /// Creates an empty collector with the same settings (silent mode, name and prefix).
+    /// It can be used in another thread and then merged with [`ErrorCollector::merge`].
+    #[must_use]
+    pub fn fork(&self) -> Self {
+        Self { vec: self.vec.as_ref().map(|_| Vec::new()), name: self.name.clone(), prefix: self.prefix.clone() }
+    }
+
+    /// Appends errors gathered by another (forked) collector.
+    pub fn merge(&mut self, other: Self) {
+        if let (Some(vec), Some(ov)) = (self.vec.as_mut(), other.vec) {
+            vec.extend(ov);
+        }
+    }
+
     /// Returns all currently gathered results.
     #[must_use]
     pub fn results(&self) -> &[EntryRepackError] {
@@ -79,4 +107,62 @@ impl Display for FileIgnoreError {
             Self::Signfile => "signfile contains SHA-256 hashes of zipped entries",
         })
     }
-}
\ No newline at end of file
+}
+
+/// An error indicating that file content does not match its extension.
+/// The file is processed according to its content (or passed if the content is unknown).
+#[derive(Debug, Clone)]
+pub struct FormatMismatch {
+    /// A file extension
+    pub ext: Box<str>,
+    /// A format detected from file content
+    pub content: Option<KnownFmt>
+}
+
+impl Error for FormatMismatch {}
+impl Display for FormatMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.content {
+            Some(c) => write!(f, "content ({c}) does not match extension .{}", self.ext),
+            None => write!(f, "unknown content does not match extension .{}", self.ext),
+        }
+    }
+}
+
+/// An error indicating that minified data is not equivalent to the original data (see [`crate::min::VerifyMinified`]).
+/// The original data is saved instead.
+#[derive(Debug, Clone)]
+pub enum VerifyError {
+    /// Original data cannot be decoded, so it cannot be compared
+    Original(KnownFmt, Box<str>),
+    /// Minified data cannot be decoded
+    Minified(KnownFmt, Box<str>),
+    /// Decoded data differs from the original
+    Mismatch(KnownFmt)
+}
+
+impl Error for VerifyError {}
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Original(fmt, e) => write!(f, "verification failed: original {fmt} cannot be decoded: {e}"),
+            Self::Minified(fmt, e) => write!(f, "verification failed: minified {fmt} cannot be decoded: {e}"),
+            Self::Mismatch(fmt) => write!(f, "verification failed: minified {fmt} differs from the original"),
+        }
+    }
+}
+
+/// A warning returned by a minifier which finished its work. The minified data is saved and the warning is collected.
+#[derive(Debug)]
+pub struct MinifyWarning(pub Error_);
+
+impl Error for MinifyWarning {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+impl Display for MinifyWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "warning: {}", self.0)
+    }
+}
@@ -1,6 +1,6 @@
 
 /// An enum containing all "known" file formats that can be processed by this library
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KnownFmt {
     /// JavaScript Object Notation, also known as JSON
     Json,
@@ -12,6 +12,10 @@ pub enum KnownFmt {
     Ogg,
     /// Named Binary Tag, also known as NBT
     Nbt,
+    /// Bedrock Edition structure (little-endian NBT)
+    Mcstructure,
+    /// Anvil region file (chunks of a Minecraft world)
+    Mca,
     /// Configuration file
     Cfg,
     /// Wavefront .obj file
@@ -22,12 +26,26 @@ pub enum KnownFmt {
     Fsh,
     /// GLSL vertex shader
     Vsh,
+    /// GLSL geometry shader
+    Gsh,
+    /// GLSL compute shader
+    Csh,
+    /// GLSL shader (usually included by other shaders)
+    Glsl,
+    /// GLSL vertex shader (used by shader packs)
+    Vert,
+    /// GLSL fragment shader (used by shader packs)
+    Frag,
     /// JavaScript
     Js,
     /// ZenScript (ZS), format used by CraftTweaker
     Zs,
     /// JAR archive
     Jar,
+    /// ZIP archive
+    Zip,
+    /// Java class file
+    Class,
     /// Java Manifest file
     Mf,
     /// Any type format with maximum length of 3 (unused bytes are marked as zeroes)
@@ -42,15 +60,24 @@ impl KnownFmt {
             "toml" => Self::Toml,
             "png" => Self::Png,
             "ogg" => Self::Ogg,
-            "nbt" | "blueprint" => Self::Nbt,
+            "nbt" | "blueprint" | "schem" | "schematic" | "litematic" => Self::Nbt,
+            "mcstructure" => Self::Mcstructure,
+            "mca" => Self::Mca,
             "cfg" => Self::Cfg,
             "obj" => Self::Obj,
             "mtl" => Self::Mtl,
             "fsh" => Self::Fsh,
             "vsh" => Self::Vsh,
+            "gsh" => Self::Gsh,
+            "csh" => Self::Csh,
+            "glsl" => Self::Glsl,
+            "vert" => Self::Vert,
+            "frag" => Self::Frag,
             "js" => Self::Js,
             "zs" => Self::Zs,
             "jar" => Self::Jar,
+            "zip" => Self::Zip,
+            "class" => Self::Class,
             "mf" => Self::Mf,
             x => match x.as_bytes() {
                 [a] => Self::Other([*a, 0, 0]),
@@ -60,4 +87,71 @@ impl KnownFmt {
             }
         })
     }
+
+    /// Return a `KnownFmt` based on file content (magic bytes and cheap heuristics).
+    /// It detects PNG, OGG, NBT (only compressed with gzip or zlib and with a valid root tag), ZIP archives, Java classes and JSON.
+    #[must_use]
+    pub fn by_content(b: &[u8]) -> Option<Self> {
+        Some(match b {
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Self::Png,
+            [b'O', b'g', b'g', b'S', ..] => Self::Ogg,
+            [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] => Self::Zip,
+            [0xca, 0xfe, 0xba, 0xbe, ..] => Self::Class,
+            [0x1f, 0x8b, ..] if nbt_root(flate2::bufread::GzDecoder::new(b)) => Self::Nbt,
+            [0x78, x, ..] if (0x7800 | u16::from(*x)) % 31 == 0 && nbt_root(flate2::bufread::ZlibDecoder::new(b)) => Self::Nbt,
+            _ if is_json(b) => Self::Json,
+            _ => return None
+        })
+    }
+
+    /// Checks if both formats are the same (JAR and ZIP archives are the same format).
+    #[must_use]
+    pub fn same_as(self, other: Self) -> bool {
+        matches!((self, other), (Self::Jar | Self::Zip, Self::Jar | Self::Zip)) || self == other
+    }
+}
+impl std::fmt::Display for KnownFmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Json => "JSON",
+            Self::Toml => "TOML",
+            Self::Png => "PNG",
+            Self::Ogg => "OGG",
+            Self::Nbt => "NBT",
+            Self::Mcstructure => "MCSTRUCTURE",
+            Self::Mca => "MCA",
+            Self::Cfg => "CFG",
+            Self::Obj => "OBJ",
+            Self::Mtl => "MTL",
+            Self::Fsh => "FSH",
+            Self::Vsh => "VSH",
+            Self::Gsh => "GSH",
+            Self::Csh => "CSH",
+            Self::Glsl => "GLSL",
+            Self::Vert => "VERT",
+            Self::Frag => "FRAG",
+            Self::Js => "JS",
+            Self::Zs => "ZS",
+            Self::Jar => "JAR",
+            Self::Zip => "ZIP",
+            Self::Class => "CLASS",
+            Self::Mf => "MF",
+            Self::Other(x) => {
+                let n = x.iter().position(|&b| b == 0).unwrap_or(3);
+                return f.write_str(&String::from_utf8_lossy(&x[..n]).to_ascii_uppercase());
+            }
+        })
+    }
+}
+
+/// Checks if decompressed data starts with a compound tag (with a name).
+fn nbt_root(mut r: impl std::io::Read) -> bool {
+    let mut hdr = [0; 3];
+    r.read_exact(&mut hdr).is_ok() && hdr[0] == 10
+}
+
+/// Checks if data looks like a JSON object or array.
+const fn is_json(b: &[u8]) -> bool {
+    let b = super::min::strip_bom(b).trim_ascii();
+    matches!((b.first(), b.last()), (Some(b'{'), Some(b'}')) | (Some(b'['), Some(b']')))
 }
@@ -1,6 +1,6 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::LazyLock};
 
-use crate::{errors::FileIgnoreError, ext, min::Minifier};
+use crate::{cfg::ConfigMap, errors::{FileIgnoreError, FormatMismatch}, ext::KnownFmt, glob::Glob, min::{Minifier, custom::Registry}};
 
 /// A file operation needed before a file is saved in repacked archive
 #[derive(Clone)]
@@ -16,26 +16,203 @@ pub enum FileOp {
 }
 
 impl FileOp {
-    pub(crate) fn by_name(fname: &str, blacklist: &TypeBlacklist) -> Self {
-        if fname.starts_with(".cache/") { return Self::Ignore(FileIgnoreError::Blacklisted) }
-        if let Some(sub) =  fname.strip_prefix("META-INF/") {
-            match sub {
-                "SIGNFILE.SF" | "SIGNFILE.DSA" => { return Self::Ignore(FileIgnoreError::Signfile) }
-                x if x.starts_with("SIG-") || [".DSA", ".RSA", ".SF"].into_iter().any(|e| x.ends_with(e)) => {
-                    return Self::Ignore(FileIgnoreError::Signfile)
-                }
-                x if x.starts_with("services/") => { return Self::Recompress(64) }
-                _ => {}
-            }
+    /// Checks a file name (path) and returns an operation for it.
+    #[must_use]
+    pub fn by_name(fname: &str, blacklist: &TypeBlacklist) -> Self {
+        Self::by_name_in(fname, blacklist, None)
+    }
+
+    /// Checks a file name (path) and returns an operation for it.
+    /// Minifiers registered (by extension or path pattern) in `registry` are checked before the built-in ones.
+    /// Ignored files (including blacklisted ones) cannot be minified by registered minifiers.
+    #[must_use]
+    pub fn by_name_with(fname: &str, blacklist: &TypeBlacklist, registry: &Registry) -> Self {
+        Self::by_name_in(fname, blacklist, Some(registry))
+    }
+
+    fn by_name_in(fname: &str, blacklist: &TypeBlacklist, registry: Option<&Registry>) -> Self {
+        if let Some(op) = BUILTIN_RULES.iter().find_map(|r| r.action_for(fname)) {
+            return op.clone()
         }
-        let Some((_, ftype)) = fname.rsplit_once('.') else {
-            return Self::Pass
-        };
-        if blacklist.can_ignore(ftype) {
+        let ftype = fname.rsplit_once('.').map(|(_, ftype)| ftype);
+        if ftype.is_some_and(|ftype| blacklist.can_ignore(ftype)) {
             return Self::Ignore(FileIgnoreError::Blacklisted)
         }
-        ext::KnownFmt::by_extension(ftype)
-            .map_or(Self::Pass, |x| Minifier::by_file_format(x).map_or(Self::Pass, Self::Minify))
+        if let Some(m) = registry.and_then(|r| r.find_by_name(fname)) {
+            return Self::Minify(Minifier::Custom(m.clone()))
+        }
+        let Some(ftype) = ftype else {
+            return Self::Pass
+        };
+        KnownFmt::by_extension(ftype).map_or(Self::Pass, Self::by_format)
+    }
+
+    fn by_format(f: KnownFmt) -> Self {
+        Minifier::by_file_format(f).map_or(Self::Pass, Self::Minify)
+    }
+
+    /// Checks file data (see [`KnownFmt::by_content`]) and returns an operation for it
+    /// if a file has no extension or its extension does not match the content.
+    /// A mismatch is returned along with the operation if a file has an extension.
+    ///
+    /// Text formats are detected with weak heuristics, so they do not override extensions of other text formats
+    /// and unknown extensions (only binary formats with signatures are detected for them).
+    /// Files with unknown content and an extension of a format with a signature (like PNG) are passed.
+    #[must_use]
+    pub fn by_content(fname: &str, data: &[u8]) -> Option<(Self, Option<FormatMismatch>)> {
+        let base = fname.rsplit_once('/').map_or(fname, |(_, b)| b);
+        let Some((_, ftype)) = base.rsplit_once('.') else {
+            return KnownFmt::by_content(data).map(|c| (Self::by_format(c), None))
+        };
+        let efmt = match KnownFmt::by_extension(ftype) {
+            None | Some(KnownFmt::Other(_)) => {
+                let c = KnownFmt::by_content(data).filter(|&c| is_binary(c))?;
+                return Some((Self::by_format(c), Some(FormatMismatch { ext: ftype.into(), content: Some(c) })))
+            }
+            Some(f) => f
+        };
+        let cfmt = KnownFmt::by_content(data);
+        let op = match cfmt {
+            Some(c) if efmt.same_as(c) => return None,
+            Some(c) if is_binary(efmt) || is_binary(c) => Self::by_format(c),
+            None if has_signature(efmt) => Self::Pass,
+            _ => return None
+        };
+        Some((op, Some(FormatMismatch { ext: ftype.into(), content: cfmt })))
+    }
+}
+
+const fn is_binary(f: KnownFmt) -> bool {
+    matches!(f, KnownFmt::Nbt | KnownFmt::Mcstructure | KnownFmt::Mca) || has_signature(f)
+}
+
+const fn has_signature(f: KnownFmt) -> bool {
+    matches!(f, KnownFmt::Png | KnownFmt::Ogg | KnownFmt::Jar | KnownFmt::Zip | KnownFmt::Class)
+}
+
+/// Built-in rules for paths. They are checked after rules defined in [`Rules`], so they can be overridden.
+static BUILTIN_RULES: LazyLock<Vec<Rule>> = LazyLock::new(|| {
+    let sign = FileOp::Ignore(FileIgnoreError::Signfile);
+    vec![
+        Rule::new(".cache/**").with_action(FileOp::Ignore(FileIgnoreError::Blacklisted)),
+        Rule::new("META-INF/*.SF").with_action(sign.clone()),
+        Rule::new("META-INF/*.DSA").with_action(sign.clone()),
+        Rule::new("META-INF/*.RSA").with_action(sign.clone()),
+        Rule::new("META-INF/*.EC").with_action(sign.clone()),
+        Rule::new("META-INF/SIG-*").with_action(sign),
+        Rule::new("META-INF/services/**").with_action(FileOp::Recompress(64)),
+    ]
+});
+
+/// A rule for entries with paths matching a pattern. It can set an operation and override configs.
+#[derive(Clone)]
+pub struct Rule {
+    pattern: Glob,
+    action: Option<FileOp>,
+    cfgmap: Option<ConfigMap>,
+}
+impl Rule {
+    /// Creates a rule (without any action) for a path pattern.
+    pub fn new(pattern: impl Into<Glob>) -> Self {
+        Self { pattern: pattern.into(), action: None, cfgmap: None }
+    }
+
+    /// Sets an operation for matched entries.
+    #[must_use]
+    pub fn with_action(mut self, action: FileOp) -> Self {
+        self.action = Some(action);
+        self
+    }
+
+    /// Sets a config map used for matched entries. It should be a child of the main config map (see [`ConfigMap::child`]).
+    #[must_use]
+    pub fn with_config(mut self, cfgmap: ConfigMap) -> Self {
+        self.cfgmap = Some(cfgmap);
+        self
+    }
+
+    /// Returns the path pattern.
+    #[must_use]
+    pub const fn pattern(&self) -> &Glob {
+        &self.pattern
+    }
+
+    fn action_for(&self, fname: &str) -> Option<&FileOp> {
+        self.action.as_ref().filter(|_| self.pattern.matches(fname))
+    }
+}
+
+/// Ordered rules which decide how entries are handled.
+///
+/// The first matching rule with an action decides an operation for an entry.
+/// If no rule matches, built-in rules (like ignoring signature files) and the blacklist are used,
+/// then the operation depends on the file extension.
+/// The first matching rule with a config map overrides configs for an entry.
+pub struct Rules {
+    rules: Vec<Rule>,
+    blacklist: TypeBlacklist,
+    registry: Registry,
+}
+impl Rules {
+    /// Creates rules with a blacklist.
+    #[must_use]
+    pub fn new(blacklist: TypeBlacklist) -> Self {
+        Self { rules: Vec::new(), blacklist, registry: Registry::default() }
+    }
+
+    /// Sets a registry of custom minifiers. They are used for entries without an operation set by a rule.
+    #[must_use]
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Adds a rule. It is checked after rules added before.
+    #[must_use]
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Returns the blacklist.
+    #[must_use]
+    pub const fn blacklist(&self) -> &TypeBlacklist {
+        &self.blacklist
+    }
+
+    /// Returns the registry of custom minifiers.
+    #[must_use]
+    pub const fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Checks a file name (path) and returns an operation for it.
+    /// Custom minifiers from the registry are checked by name here (see [`FileOp::by_name_with`]).
+    #[must_use]
+    pub fn file_op(&self, fname: &str) -> FileOp {
+        self.action(fname).cloned().unwrap_or_else(|| FileOp::by_name_with(fname, &self.blacklist, &self.registry))
+    }
+
+    /// Returns an operation set by a matching rule (built-in rules are not checked).
+    #[must_use]
+    pub fn action(&self, fname: &str) -> Option<&FileOp> {
+        self.rules.iter().find_map(|r| r.action_for(fname))
+    }
+
+    /// Returns a config map set by a matching rule.
+    #[must_use]
+    pub fn config(&self, fname: &str) -> Option<&ConfigMap> {
+        self.rules.iter().find(|r| r.cfgmap.is_some() && r.pattern.matches(fname)).and_then(|r| r.cfgmap.as_ref())
+    }
+}
+impl Default for Rules {
+    fn default() -> Self {
+        Self::new(TypeBlacklist::default())
+    }
+}
+impl From<TypeBlacklist> for Rules {
+    fn from(blacklist: TypeBlacklist) -> Self {
+        Self::new(blacklist)
     }
 }
 
@@ -47,6 +224,12 @@ pub enum TypeBlacklist {
     /// Override the blacklist. You can define your own list of file types regardless of the predefined list.
     Override(Option<HashSet<Box<str>>>)
 }
+impl Default for TypeBlacklist {
+    /// Returns a blacklist with only built-in file types.
+    fn default() -> Self {
+        Self::Extend(None)
+    }
+}
 impl TypeBlacklist {
     fn can_ignore(&self, s: &str) -> bool {
         let inner = match self {
@@ -60,4 +243,73 @@ impl TypeBlacklist {
         };
         inner.as_ref().is_some_and(|x| x.contains(s))
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_rules() {
+        let rules = Rules::default();
+        // Only top-level files are signatures (the same as in `sign::is_signature_file`)
+        for n in ["META-INF/CERT.SF", "META-INF/CERT.EC", "META-INF/CERT.RSA", "META-INF/C.DSA", "META-INF/SIG-X"] {
+            assert!(matches!(rules.file_op(n), FileOp::Ignore(FileIgnoreError::Signfile)), "{n}");
+            assert!(crate::sign::is_signature_file(n), "{n}");
+        }
+        for n in ["CERT.SF", "assets/META-INF/CERT.SF", "META-INF/MANIFEST.MF", "META-INF/jarjar/CERT.SF", "META-INF/versions/9/A.RSA", "META-INF/SIG-X/y"] {
+            assert!(!matches!(rules.file_op(n), FileOp::Ignore(_)), "{n}");
+            assert!(!crate::sign::is_signature_file(n), "{n}");
+        }
+        assert!(matches!(rules.file_op("META-INF/services/a.b.C"), FileOp::Recompress(64)));
+        assert!(matches!(rules.file_op(".cache/x.json"), FileOp::Ignore(FileIgnoreError::Blacklisted)));
+    }
+
+    #[test]
+    fn default_blacklist() {
+        let rules = Rules::default();
+        assert!(matches!(rules.file_op("textures/a.psd"), FileOp::Ignore(FileIgnoreError::Blacklisted)));
+        assert!(matches!(rules.file_op("a.json.bak"), FileOp::Ignore(FileIgnoreError::Blacklisted)));
+        assert!(matches!(rules.file_op("a.json"), FileOp::Minify(Minifier::JSON)));
+        let rules = Rules::new(TypeBlacklist::Override(None));
+        assert!(!matches!(rules.file_op("textures/a.psd"), FileOp::Ignore(_)));
+    }
+
+    #[test]
+    #[cfg(all(feature = "png", feature = "ogg", feature = "nbt", feature = "class", feature = "jar"))]
+    fn by_content() {
+        fn gzip(b: &[u8]) -> Vec<u8> {
+            use std::io::Write;
+            let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            enc.write_all(b).unwrap();
+            enc.finish().unwrap()
+        }
+
+        fn content(fname: &str, data: &[u8]) -> Option<(FileOp, Option<KnownFmt>)> {
+            FileOp::by_content(fname, data).map(|(op, m)| (op, m.and_then(|m| m.content)))
+        }
+
+        const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let nbt = gzip(&[10, 0, 0, 1, 0, 1, b'a', 1, 0]);
+        // Files without an extension (no mismatch is reported)
+        assert!(matches!(FileOp::by_content("LICENSE", b"{\"a\": 1}"), Some((FileOp::Minify(Minifier::JSON), None))));
+        assert!(matches!(FileOp::by_content("a/data", PNG), Some((FileOp::Minify(Minifier::PNG), None))));
+        assert!(FileOp::by_content("LICENSE", b"MIT License").is_none());
+        // Unknown extensions with binary formats
+        assert!(matches!(content("image.dat", PNG), Some((FileOp::Minify(Minifier::PNG), Some(KnownFmt::Png)))));
+        assert!(matches!(content("data/raids.dat", &nbt), Some((FileOp::Minify(Minifier::NBT), Some(KnownFmt::Nbt)))));
+        assert!(matches!(content("a.bin", b"OggS\0\x02"), Some((FileOp::Minify(Minifier::OGG), Some(KnownFmt::Ogg)))));
+        assert!(matches!(content("a.bin", b"\xca\xfe\xba\xbe\0\0\0\x3d"), Some((FileOp::Minify(Minifier::Class), Some(KnownFmt::Class)))));
+        assert!(matches!(content("a.backup", b"PK\x05\x06"), Some((FileOp::Minify(Minifier::JAR), Some(KnownFmt::Zip)))));
+        // Text formats and unknown data do not override unknown extensions
+        assert!(FileOp::by_content("a.txt", b"{\"a\": 1}").is_none());
+        assert!(FileOp::by_content("a.dat", b"not nbt").is_none());
+        assert!(FileOp::by_content("a.dat", &gzip(b"text")).is_none());
+        // Known extensions
+        assert!(FileOp::by_content("a.png", PNG).is_none());
+        assert!(FileOp::by_content("a.nbt", &nbt).is_none());
+        assert!(matches!(content("a.json", PNG), Some((FileOp::Minify(Minifier::PNG), Some(KnownFmt::Png)))));
+        assert!(matches!(content("a.png", b"{\"a\": 1}"), Some((FileOp::Minify(Minifier::JSON), Some(KnownFmt::Json)))));
+        assert!(matches!(content("a.png", b"text"), Some((FileOp::Pass, None))));
+        assert!(FileOp::by_content("a.toml", b"{\"a\": 1}").is_none());
+    }
+}
@@ -0,0 +1,67 @@
+/// A pattern for matching entry paths.
+///
+/// `*` matches any characters except `/`, `**` matches any characters (including `/`)
+/// and `?` matches a single character except `/`. A pattern like `a/**/b` also matches `a/b`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob(Box<str>);
+impl Glob {
+    /// Creates a new pattern.
+    pub fn new(pattern: impl Into<Box<str>>) -> Self {
+        Self(pattern.into())
+    }
+
+    /// Returns the pattern text.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Checks if a path matches the pattern.
+    #[must_use]
+    pub fn matches(&self, path: &str) -> bool {
+        glob_match(&self.0, path)
+    }
+}
+impl From<&str> for Glob {
+    fn from(s: &str) -> Self {
+        Self::new(s)
+    }
+}
+impl From<String> for Glob {
+    fn from(s: String) -> Self {
+        Self::new(s)
+    }
+}
+impl std::str::FromStr for Glob {
+    type Err = std::convert::Infallible;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::new(s))
+    }
+}
+
+fn glob_match(p: &str, s: &str) -> bool {
+    if let Some(rest) = p.strip_prefix("**") {
+        if let Some(rest) = rest.strip_prefix('/') {
+            // Zero or more directories
+            return glob_match(rest, s)
+                || s.match_indices('/').any(|(i, _)| glob_match(rest, &s[i + 1..]));
+        }
+        return s.char_indices().map(|(i, _)| i).chain([s.len()]).any(|i| glob_match(rest, &s[i..]));
+    }
+    let mut pc = p.chars();
+    match pc.next() {
+        None => s.is_empty(),
+        Some('*') => {
+            let rest = pc.as_str();
+            let end = s.find('/').unwrap_or(s.len());
+            s[..end].char_indices().map(|(i, _)| i).chain([end]).any(|i| glob_match(rest, &s[i..]))
+        }
+        Some(c) => {
+            let mut sc = s.chars();
+            match sc.next() {
+                Some(x) if x == c || (c == '?' && x != '/') => glob_match(pc.as_str(), sc.as_str()),
+                _ => false,
+            }
+        }
+    }
+}
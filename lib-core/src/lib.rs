@@ -16,6 +16,16 @@ pub mod entry;
 pub mod cfg;
 /// Working on file extensions.
 pub mod ext;
+/// Checking and verifying signed JAR archives.
+pub mod sign;
+/// Matching entry paths with patterns.
+pub mod glob;
+/// Reading and writing NBT data.
+#[cfg(feature = "nbt")]
+pub mod nbt;
+/// A persistent cache of optimized entries.
+#[cfg(feature = "cache")]
+pub mod cache;
 
 pub(crate) type Result_<T> = anyhow::Result<T>;
 
@@ -0,0 +1,857 @@
+#![cfg(feature = "class")]
+
+use crate::cfg::{acfg, ConfigHolder};
+
+use super::Result_;
+
+acfg!(
+    /// A Java class optimizer that accepts [`ClassConfig`].
+    MinifierClass: ClassConfig
+);
+impl ConfigHolder<MinifierClass> {
+    pub(super) fn minify(&self, b: &[u8], vout: &mut Vec<u8>) -> Result_ {
+        let mut cf = ClassFile::parse(b)?;
+        let mut changed = false;
+        if self.strip_source {
+            changed |= cf.strip_attrs(|n| matches!(n, b"SourceFile" | b"SourceDebugExtension"))?;
+        }
+        if self.strip_line_numbers || self.strip_local_vars {
+            changed |= cf.strip_code_attrs(|n| match n {
+                b"LineNumberTable" => self.strip_line_numbers,
+                b"LocalVariableTable" | b"LocalVariableTypeTable" => self.strip_local_vars,
+                _ => false
+            })?;
+        }
+        if self.compact_pool {
+            changed |= cf.compact_pool()?;
+        }
+        if !changed {
+            vout.extend_from_slice(b);
+            return Ok(());
+        }
+        cf.write(vout);
+        ClassFile::parse(vout)?.check()?;
+        Ok(())
+    }
+}
+
+/// Configuration for Java class optimizer
+#[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClassConfig {
+    /// An optional flag that enables removing unused constant pool entries.
+    /// Classes with unknown attributes are never compacted.
+    /// Defaults to `true`.
+    pub compact_pool: bool,
+    /// An optional flag that enables stripping `LineNumberTable` attributes (stack traces will not show line numbers).
+    pub strip_line_numbers: bool,
+    /// An optional flag that enables stripping `LocalVariableTable` and `LocalVariableTypeTable` attributes.
+    pub strip_local_vars: bool,
+    /// An optional flag that enables stripping `SourceFile` and `SourceDebugExtension` attributes.
+    pub strip_source: bool
+}
+impl Default for ClassConfig {
+    fn default() -> Self {
+        Self { compact_pool: true, strip_line_numbers: false, strip_local_vars: false, strip_source: false }
+    }
+}
+
+/// An error that occurs when a class file is malformed
+#[derive(Debug)]
+pub enum ClassError {
+    /// A class file ends unexpectedly (or contains trailing bytes)
+    Truncated,
+    /// A class file does not start with `0xCAFEBABE`
+    BadMagic,
+    /// A constant pool entry has an unknown tag
+    BadConstant(u8),
+    /// A constant pool index is out of bounds or points to an invalid entry
+    BadIndex(u16),
+    /// A method contains an unknown opcode
+    BadOpcode(u8),
+    /// An attribute contains an unknown tag or does not match its length
+    BadAttribute
+}
+impl std::error::Error for ClassError {}
+impl std::fmt::Display for ClassError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Truncated => f.write_str("class file is truncated"),
+            Self::BadMagic => f.write_str("invalid class file signature"),
+            Self::BadConstant(t) => write!(f, "invalid constant tag: {t}"),
+            Self::BadIndex(i) => write!(f, "invalid constant index: {i}"),
+            Self::BadOpcode(o) => write!(f, "invalid opcode: {o:#04x}"),
+            Self::BadAttribute => f.write_str("malformed attribute"),
+        }
+    }
+}
+
+type ClassResult<T> = Result<T, ClassError>;
+
+struct Reader<'a> {
+    b: &'a [u8],
+    pos: usize
+}
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> ClassResult<&'a [u8]> {
+        let s = self.b.get(self.pos..self.pos + n).ok_or(ClassError::Truncated)?;
+        self.pos += n;
+        Ok(s)
+    }
+    fn u1(&mut self) -> ClassResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u2(&mut self) -> ClassResult<u16> {
+        let s = self.bytes(2)?;
+        Ok(u16::from_be_bytes([s[0], s[1]]))
+    }
+    fn u4(&mut self) -> ClassResult<u32> {
+        let s = self.bytes(4)?;
+        Ok(u32::from_be_bytes([s[0], s[1], s[2], s[3]]))
+    }
+    fn attrs(&mut self) -> ClassResult<Vec<Attr>> {
+        let n = self.u2()?;
+        (0..n).map(|_| {
+            let name = self.u2()?;
+            let len = self.u4()? as usize;
+            Ok(Attr { name, data: self.bytes(len)?.to_vec() })
+        }).collect()
+    }
+}
+
+/// A cursor that can rewrite constant pool indices in place.
+struct Cur<'a> {
+    b: &'a mut [u8],
+    pos: usize
+}
+impl<'a> Cur<'a> {
+    const fn new(b: &'a mut [u8]) -> Self {
+        Self { b, pos: 0 }
+    }
+    const fn skip(&mut self, n: usize) -> ClassResult<()> {
+        if self.pos + n > self.b.len() {
+            return Err(ClassError::Truncated);
+        }
+        self.pos += n;
+        Ok(())
+    }
+    fn u1(&mut self) -> ClassResult<u8> {
+        let v = *self.b.get(self.pos).ok_or(ClassError::Truncated)?;
+        self.pos += 1;
+        Ok(v)
+    }
+    fn u2(&mut self) -> ClassResult<u16> {
+        let s = self.b.get(self.pos..self.pos + 2).ok_or(ClassError::Truncated)?;
+        self.pos += 2;
+        Ok(u16::from_be_bytes([s[0], s[1]]))
+    }
+    fn u4(&mut self) -> ClassResult<u32> {
+        let s = self.b.get(self.pos..self.pos + 4).ok_or(ClassError::Truncated)?;
+        self.pos += 4;
+        Ok(u32::from_be_bytes([s[0], s[1], s[2], s[3]]))
+    }
+    fn sub(&mut self, n: usize) -> ClassResult<Cur<'_>> {
+        let start = self.pos;
+        self.skip(n)?;
+        Ok(Cur::new(&mut self.b[start..start + n]))
+    }
+    fn peek_u2(&self) -> ClassResult<u16> {
+        let s = self.b.get(self.pos..self.pos + 2).ok_or(ClassError::Truncated)?;
+        Ok(u16::from_be_bytes([s[0], s[1]]))
+    }
+    /// Reads a constant pool index and replaces it with a visited one (zero means "no entry" and is never visited).
+    fn idx(&mut self, w: &mut Walk) -> ClassResult<()> {
+        let i = self.u2()?;
+        if i != 0 {
+            let ni = w.visit(i)?;
+            self.b[self.pos - 2..self.pos].copy_from_slice(&ni.to_be_bytes());
+        }
+        Ok(())
+    }
+    const fn is_end(&self) -> bool {
+        self.pos == self.b.len()
+    }
+}
+
+struct Constant {
+    /// A constant tag (zero is used for unusable entries)
+    tag: u8,
+    data: Vec<u8>
+}
+impl Constant {
+    /// Returns offsets of constant pool indices in constant data.
+    const fn ref_offsets(&self) -> &'static [usize] {
+        match self.tag {
+            7 | 8 | 16 | 19 | 20 => &[0],
+            9..=12 => &[0, 2],
+            15 => &[1],
+            17 | 18 => &[2],
+            _ => &[]
+        }
+    }
+    fn refs(&self) -> impl Iterator<Item = u16> + '_ {
+        self.ref_offsets().iter().map(|&o| u16::from_be_bytes([self.data[o], self.data[o + 1]]))
+    }
+}
+
+struct Attr {
+    name: u16,
+    data: Vec<u8>
+}
+
+struct Member {
+    access: u16,
+    name: u16,
+    desc: u16,
+    attrs: Vec<Attr>
+}
+
+struct ClassFile {
+    version: [u8; 4],
+    cp: Vec<Constant>,
+    access: u16,
+    this: u16,
+    sup: u16,
+    interfaces: Vec<u16>,
+    fields: Vec<Member>,
+    methods: Vec<Member>,
+    attrs: Vec<Attr>
+}
+
+/// A visitor for constant pool indices.
+struct Walk<'f> {
+    f: &'f mut dyn FnMut(u16) -> ClassResult<u16>,
+    /// Set if any attribute could not be recognized (its indices were not visited)
+    unknown: bool
+}
+impl Walk<'_> {
+    #[inline]
+    fn visit(&mut self, i: u16) -> ClassResult<u16> {
+        (self.f)(i)
+    }
+}
+
+impl ClassFile {
+    fn parse(b: &[u8]) -> ClassResult<Self> {
+        let mut r = Reader { b, pos: 0 };
+        if r.bytes(4)? != [0xCA, 0xFE, 0xBA, 0xBE] {
+            return Err(ClassError::BadMagic);
+        }
+        let version = r.bytes(4)?.try_into().map_err(|_| ClassError::Truncated)?;
+        let count = r.u2()? as usize;
+        let mut cp = Vec::with_capacity(count);
+        cp.push(Constant { tag: 0, data: Vec::new() });
+        while cp.len() < count {
+            let tag = r.u1()?;
+            let len = match tag {
+                1 => {
+                    let l = r.u2()? as usize;
+                    r.pos -= 2;
+                    l + 2
+                }
+                7 | 8 | 16 | 19 | 20 => 2,
+                15 => 3,
+                3 | 4 | 9..=12 | 17 | 18 => 4,
+                5 | 6 => 8,
+                t => return Err(ClassError::BadConstant(t))
+            };
+            cp.push(Constant { tag, data: r.bytes(len)?.to_vec() });
+            if matches!(tag, 5 | 6) {
+                cp.push(Constant { tag: 0, data: Vec::new() });
+            }
+        }
+        if cp.len() != count {
+            return Err(ClassError::BadConstant(0));
+        }
+        let access = r.u2()?;
+        let this = r.u2()?;
+        let sup = r.u2()?;
+        let ni = r.u2()?;
+        let interfaces = (0..ni).map(|_| r.u2()).collect::<ClassResult<_>>()?;
+        let mut members = || -> ClassResult<Vec<Member>> {
+            let n = r.u2()?;
+            (0..n).map(|_| Ok(Member { access: r.u2()?, name: r.u2()?, desc: r.u2()?, attrs: r.attrs()? })).collect()
+        };
+        let fields = members()?;
+        let methods = members()?;
+        let attrs = r.attrs()?;
+        if r.pos != b.len() {
+            return Err(ClassError::Truncated);
+        }
+        Ok(Self { version, cp, access, this, sup, interfaces, fields, methods, attrs })
+    }
+
+    fn write(&self, vout: &mut Vec<u8>) {
+        fn put_attrs(vout: &mut Vec<u8>, attrs: &[Attr]) {
+            vout.extend_from_slice(&(attrs.len() as u16).to_be_bytes());
+            for a in attrs {
+                vout.extend_from_slice(&a.name.to_be_bytes());
+                vout.extend_from_slice(&(a.data.len() as u32).to_be_bytes());
+                vout.extend_from_slice(&a.data);
+            }
+        }
+        fn put_members(vout: &mut Vec<u8>, members: &[Member]) {
+            vout.extend_from_slice(&(members.len() as u16).to_be_bytes());
+            for m in members {
+                for x in [m.access, m.name, m.desc] {
+                    vout.extend_from_slice(&x.to_be_bytes());
+                }
+                put_attrs(vout, &m.attrs);
+            }
+        }
+        vout.extend_from_slice(&[0xCA, 0xFE, 0xBA, 0xBE]);
+        vout.extend_from_slice(&self.version);
+        vout.extend_from_slice(&(self.cp.len() as u16).to_be_bytes());
+        for c in self.cp.iter().filter(|c| c.tag != 0) {
+            vout.push(c.tag);
+            vout.extend_from_slice(&c.data);
+        }
+        for x in [self.access, self.this, self.sup, self.interfaces.len() as u16] {
+            vout.extend_from_slice(&x.to_be_bytes());
+        }
+        for x in &self.interfaces {
+            vout.extend_from_slice(&x.to_be_bytes());
+        }
+        put_members(vout, &self.fields);
+        put_members(vout, &self.methods);
+        put_attrs(vout, &self.attrs);
+    }
+
+    /// Removes class attributes with matching names.
+    fn strip_attrs(&mut self, strip: impl Fn(&[u8]) -> bool) -> ClassResult<bool> {
+        let n = self.attrs.len();
+        let cp = &self.cp;
+        let mut err = None;
+        self.attrs.retain(|a| match utf8(cp, a.name) {
+            Ok(name) => !strip(name),
+            Err(e) => {
+                err = Some(e);
+                true
+            }
+        });
+        err.map_or(Ok(self.attrs.len() != n), Err)
+    }
+
+    /// Removes attributes with matching names from all `Code` attributes.
+    fn strip_code_attrs(&mut self, strip: impl Fn(&[u8]) -> bool) -> ClassResult<bool> {
+        let mut changed = false;
+        for a in self.methods.iter_mut().flat_map(|m| &mut m.attrs) {
+            if utf8(&self.cp, a.name)? != b"Code" {
+                continue;
+            }
+            let mut r = Reader { b: &a.data, pos: 4 };
+            let code_len = r.u4()? as usize;
+            r.bytes(code_len)?;
+            let exc_len = r.u2()? as usize;
+            r.bytes(exc_len * 8)?;
+            let head = r.pos;
+            let nested = r.attrs()?;
+            let n = nested.len();
+            let mut kept = Vec::with_capacity(n);
+            for na in nested {
+                if !strip(utf8(&self.cp, na.name)?) {
+                    kept.push(na);
+                }
+            }
+            if kept.len() == n {
+                continue;
+            }
+            a.data.truncate(head);
+            a.data.extend_from_slice(&(kept.len() as u16).to_be_bytes());
+            for na in kept {
+                a.data.extend_from_slice(&na.name.to_be_bytes());
+                a.data.extend_from_slice(&(na.data.len() as u32).to_be_bytes());
+                a.data.extend_from_slice(&na.data);
+            }
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// Visits every constant pool index used by the class structure (without constants referring to other constants).
+    fn walk(&mut self, w: &mut Walk) -> ClassResult<()> {
+        let cp = &self.cp;
+        self.this = visit_opt(w, self.this)?;
+        self.sup = visit_opt(w, self.sup)?;
+        for i in &mut self.interfaces {
+            *i = w.visit(*i)?;
+        }
+        for m in self.fields.iter_mut().chain(&mut self.methods) {
+            m.name = w.visit(m.name)?;
+            m.desc = w.visit(m.desc)?;
+            walk_attrs(cp, &mut m.attrs, w)?;
+        }
+        walk_attrs(cp, &mut self.attrs, w)
+    }
+
+    /// Removes unused constant pool entries while keeping the order of used ones.
+    /// Returns `false` if nothing was removed or the class contains unknown attributes.
+    fn compact_pool(&mut self) -> ClassResult<bool> {
+        let len = self.cp.len();
+        let mut used = vec![false; len];
+        let mut mark = |i: u16| {
+            *used.get_mut(i as usize).ok_or(ClassError::BadIndex(i))? = true;
+            Ok(i)
+        };
+        let mut w = Walk { f: &mut mark, unknown: false };
+        self.walk(&mut w)?;
+        if w.unknown {
+            return Ok(false);
+        }
+        let mut stack: Vec<usize> = (0..len).filter(|&i| used[i]).collect();
+        while let Some(i) = stack.pop() {
+            for r in self.cp[i].refs() {
+                let u = used.get_mut(r as usize).ok_or(ClassError::BadIndex(r))?;
+                if !*u {
+                    *u = true;
+                    stack.push(r as usize);
+                }
+            }
+        }
+        let mut map = vec![0u16; len];
+        let mut next = 1u16;
+        for i in 1..len {
+            if used[i] && self.cp[i].tag != 0 {
+                map[i] = next;
+                next += if matches!(self.cp[i].tag, 5 | 6) { 2 } else { 1 };
+            }
+        }
+        if next as usize == len {
+            return Ok(false);
+        }
+        let mut remap = |i: u16| match map.get(i as usize) {
+            Some(&ni) if ni != 0 => Ok(ni),
+            _ => Err(ClassError::BadIndex(i))
+        };
+        self.walk(&mut Walk { f: &mut remap, unknown: false })?;
+        let old = std::mem::take(&mut self.cp);
+        self.cp.push(Constant { tag: 0, data: Vec::new() });
+        for (i, mut c) in old.into_iter().enumerate() {
+            if map[i] == 0 {
+                continue;
+            }
+            for &o in c.ref_offsets() {
+                let r = u16::from_be_bytes([c.data[o], c.data[o + 1]]);
+                c.data[o..o + 2].copy_from_slice(&map[r as usize].to_be_bytes());
+            }
+            let wide = matches!(c.tag, 5 | 6);
+            self.cp.push(c);
+            if wide {
+                self.cp.push(Constant { tag: 0, data: Vec::new() });
+            }
+        }
+        Ok(true)
+    }
+
+    /// Checks if every constant pool index points to a valid entry.
+    fn check(mut self) -> ClassResult<()> {
+        let valid: Vec<bool> = self.cp.iter().map(|c| c.tag != 0).collect();
+        let is_valid = |i: u16| valid.get(i as usize).copied().unwrap_or(false);
+        for c in &self.cp {
+            if let Some(r) = c.refs().find(|&r| !is_valid(r)) {
+                return Err(ClassError::BadIndex(r));
+            }
+        }
+        let mut check = |i: u16| if is_valid(i) { Ok(i) } else { Err(ClassError::BadIndex(i)) };
+        self.walk(&mut Walk { f: &mut check, unknown: false })
+    }
+}
+
+#[inline]
+fn visit_opt(w: &mut Walk, i: u16) -> ClassResult<u16> {
+    if i == 0 { Ok(0) } else { w.visit(i) }
+}
+
+fn utf8(cp: &[Constant], i: u16) -> ClassResult<&[u8]> {
+    match cp.get(i as usize) {
+        Some(Constant { tag: 1, data }) => Ok(&data[2..]),
+        _ => Err(ClassError::BadIndex(i))
+    }
+}
+
+fn walk_attrs(cp: &[Constant], attrs: &mut [Attr], w: &mut Walk) -> ClassResult<()> {
+    for a in attrs {
+        let name = utf8(cp, a.name)?;
+        walk_attr_data(cp, name, &mut Cur::new(&mut a.data), w)?;
+        a.name = w.visit(a.name)?;
+    }
+    Ok(())
+}
+
+fn walk_nested_attrs(cp: &[Constant], c: &mut Cur, w: &mut Walk) -> ClassResult<()> {
+    for _ in 0..c.u2()? {
+        let name = utf8(cp, c.peek_u2()?)?;
+        c.idx(w)?;
+        let len = c.u4()? as usize;
+        walk_attr_data(cp, name, &mut c.sub(len)?, w)?;
+    }
+    Ok(())
+}
+
+fn walk_attr_data(cp: &[Constant], name: &[u8], c: &mut Cur, w: &mut Walk) -> ClassResult<()> {
+    match name {
+        b"ConstantValue" | b"Signature" | b"SourceFile" | b"NestHost" | b"ModuleMainClass" => c.idx(w)?,
+        b"Exceptions" | b"NestMembers" | b"PermittedSubclasses" | b"ModulePackages" => {
+            for _ in 0..c.u2()? {
+                c.idx(w)?;
+            }
+        }
+        b"InnerClasses" => {
+            for _ in 0..c.u2()? {
+                c.idx(w)?;
+                c.idx(w)?;
+                c.idx(w)?;
+                c.skip(2)?;
+            }
+        }
+        b"EnclosingMethod" => {
+            c.idx(w)?;
+            c.idx(w)?;
+        }
+        b"Code" => {
+            c.skip(4)?;
+            let len = c.u4()? as usize;
+            walk_bytecode(&mut c.sub(len)?, w)?;
+            for _ in 0..c.u2()? {
+                c.skip(6)?;
+                c.idx(w)?;
+            }
+            walk_nested_attrs(cp, c, w)?;
+        }
+        b"LineNumberTable" | b"SourceDebugExtension" | b"Deprecated" | b"Synthetic" => {
+            c.pos = c.b.len();
+        }
+        b"LocalVariableTable" | b"LocalVariableTypeTable" => {
+            for _ in 0..c.u2()? {
+                c.skip(4)?;
+                c.idx(w)?;
+                c.idx(w)?;
+                c.skip(2)?;
+            }
+        }
+        b"StackMapTable" => {
+            for _ in 0..c.u2()? {
+                walk_frame(c, w)?;
+            }
+        }
+        b"RuntimeVisibleAnnotations" | b"RuntimeInvisibleAnnotations" => {
+            for _ in 0..c.u2()? {
+                walk_annotation(c, w)?;
+            }
+        }
+        b"RuntimeVisibleParameterAnnotations" | b"RuntimeInvisibleParameterAnnotations" => {
+            for _ in 0..c.u1()? {
+                for _ in 0..c.u2()? {
+                    walk_annotation(c, w)?;
+                }
+            }
+        }
+        b"RuntimeVisibleTypeAnnotations" | b"RuntimeInvisibleTypeAnnotations" => {
+            for _ in 0..c.u2()? {
+                walk_type_annotation(c, w)?;
+            }
+        }
+        b"AnnotationDefault" => walk_element_value(c, w)?,
+        b"BootstrapMethods" => {
+            for _ in 0..c.u2()? {
+                c.idx(w)?;
+                for _ in 0..c.u2()? {
+                    c.idx(w)?;
+                }
+            }
+        }
+        b"MethodParameters" => {
+            for _ in 0..c.u1()? {
+                c.idx(w)?;
+                c.skip(2)?;
+            }
+        }
+        b"Module" => walk_module(c, w)?,
+        b"Record" => {
+            for _ in 0..c.u2()? {
+                c.idx(w)?;
+                c.idx(w)?;
+                walk_nested_attrs(cp, c, w)?;
+            }
+        }
+        _ => {
+            w.unknown = true;
+            c.pos = c.b.len();
+        }
+    }
+    if c.is_end() { Ok(()) } else { Err(ClassError::BadAttribute) }
+}
+
+fn walk_bytecode(c: &mut Cur, w: &mut Walk) -> ClassResult<()> {
+    while !c.is_end() {
+        let pc = c.pos;
+        let op = c.u1()?;
+        match op {
+            0x12 => {
+                // ldc has a one-byte index, compacting never makes it larger
+                let i = u16::from(c.u1()?);
+                let ni = u8::try_from(w.visit(i)?).map_err(|_| ClassError::BadIndex(i))?;
+                c.b[c.pos - 1] = ni;
+            }
+            0x13 | 0x14 | 0xb2..=0xb8 | 0xbb | 0xbd | 0xc0 | 0xc1 => c.idx(w)?,
+            0xb9 | 0xba => {
+                c.idx(w)?;
+                c.skip(2)?;
+            }
+            0xc5 => {
+                c.idx(w)?;
+                c.skip(1)?;
+            }
+            0xaa => {
+                c.skip(3 - pc % 4)?;
+                c.skip(4)?;
+                let low = c.u4()? as i32;
+                let high = c.u4()? as i32;
+                let n = usize::try_from(i64::from(high) - i64::from(low) + 1).map_err(|_| ClassError::BadOpcode(op))?;
+                c.skip(n * 4)?;
+            }
+            0xab => {
+                c.skip(3 - pc % 4)?;
+                c.skip(4)?;
+                let n = c.u4()? as usize;
+                c.skip(n * 8)?;
+            }
+            0xc4 => {
+                let wop = c.u1()?;
+                c.skip(if wop == 0x84 { 4 } else { 2 })?;
+            }
+            0x10 | 0x15..=0x19 | 0x36..=0x3a | 0xa9 | 0xbc => c.skip(1)?,
+            0x11 | 0x84 | 0x99..=0xa8 | 0xc6 | 0xc7 => c.skip(2)?,
+            0xc8 | 0xc9 => c.skip(4)?,
+            0x00..=0x0f | 0x1a..=0x35 | 0x3b..=0x83 | 0x85..=0x98 | 0xac..=0xb1 | 0xbe | 0xbf | 0xc2 | 0xc3 => {}
+            _ => return Err(ClassError::BadOpcode(op))
+        }
+    }
+    Ok(())
+}
+
+fn walk_frame(c: &mut Cur, w: &mut Walk) -> ClassResult<()> {
+    let t = c.u1()?;
+    match t {
+        0..=63 => {}
+        64..=127 => walk_vtype(c, w)?,
+        247 => {
+            c.skip(2)?;
+            walk_vtype(c, w)?;
+        }
+        248..=251 => c.skip(2)?,
+        252..=254 => {
+            c.skip(2)?;
+            for _ in 251..t {
+                walk_vtype(c, w)?;
+            }
+        }
+        255 => {
+            c.skip(2)?;
+            for _ in 0..2 {
+                for _ in 0..c.u2()? {
+                    walk_vtype(c, w)?;
+                }
+            }
+        }
+        _ => return Err(ClassError::BadAttribute)
+    }
+    Ok(())
+}
+
+fn walk_vtype(c: &mut Cur, w: &mut Walk) -> ClassResult<()> {
+    match c.u1()? {
+        0..=6 => Ok(()),
+        7 => c.idx(w),
+        8 => c.skip(2),
+        _ => Err(ClassError::BadAttribute)
+    }
+}
+
+fn walk_annotation(c: &mut Cur, w: &mut Walk) -> ClassResult<()> {
+    c.idx(w)?;
+    for _ in 0..c.u2()? {
+        c.idx(w)?;
+        walk_element_value(c, w)?;
+    }
+    Ok(())
+}
+
+fn walk_element_value(c: &mut Cur, w: &mut Walk) -> ClassResult<()> {
+    match c.u1()? {
+        b'B' | b'C' | b'D' | b'F' | b'I' | b'J' | b'S' | b'Z' | b's' | b'c' => c.idx(w),
+        b'e' => {
+            c.idx(w)?;
+            c.idx(w)
+        }
+        b'@' => walk_annotation(c, w),
+        b'[' => {
+            for _ in 0..c.u2()? {
+                walk_element_value(c, w)?;
+            }
+            Ok(())
+        }
+        _ => Err(ClassError::BadAttribute)
+    }
+}
+
+fn walk_type_annotation(c: &mut Cur, w: &mut Walk) -> ClassResult<()> {
+    match c.u1()? {
+        0x00 | 0x01 | 0x16 => c.skip(1)?,
+        0x10 | 0x11 | 0x12 | 0x17 | 0x42..=0x46 => c.skip(2)?,
+        0x13..=0x15 => {}
+        0x40 | 0x41 => {
+            let n = c.u2()? as usize;
+            c.skip(n * 6)?;
+        }
+        0x47..=0x4b => c.skip(3)?,
+        _ => return Err(ClassError::BadAttribute)
+    }
+    let path_len = c.u1()? as usize;
+    c.skip(path_len * 2)?;
+    walk_annotation(c, w)
+}
+
+fn walk_module(c: &mut Cur, w: &mut Walk) -> ClassResult<()> {
+    c.idx(w)?;
+    c.skip(2)?;
+    c.idx(w)?;
+    for _ in 0..c.u2()? {
+        c.idx(w)?;
+        c.skip(2)?;
+        c.idx(w)?;
+    }
+    // exports and opens
+    for _ in 0..2 {
+        for _ in 0..c.u2()? {
+            c.idx(w)?;
+            c.skip(2)?;
+            for _ in 0..c.u2()? {
+                c.idx(w)?;
+            }
+        }
+    }
+    for _ in 0..c.u2()? {
+        c.idx(w)?;
+    }
+    for _ in 0..c.u2()? {
+        c.idx(w)?;
+        for _ in 0..c.u2()? {
+            c.idx(w)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cfg::ConfigMap;
+
+    use super::{Attr, ClassConfig, ClassFile, Constant, MinifierClass};
+
+    // Compiled with `javac -g --release 17` from sources in the same directory
+    const CONSTS: &[u8] = include_bytes!("../../tests/classes/Consts.class");
+    const SWITCHES: &[u8] = include_bytes!("../../tests/classes/Switches.class");
+    const WIDE: &[u8] = include_bytes!("../../tests/classes/Wide.class");
+
+    fn minify(b: &[u8], cfg: ClassConfig) -> Vec<u8> {
+        let cfgmap = ConfigMap::default();
+        cfgmap.set::<MinifierClass>(cfg);
+        let mut v = Vec::new();
+        cfgmap.fetch::<MinifierClass>().minify(b, &mut v).unwrap();
+        v
+    }
+
+    fn strip_debug() -> ClassConfig {
+        ClassConfig { compact_pool: true, strip_line_numbers: true, strip_local_vars: true, strip_source: true }
+    }
+
+    fn utf8s(cf: &ClassFile) -> Vec<&[u8]> {
+        cf.cp.iter().filter(|c| c.tag == 1).map(|c| &c.data[2..]).collect()
+    }
+
+    fn count_tags(cf: &ClassFile, tags: &[u8]) -> usize {
+        cf.cp.iter().filter(|c| tags.contains(&c.tag)).count()
+    }
+
+    /// Returns lengths of bytecode in `Code` attributes of all methods.
+    fn code_lengths(cf: &ClassFile) -> Vec<u32> {
+        cf.methods.iter().flat_map(|m| &m.attrs)
+            .filter(|a| super::utf8(&cf.cp, a.name).is_ok_and(|n| n == b"Code"))
+            .map(|a| u32::from_be_bytes([a.data[4], a.data[5], a.data[6], a.data[7]]))
+            .collect()
+    }
+
+    #[test]
+    fn fixtures_are_valid() {
+        for b in [CONSTS, SWITCHES, WIDE] {
+            ClassFile::parse(b).unwrap().check().unwrap();
+        }
+    }
+
+    #[test]
+    fn unchanged_class_is_copied() {
+        assert_eq!(minify(SWITCHES, ClassConfig::default()), SWITCHES);
+    }
+
+    #[test]
+    fn long_and_double_constants() {
+        let out = minify(CONSTS, strip_debug());
+        assert!(out.len() < CONSTS.len());
+        let (old, new) = (ClassFile::parse(CONSTS).unwrap(), ClassFile::parse(&out).unwrap());
+        // Wide constants take two slots, so remapped indices after them must stay consistent
+        assert_eq!(count_tags(&old, &[5, 6]), count_tags(&new, &[5, 6]));
+        assert_eq!(code_lengths(&old), code_lengths(&new));
+        assert!(utf8s(&new).contains(&&b"RuntimeVisibleTypeAnnotations"[..]));
+        assert!(utf8s(&new).contains(&&b"BootstrapMethods"[..]));
+        new.check().unwrap();
+    }
+
+    #[test]
+    fn tableswitch_alignments() {
+        // Switches start at offsets 1, 2, 4, 5 and 7, so their padding has every length
+        let out = minify(SWITCHES, strip_debug());
+        let (old, new) = (ClassFile::parse(SWITCHES).unwrap(), ClassFile::parse(&out).unwrap());
+        assert!(new.cp.len() < old.cp.len());
+        assert_eq!(code_lengths(&old), code_lengths(&new));
+        new.check().unwrap();
+    }
+
+    #[test]
+    fn ldc_and_ldc_w() {
+        let out = minify(WIDE, strip_debug());
+        let new = ClassFile::parse(&out).unwrap();
+        assert!(new.cp.len() > 256);
+        new.check().unwrap();
+    }
+
+    #[test]
+    fn strip_debug_attributes() {
+        for b in [CONSTS, SWITCHES, WIDE] {
+            let out = minify(b, ClassConfig { strip_source: false, ..strip_debug() });
+            let new = ClassFile::parse(&out).unwrap();
+            let names = utf8s(&new);
+            assert!(!names.contains(&&b"LineNumberTable"[..]));
+            assert!(!names.contains(&&b"LocalVariableTable"[..]));
+            assert!(names.contains(&&b"SourceFile"[..]));
+            new.check().unwrap();
+        }
+    }
+
+    #[test]
+    fn unknown_attribute_skips_compaction() {
+        let mut cf = ClassFile::parse(SWITCHES).unwrap();
+        let name = b"CustomAttribute";
+        let mut data = (name.len() as u16).to_be_bytes().to_vec();
+        data.extend_from_slice(name);
+        cf.cp.push(Constant { tag: 1, data });
+        cf.attrs.push(Attr { name: (cf.cp.len() - 1) as u16, data: vec![1, 2, 3] });
+        let mut b = Vec::new();
+        cf.write(&mut b);
+
+        let mut cf = ClassFile::parse(&b).unwrap();
+        assert!(cf.strip_code_attrs(|n| n == b"LineNumberTable").unwrap());
+        assert!(!cf.compact_pool().unwrap());
+        let cfg = ClassConfig { compact_pool: true, strip_line_numbers: false, strip_local_vars: false, strip_source: false };
+        assert_eq!(minify(&b, cfg), b);
+    }
+}
@@ -0,0 +1,195 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    cfg::{AcceptsConfig, ConfigMap},
+    glob::Glob,
+};
+
+use super::Result_;
+
+/// A minifier defined outside of this library. It should be registered in [`Registry`].
+///
+/// Its config is stored in [`ConfigMap`] (like configs of built-in minifiers) and can be set with [`ConfigMap::set`].
+pub trait CustomMinifier: Send + Sync + 'static {
+    /// A type that accepts the config of this minifier. It usually is an empty enum.
+    type Config: AcceptsConfig;
+
+    /// Returns a unique name of the minifier.
+    fn name(&self) -> &str;
+
+    /// Minifies file data and writes the result in provided vec.
+    /// # Errors
+    /// Returns an error if minifying fails
+    fn minify(&self, cfg: &<Self::Config as AcceptsConfig>::Cfg, v: &[u8], vout: &mut Vec<u8>) -> Result_;
+
+    /// Define a minimal size for file compression. Files with lower sizes will be stored as-is.
+    fn compress_min(&self) -> u16 {
+        24
+    }
+
+    /// Returns a text which identifies the config (used by the entry cache).
+    /// Results of this minifier are not cached if it returns `None` (the default).
+    fn fingerprint(&self, _cfg: &<Self::Config as AcceptsConfig>::Cfg) -> Option<String> {
+        None
+    }
+}
+
+/// An object-safe version of [`CustomMinifier`].
+trait DynMinifier: Send + Sync {
+    fn name(&self) -> &str;
+    fn minify(&self, cfgmap: &ConfigMap, v: &[u8], vout: &mut Vec<u8>) -> Result_;
+    fn compress_min(&self) -> u16;
+    #[cfg(feature = "cache")]
+    fn fingerprint(&self, cfgmap: &ConfigMap) -> Option<String>;
+}
+impl<M: CustomMinifier> DynMinifier for M {
+    fn name(&self) -> &str {
+        CustomMinifier::name(self)
+    }
+    fn minify(&self, cfgmap: &ConfigMap, v: &[u8], vout: &mut Vec<u8>) -> Result_ {
+        CustomMinifier::minify(self, &cfgmap.fetch::<M::Config>(), v, vout)
+    }
+    fn compress_min(&self) -> u16 {
+        CustomMinifier::compress_min(self)
+    }
+    #[cfg(feature = "cache")]
+    fn fingerprint(&self, cfgmap: &ConfigMap) -> Option<String> {
+        CustomMinifier::fingerprint(self, &cfgmap.fetch::<M::Config>())
+    }
+}
+
+/// A shared handle of a [`CustomMinifier`]. It is used by [`super::Minifier::Custom`].
+#[derive(Clone)]
+pub struct Custom(Arc<dyn DynMinifier>);
+impl Custom {
+    /// Creates a handle of a minifier.
+    pub fn new(m: impl CustomMinifier) -> Self {
+        Self(Arc::new(m))
+    }
+
+    /// Returns the minifier name.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    pub(super) fn minify(&self, cfgmap: &ConfigMap, v: &[u8], vout: &mut Vec<u8>) -> Result_ {
+        self.0.minify(cfgmap, v, vout)
+    }
+
+    pub(super) fn compress_min(&self) -> u16 {
+        self.0.compress_min()
+    }
+
+    #[cfg(feature = "cache")]
+    pub(super) fn fingerprint(&self, cfgmap: &ConfigMap) -> Option<String> {
+        self.0.fingerprint(cfgmap).map(|f| format!("custom-{}:{f}", self.name()))
+    }
+}
+
+/// A function which checks file data (usually its first bytes) to detect a format.
+pub type Sniffer = Box<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// Custom minifiers matched by file extension, path pattern or content.
+///
+/// Path patterns are checked first (in order of registration), then extensions, then content sniffers.
+/// Registered minifiers are used before the built-in ones, but operations set by rules are kept.
+/// A registry is a part of [`Rules`](crate::fop::Rules) (see [`Rules::with_registry`](crate::fop::Rules::with_registry)).
+#[derive(Default)]
+pub struct Registry {
+    by_path: Vec<(Glob, Custom)>,
+    by_ext: HashMap<Box<str>, Custom>,
+    by_content: Vec<(Sniffer, Custom)>,
+}
+impl Registry {
+    /// Registers a minifier for files with an extension (without a dot).
+    #[must_use]
+    pub fn with_extension(mut self, ext: &str, m: Custom) -> Self {
+        self.by_ext.insert(ext.into(), m);
+        self
+    }
+
+    /// Registers a minifier for files with paths matching a pattern.
+    #[must_use]
+    pub fn with_path(mut self, pattern: Glob, m: Custom) -> Self {
+        self.by_path.push((pattern, m));
+        self
+    }
+
+    /// Registers a minifier for files with data accepted by a sniffer.
+    #[must_use]
+    pub fn with_content(mut self, sniffer: impl Fn(&[u8]) -> bool + Send + Sync + 'static, m: Custom) -> Self {
+        self.by_content.push((Box::new(sniffer), m));
+        self
+    }
+
+    /// Returns `true` if no minifiers are registered.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.by_path.is_empty() && self.by_ext.is_empty() && self.by_content.is_empty()
+    }
+
+    /// Finds a minifier for a file name (path).
+    #[must_use]
+    pub fn find_by_name(&self, fname: &str) -> Option<&Custom> {
+        self.by_path.iter().find(|(g, _)| g.matches(fname)).map(|(_, m)| m).or_else(|| {
+            let (_, ftype) = fname.rsplit_once('.')?;
+            self.by_ext.get(ftype)
+        })
+    }
+
+    /// Finds a minifier for a file name (path) or its data.
+    #[must_use]
+    pub fn find(&self, fname: &str, data: &[u8]) -> Option<&Custom> {
+        self.find_by_name(fname).or_else(|| self.by_content.iter().find(|(s, _)| s(data)).map(|(_, m)| m))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::{
+        entry::{EntryReader, SavingEntry, ZipEntryReader, process_entry, read_entry},
+        errors::ErrorCollector,
+        fop::{FileOp, Rules},
+        min::Minifier,
+    };
+
+    struct Upper;
+    enum UpperCfg {}
+    impl AcceptsConfig for UpperCfg {
+        type Cfg = ();
+    }
+    impl CustomMinifier for Upper {
+        type Config = UpperCfg;
+        fn name(&self) -> &'static str {
+            "upper"
+        }
+        fn minify(&self, (): &(), v: &[u8], vout: &mut Vec<u8>) -> Result_ {
+            vout.extend(v.to_ascii_uppercase());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn registered_by_extension() {
+        let mut zw = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zw.start_file("assets/a.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        zw.write_all(b"some text").unwrap();
+        let data = zw.finish().unwrap().into_inner();
+
+        let rules = Rules::default().with_registry(Registry::default().with_extension("txt", Custom::new(Upper)));
+        assert!(matches!(rules.file_op("a.txt"), FileOp::Minify(Minifier::Custom(c)) if c.name() == "upper"));
+        let mut zr = ZipEntryReader::new_mem(&data[..]).unwrap();
+        zr.keep_compressed();
+        let re = zr.read_iter().next().unwrap();
+        let ne = read_entry::<ZipEntryReader<_>>(re, &rules).unwrap().unwrap();
+        let mut ev = ErrorCollector::new(false);
+        let mut cv = Vec::new();
+        let se = process_entry(&mut cv, &ne, &mut ev, &ConfigMap::default(), &rules);
+        assert!(matches!(se, Some(SavingEntry::File(b"SOME TEXT", _))));
+        assert!(ev.results().is_empty());
+    }
+}
@@ -0,0 +1,408 @@
+use std::{borrow::Cow, collections::{HashMap, HashSet}};
+
+use crate::cfg::{acfg, ConfigHolder};
+
+use super::Result_;
+
+acfg!(
+    /// A GLSL shader minifier that accepts [`GLSLConfig`].
+    MinifierGLSL: GLSLConfig
+);
+impl ConfigHolder<MinifierGLSL> {
+    pub(super) fn minify(&self, b: &[u8], vout: &mut Vec<u8>) -> Result_ {
+        let mut toks = tokenize(b)?;
+        if self.rename_locals {
+            rename_locals(&mut toks);
+        }
+        write_tokens(&toks, vout);
+        Ok(())
+    }
+}
+
+/// Configuration for GLSL minifier
+#[derive(Default)]
+#[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize))]
+pub struct GLSLConfig {
+    /// An optional flag that enables shortening names of local variables declared inside function bodies.
+    /// Names used by directives or outside of function bodies are never changed.
+    /// Defaults to `false`, because macros from imported files may refer to local variables.
+    pub rename_locals: bool
+}
+
+/// An error that occurs when a shader contains an unterminated block comment
+#[derive(Debug)]
+pub struct GLSLError;
+impl std::error::Error for GLSLError {}
+impl std::fmt::Display for GLSLError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("unterminated block comment")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind { Directive, Word, Number, Punct }
+
+struct Tok<'a> {
+    kind: Kind,
+    text: Cow<'a, [u8]>,
+    /// Whether the token was separated from the previous one in the source
+    spaced: bool
+}
+impl Tok<'_> {
+    #[inline]
+    fn is(&self, kind: Kind, text: &[u8]) -> bool {
+        self.kind == kind && *self.text == *text
+    }
+}
+
+fn tokenize(v: &[u8]) -> crate::Result_<Vec<Tok<'_>>> {
+    let mut toks = Vec::new();
+    let mut i = 0;
+    let mut line_start = true;
+    let mut spaced = false;
+    while let Some(&b) = v.get(i) {
+        let next = v.get(i + 1).copied();
+        let (kind, j) = match b {
+            b'\n' => {
+                line_start = true;
+                spaced = true;
+                i += 1;
+                continue;
+            }
+            _ if b.is_ascii_whitespace() => {
+                spaced = true;
+                i += 1;
+                continue;
+            }
+            b'/' if next == Some(b'/') => {
+                spaced = true;
+                i = line_end(v, i);
+                continue;
+            }
+            b'/' if next == Some(b'*') => {
+                let j = comment_end(v, i)?;
+                line_start |= v[i..j].contains(&b'\n');
+                spaced = true;
+                i = j;
+                continue;
+            }
+            b'#' if line_start => {
+                let (text, j) = directive(v, i)?;
+                toks.push(Tok { kind: Kind::Directive, text: Cow::Owned(text), spaced: true });
+                i = j;
+                spaced = true;
+                continue;
+            }
+            b'0'..=b'9' => (Kind::Number, number_end(v, i)),
+            b'.' if next.is_some_and(|n| n.is_ascii_digit()) => (Kind::Number, number_end(v, i)),
+            _ if is_word(b) => (Kind::Word, v[i..].iter().position(|&c| !is_word(c)).map_or(v.len(), |p| i + p)),
+            b'+' | b'-' | b'&' | b'|' | b'^' if next == Some(b) => (Kind::Punct, i + 2),
+            b'<' | b'>' if next == Some(b) => (Kind::Punct, if v.get(i + 2) == Some(&b'=') { i + 3 } else { i + 2 }),
+            b'+' | b'-' | b'*' | b'/' | b'%' | b'<' | b'>' | b'=' | b'!' | b'&' | b'|' | b'^' if next == Some(b'=') => (Kind::Punct, i + 2),
+            _ => (Kind::Punct, i + 1)
+        };
+        toks.push(Tok { kind, text: Cow::Borrowed(&v[i..j]), spaced });
+        line_start = false;
+        spaced = false;
+        i = j;
+    }
+    Ok(toks)
+}
+
+/// Reads a preprocessor directive without comments. Line continuations are kept.
+fn directive(v: &[u8], mut i: usize) -> crate::Result_<(Vec<u8>, usize)> {
+    let mut text = Vec::new();
+    while let Some(&b) = v.get(i) {
+        match b {
+            b'\n' => break,
+            b'\\' if v.get(i + 1) == Some(&b'\n') || v[i + 1..].starts_with(b"\r\n") => {
+                let j = line_end(v, i) + 1;
+                text.extend_from_slice(&v[i..j]);
+                i = j;
+            }
+            b'/' if v.get(i + 1) == Some(&b'/') => {
+                i = line_end(v, i);
+            }
+            b'/' if v.get(i + 1) == Some(&b'*') => {
+                i = comment_end(v, i)?;
+                text.push(b' ');
+            }
+            b'"' => {
+                let j = match v[i + 1..].iter().position(|&c| c == b'"' || c == b'\n') {
+                    Some(p) if v[i + 1 + p] == b'"' => i + p + 2,
+                    Some(p) => i + 1 + p,
+                    None => v.len()
+                };
+                text.extend_from_slice(&v[i..j]);
+                i = j;
+            }
+            _ => {
+                text.push(b);
+                i += 1;
+            }
+        }
+    }
+    let len = text.trim_ascii_end().len();
+    text.truncate(len);
+    Ok((text, i))
+}
+
+fn write_tokens(toks: &[Tok], vout: &mut Vec<u8>) {
+    let mut last: Option<&Tok> = None;
+    for t in toks {
+        if t.kind == Kind::Directive {
+            if !vout.is_empty() && !vout.ends_with(b"\n") {
+                vout.push(b'\n');
+            }
+            vout.extend_from_slice(&t.text);
+            vout.push(b'\n');
+            last = None;
+            continue;
+        }
+        if let Some(l) = last {
+            if t.spaced && needs_space(l, t) {
+                vout.push(b' ');
+            }
+        }
+        vout.extend_from_slice(&t.text);
+        last = Some(t);
+    }
+    if !vout.is_empty() && !vout.ends_with(b"\n") {
+        vout.push(b'\n');
+    }
+}
+
+fn needs_space(l: &Tok, t: &Tok) -> bool {
+    let (Some(&a), Some(&b)) = (l.text.last(), t.text.first()) else {
+        return false;
+    };
+    (is_word(a) && is_word(b))
+        || (l.kind == Kind::Number && b == b'.')
+        || matches!((a, b), (b'+', b'+') | (b'-', b'-') | (b'/', b'/' | b'*'))
+}
+
+#[inline]
+const fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+fn line_end(v: &[u8], i: usize) -> usize {
+    v[i..].iter().position(|&b| b == b'\n').map_or(v.len(), |p| i + p)
+}
+
+fn comment_end(v: &[u8], i: usize) -> crate::Result_<usize> {
+    Ok(v[i + 2..].windows(2).position(|w| w == b"*/").map(|p| i + p + 4).ok_or(GLSLError)?)
+}
+
+fn number_end(v: &[u8], i: usize) -> usize {
+    let hex = v[i..].starts_with(b"0x") || v[i..].starts_with(b"0X");
+    let mut j = i + 1;
+    while let Some(&b) = v.get(j) {
+        let exp_sign = matches!(b, b'+' | b'-') && !hex && matches!(v[j - 1], b'e' | b'E');
+        if !(is_word(b) || b == b'.' || exp_sign) {
+            break;
+        }
+        j += 1;
+    }
+    j
+}
+
+/// Checks if a word is a non-opaque built-in type (only variables of these types are renamed).
+const fn is_value_type(w: &[u8]) -> bool {
+    matches!(
+        w,
+        b"float" | b"double" | b"int" | b"uint" | b"bool"
+        | [b'v', b'e', b'c', b'2'..=b'4']
+        | [b'i' | b'u' | b'b' | b'd', b'v', b'e', b'c', b'2'..=b'4']
+        | [b'm', b'a', b't', b'2'..=b'4']
+        | [b'm', b'a', b't', b'2'..=b'4', b'x', b'2'..=b'4']
+    )
+}
+
+/// Returns token ranges of function bodies (without braces).
+fn function_bodies(toks: &[Tok]) -> Vec<(usize, usize)> {
+    let mut bodies = Vec::new();
+    let mut depth = 0usize;
+    let mut start = None;
+    let mut prev: Option<&Tok> = None;
+    for (i, t) in toks.iter().enumerate() {
+        if t.kind == Kind::Directive {
+            continue;
+        }
+        if t.is(Kind::Punct, b"{") {
+            if depth == 0 && prev.is_some_and(|p| p.is(Kind::Punct, b")")) {
+                start = Some(i + 1);
+            }
+            depth += 1;
+        } else if t.is(Kind::Punct, b"}") {
+            depth = depth.saturating_sub(1);
+            if depth == 0 {
+                if let Some(s) = start.take() {
+                    bodies.push((s, i));
+                }
+            }
+        }
+        prev = Some(t);
+    }
+    bodies
+}
+
+/// Finds names declared as local variables (`vec3 a = ..., b;`) in a token range.
+fn declared_locals<'a>(toks: &'a [Tok]) -> Vec<&'a [u8]> {
+    let sig: Vec<&Tok> = toks.iter().filter(|t| t.kind != Kind::Directive).collect();
+    let is_declarator = |i: usize| {
+        sig.get(i).is_some_and(|t| t.kind == Kind::Word && !is_value_type(&t.text))
+            && sig.get(i + 1).is_some_and(|t| matches!(&*t.text, b"=" | b";" | b"," | b"["))
+    };
+    let mut names = Vec::new();
+    let mut i = 0;
+    while i < sig.len() {
+        let t = sig[i];
+        let after_dot = i > 0 && sig[i - 1].is(Kind::Punct, b".");
+        if t.kind != Kind::Word || !is_value_type(&t.text) || after_dot || !is_declarator(i + 1) {
+            i += 1;
+            continue;
+        }
+        names.push(&*sig[i + 1].text);
+        let mut depth = 0isize;
+        i += 2;
+        while let Some(t) = sig.get(i) {
+            match &*t.text {
+                b";" if depth == 0 => break,
+                b"(" | b"[" | b"{" => depth += 1,
+                b")" | b"]" | b"}" => {
+                    depth -= 1;
+                    if depth < 0 {
+                        break;
+                    }
+                }
+                b"," if depth == 0 && is_declarator(i + 1) => {
+                    names.push(&*sig[i + 1].text);
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+    names
+}
+
+fn short_name(mut n: usize) -> Vec<u8> {
+    const ALPHA: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let mut name = vec![ALPHA[n % ALPHA.len()]];
+    n /= ALPHA.len();
+    while n > 0 {
+        n -= 1;
+        name.push(ALPHA[n % ALPHA.len()]);
+        n /= ALPHA.len();
+    }
+    name
+}
+
+fn rename_locals(toks: &mut [Tok]) {
+    let bodies = function_bodies(toks);
+    // Names that must stay untouched: used in directives or outside of function bodies
+    let mut fixed: HashSet<Vec<u8>> = HashSet::new();
+    let mut used: HashSet<Vec<u8>> = HashSet::new();
+    let mut bi = bodies.iter().peekable();
+    for (i, t) in toks.iter().enumerate() {
+        while bi.peek().is_some_and(|&&(_, e)| e < i) {
+            bi.next();
+        }
+        let in_body = bi.peek().is_some_and(|&&(s, e)| s <= i && i < e);
+        match t.kind {
+            Kind::Directive => {
+                for w in t.text.split(|&b| !is_word(b)).filter(|w| !w.is_empty()) {
+                    fixed.insert(w.to_vec());
+                    used.insert(w.to_vec());
+                }
+            }
+            Kind::Word => {
+                if !in_body {
+                    fixed.insert(t.text.to_vec());
+                }
+                used.insert(t.text.to_vec());
+            }
+            _ => {}
+        }
+    }
+    for (s, e) in bodies {
+        let body = &toks[s..e];
+        let mut map: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut n = 0;
+        for name in declared_locals(body) {
+            if map.contains_key(name) || fixed.contains(name) || name.starts_with(b"gl_") {
+                continue;
+            }
+            let called = body.windows(2).any(|w| *w[0].text == *name && w[1].is(Kind::Punct, b"("));
+            if called {
+                continue;
+            }
+            let short = loop {
+                let c = short_name(n);
+                n += 1;
+                if !used.contains(&c) && !matches!(&*c, b"do" | b"if" | b"in") {
+                    break c;
+                }
+            };
+            if short.len() < name.len() {
+                map.insert(name.to_vec(), short);
+            }
+        }
+        if map.is_empty() {
+            continue;
+        }
+        let mut after_dot = false;
+        for t in &mut toks[s..e] {
+            if t.kind == Kind::Word && !after_dot {
+                if let Some(short) = map.get(&*t.text) {
+                    t.text = Cow::Owned(short.clone());
+                }
+            }
+            if t.kind != Kind::Directive {
+                after_dot = t.is(Kind::Punct, b".");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rename_locals, tokenize, write_tokens};
+
+    fn minify(s: &str, rename: bool) -> String {
+        let mut toks = tokenize(s.as_bytes()).unwrap();
+        if rename {
+            rename_locals(&mut toks);
+        }
+        let mut v = Vec::new();
+        write_tokens(&toks, &mut v);
+        String::from_utf8(v).unwrap()
+    }
+
+    #[test]
+    fn directives_are_kept() {
+        let s = "#version 150 // core\n#moj_import <fog.glsl>\n  # define SCALE 2.0 /* x */\nuniform float a; void main() { a = a * SCALE; }\n";
+        assert_eq!(minify(s, false), "#version 150\n#moj_import <fog.glsl>\n# define SCALE 2.0\nuniform float a;void main(){a=a*SCALE;}\n");
+    }
+
+    #[test]
+    fn line_continuations() {
+        let s = "#define MUL(a, b) \\\n    ((a) * (b))\nint x = MUL(1, 2);\n";
+        assert_eq!(minify(s, false), "#define MUL(a, b) \\\n    ((a) * (b))\nint x=MUL(1,2);\n");
+    }
+
+    #[test]
+    fn operators_keep_spaces() {
+        assert_eq!(minify("x = a - -b + +c; y = 1. .5;", false), "x=a- -b+ +c;y=1. .5;\n");
+    }
+
+    #[test]
+    fn locals_are_renamed() {
+        let s = "#define OFFSET offset\nuniform vec2 size;\nvoid main() {\n  vec3 color = vec3(1.0), other;\n  float offset = 2.0;\n  other = color.xyz * size.x + OFFSET;\n}\n";
+        assert_eq!(
+            minify(s, true),
+            "#define OFFSET offset\nuniform vec2 size;void main(){vec3 a=vec3(1.0),b;float offset=2.0;b=a.xyz*size.x+OFFSET;}\n"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use super::Result_;
+
+/// A dialect of files with hash (`#`) comments. Each dialect has its own rules for detecting real comments.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HashFmt {
+    /// Legacy Forge configuration (`.cfg`).
+    /// Values after `=` and entries of `<` `>` lists are kept intact, even if they contain `#`.
+    ForgeCfg,
+    /// Wavefront geometry and material files (`.obj`, `.mtl`).
+    /// A comment starts at any `#` and whitespace in data statements is collapsed.
+    Wavefront
+}
+impl HashFmt {
+    pub(super) fn minify(self, v: &[u8], vout: &mut Vec<u8>) -> Result_ {
+        match self {
+            Self::ForgeCfg => minify_cfg(v, vout),
+            Self::Wavefront => minify_wavefront(v, vout)
+        }
+        Ok(())
+    }
+}
+
+fn lines(v: &[u8]) -> impl Iterator<Item = &[u8]> {
+    v.split_inclusive(|&b| b == b'\n').map(|l| {
+        let l = l.strip_suffix(b"\n").unwrap_or(l);
+        l.strip_suffix(b"\r").unwrap_or(l)
+    })
+}
+
+#[inline]
+fn push_line(vout: &mut Vec<u8>, l: &[u8]) {
+    vout.extend_from_slice(l);
+    vout.push(b'\n');
+}
+
+fn minify_cfg(v: &[u8], vout: &mut Vec<u8>) {
+    let mut in_list = false;
+    for l in lines(v) {
+        let l = l.trim_ascii_start();
+        if in_list {
+            // Every line inside a list (including empty ones) is an entry
+            if l.first() != Some(&b'>') {
+                push_line(vout, l.trim_ascii_end());
+                continue;
+            }
+            in_list = false;
+        }
+        let (l, is_value) = cfg_line(l);
+        if l.is_empty() {
+            continue;
+        }
+        in_list = !is_value && l.ends_with(b"<");
+        push_line(vout, l);
+    }
+}
+
+/// Returns a line without a comment and a flag if the line contains a value.
+/// Quoted names may contain escaped quotes (`\"`).
+fn cfg_line(l: &[u8]) -> (&[u8], bool) {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, &b) in l.iter().enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match b {
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            b'=' if !quoted => return (l, true),
+            b'#' if !quoted => return (l[..i].trim_ascii_end(), false),
+            _ => {}
+        }
+    }
+    (l.trim_ascii_end(), false)
+}
+
+fn minify_wavefront(v: &[u8], vout: &mut Vec<u8>) {
+    let mut cont = false;
+    for l in lines(v) {
+        let l = l.iter().position(|&b| b == b'#').map_or(l, |i| &l[..i]);
+        if cont {
+            // A continued line is always kept, because dropping it would join the next statement
+            let l = l.trim_ascii_end();
+            cont = l.ends_with(b"\\");
+            push_line(vout, l);
+            continue;
+        }
+        let l = l.trim_ascii();
+        if l.is_empty() {
+            continue;
+        }
+        cont = l.ends_with(b"\\");
+        if is_wavefront_data(l) {
+            let mut tokens = l.split(u8::is_ascii_whitespace).filter(|t| !t.is_empty());
+            if let Some(t) = tokens.next() {
+                vout.extend_from_slice(t);
+            }
+            for t in tokens {
+                vout.push(b' ');
+                vout.extend_from_slice(t);
+            }
+            vout.push(b'\n');
+        } else {
+            push_line(vout, l);
+        }
+    }
+}
+
+/// Checks if a statement contains only numbers or indices (file names may contain significant whitespace).
+fn is_wavefront_data(l: &[u8]) -> bool {
+    let kw = l.split(u8::is_ascii_whitespace).next().unwrap_or_default();
+    matches!(
+        kw,
+        b"v" | b"vt" | b"vn" | b"vp" | b"f" | b"l" | b"p"
+        | b"Ka" | b"Kd" | b"Ks" | b"Ke" | b"Ns" | b"Ni" | b"d" | b"Tr" | b"Tf" | b"illum"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashFmt;
+
+    fn minify(f: HashFmt, s: &str) -> String {
+        let mut v = Vec::new();
+        f.minify(s.as_bytes(), &mut v).unwrap();
+        String::from_utf8(v).unwrap()
+    }
+
+    #[test]
+    fn cfg_values_keep_hashes() {
+        let s = "# General settings\ngeneral {\n    S:color=#FF0000 # not a comment\n    I:size=4\n}\n";
+        assert_eq!(minify(HashFmt::ForgeCfg, s), "general {\nS:color=#FF0000 # not a comment\nI:size=4\n}\n");
+    }
+
+    #[test]
+    fn cfg_quoted_hash_is_kept() {
+        let s = "\"a#b\" { # comment\n    S:\"key#1\"=x\n}\n";
+        assert_eq!(minify(HashFmt::ForgeCfg, s), "\"a#b\" {\nS:\"key#1\"=x\n}\n");
+    }
+
+    #[test]
+    fn cfg_escaped_quotes() {
+        let s = "\"a\\\"b#c\" { # comment\n    S:\"a\\\"b#c\"=1\n    S:\"d\\\\\"=2 # end\n}\n";
+        assert_eq!(minify(HashFmt::ForgeCfg, s), "\"a\\\"b#c\" {\nS:\"a\\\"b#c\"=1\nS:\"d\\\\\"=2 # end\n}\n");
+    }
+
+    #[test]
+    fn cfg_list_entries_are_kept() {
+        let s = "S:items <\n    #minecraft:logs\n\n    minecraft:stone\n >\n# end\n";
+        assert_eq!(minify(HashFmt::ForgeCfg, s), "S:items <\n#minecraft:logs\n\nminecraft:stone\n>\n");
+    }
+
+    #[test]
+    fn wavefront_comments_and_whitespace() {
+        let s = "# Blender\r\nmtllib my model.mtl\nv  1.0   2.0 3.0 # vertex\nf 1/1 2/2 \\\n  3/3\n";
+        assert_eq!(minify(HashFmt::Wavefront, s), "mtllib my model.mtl\nv 1.0 2.0 3.0\nf 1/1 2/2 \\\n  3/3\n");
+    }
+}
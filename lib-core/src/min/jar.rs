@@ -9,8 +9,14 @@ use zip::{
 
 use super::Result_;
 use crate::{
-    cfg::{ConfigHolder, acfg},
-    entry::zip::compress_check,
+    cfg::{ConfigHolder, ConfigMap, acfg},
+    entry::{
+        Batch, EntryReader, ZipEntryReader, ZipEntrySaver, process_entries_nested, read_entry,
+        zip::{EntryMtime, compress_check},
+    },
+    errors::ErrorCollector,
+    fop::Rules,
+    sign::SignedJarPolicy,
 };
 
 acfg!(
@@ -18,13 +24,21 @@ acfg!(
     MinifierJAR: JARConfig
 );
 
+acfg!(
+    /// A modification time of entries in nested archives. It should be the same as in the parent archive.
+    NestedMtime: EntryMtime
+);
+
 impl ConfigHolder<MinifierJAR> {
-    pub(super) fn minify(&self, b: &[u8], vout: &mut Vec<u8>) -> Result_ {
+    pub(super) fn minify(&self, b: &[u8], vout: &mut Vec<u8>, mtime: EntryMtime) -> Result_ {
         let mut zread = ZipArchive::new(Cursor::new(b))?;
-        let stored: SimpleFileOptions =
-            FileOptions::default().compression_method(CompressionMethod::Stored);
+        let mtime = mtime.dos_time();
+        let stored: SimpleFileOptions = FileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .last_modified_time(mtime);
         let deflated: SimpleFileOptions = FileOptions::default()
             .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(mtime)
             .compression_level(Some(self.compress_level()));
         let mut zwrite = ZipWriter::new(Cursor::new(vout));
         let mut v = Vec::new();
@@ -55,17 +69,75 @@ impl ConfigHolder<MinifierJAR> {
     }
 }
 
+/// Repacks a nested archive entry `name` with the same pipeline (and the same limits) as its parent archive.
+/// Entries are read one by one, so only a batch of them is held in memory at once.
+/// Errors of nested entries are collected with paths like `inner.jar!/assets/x.json`.
+/// If the depth limit is reached, the archive is only recompressed.
+pub(crate) fn repack_nested(
+    cfgmap: &ConfigMap,
+    b: &[u8],
+    vout: &mut Vec<u8>,
+    ev: &mut ErrorCollector,
+    rules: &Rules,
+    name: &str,
+    limits: Batch,
+) -> Result_ {
+    let jc = cfgmap.fetch::<MinifierJAR>();
+    let mtime = *cfgmap.fetch::<NestedMtime>();
+    if limits.depth >= jc.max_depth {
+        return jc.minify(b, vout, mtime);
+    }
+    let mut zread = ZipEntryReader::new_mem(b)?;
+    if !ev.nested(name, |ev| jc.signed.apply(&mut zread, ev))? {
+        vout.extend_from_slice(b);
+        return Ok(());
+    }
+    zread.keep_compressed();
+    let mut zsave = ZipEntrySaver::custom_compress(Cursor::new(vout), jc.keep_dirs, jc.compress_level())
+        .with_manifest(zread.has_manifest());
+    if let EntryMtime::Fixed(epoch) = mtime {
+        zsave = zsave.with_mtime(epoch);
+    }
+    let mut read_err = Ok(());
+    let entries = zread
+        .read_iter()
+        .map_while(|re| read_entry::<ZipEntryReader<_>>(re, rules).map_err(|e| read_err = Err(e)).ok())
+        .flatten();
+    let limits = Batch { depth: limits.depth + 1, ..limits };
+    ev.nested(name, |ev| process_entries_nested(&mut zsave, entries, ev, cfgmap, rules, |_| Ok(()), limits))?;
+    read_err?;
+    zsave.finish()?;
+    Ok(())
+}
+
 /// Configuration for JAR repacker
-#[derive(Default)]
-#[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct JARConfig {
     /// Keep directories in the archive
     pub keep_dirs: bool,
 
+    /// Maximum depth of nested archives processed entry by entry.
+    /// Archives nested deeper are only recompressed.
+    pub max_depth: u8,
+
+    /// A policy for signed archives
+    pub signed: SignedJarPolicy,
+
     #[cfg(feature = "zip-zopfli")]
     /// Enables Zopfli compression (better, but slower)
     pub use_zopfli: crate::cfg::CfgZopfli,
 }
+impl Default for JARConfig {
+    fn default() -> Self {
+        Self {
+            keep_dirs: false,
+            max_depth: 2,
+            signed: SignedJarPolicy::default(),
+            #[cfg(feature = "zip-zopfli")]
+            use_zopfli: crate::cfg::CfgZopfli::default(),
+        }
+    }
+}
 impl JARConfig {
     #[inline]
     #[cfg(feature = "zip-zopfli")]
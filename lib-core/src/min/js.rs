@@ -0,0 +1,406 @@
+use crate::cfg::{acfg, ConfigHolder};
+
+use super::Result_;
+
+acfg!(
+    /// A JavaScript and ZenScript minifier that accepts [`JSConfig`].
+    MinifierJS: JSConfig
+);
+impl ConfigHolder<MinifierJS> {
+    pub(super) fn minify_js(&self, b: &[u8], vout: &mut Vec<u8>) -> Result_ {
+        Minify::new(Lang::JS, self.keep_license, vout).run(b)
+    }
+    pub(super) fn minify_zs(&self, b: &[u8], vout: &mut Vec<u8>) -> Result_ {
+        Minify::new(Lang::ZS, self.keep_license, vout).run(b)
+    }
+}
+
+/// Configuration for JavaScript and ZenScript minifier
+#[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize))]
+pub struct JSConfig {
+    /// An optional flag that keeps license comments (`/*! ... */` or block comments with `@license` or `@preserve`).
+    /// Defaults to `true`.
+    pub keep_license: bool
+}
+impl Default for JSConfig {
+    fn default() -> Self {
+        Self { keep_license: true }
+    }
+}
+
+/// An error that occurs when a script contains an unterminated token
+#[derive(Debug)]
+pub enum JSError {
+    /// A string literal is not closed
+    UnterminatedString,
+    /// A block comment is not closed
+    UnterminatedComment,
+    /// A template literal is not closed
+    UnterminatedTemplate,
+    /// A regular expression literal is not closed
+    UnterminatedRegex
+}
+impl std::error::Error for JSError {}
+impl std::fmt::Display for JSError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::UnterminatedString => "unterminated string literal",
+            Self::UnterminatedComment => "unterminated block comment",
+            Self::UnterminatedTemplate => "unterminated template literal",
+            Self::UnterminatedRegex => "unterminated regular expression",
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Lang { JS, ZS }
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tok { Word, Number, Str, Regex, Punct, TemplateOpen, Template }
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Ws { None, Space, Newline }
+
+const REGEX_KEYWORDS: &[&[u8]] = &[
+    b"return", b"typeof", b"instanceof", b"in", b"of", b"new", b"delete",
+    b"void", b"throw", b"case", b"do", b"else", b"yield", b"await"
+];
+
+struct Minify<'a> {
+    lang: Lang,
+    keep_license: bool,
+    out: &'a mut Vec<u8>,
+    last: Option<(Tok, usize, usize)>,
+    ws: Ws,
+    /// Brace depths of open template substitutions (`${`)
+    tpl: Vec<u32>,
+}
+impl<'a> Minify<'a> {
+    const fn new(lang: Lang, keep_license: bool, out: &'a mut Vec<u8>) -> Self {
+        Self { lang, keep_license, out, last: None, ws: Ws::None, tpl: Vec::new() }
+    }
+
+    fn run(mut self, v: &[u8]) -> Result_ {
+        let mut i = 0;
+        if self.lang == Lang::JS && v.starts_with(b"#!") {
+            i = line_end(v, 0);
+            self.out.extend_from_slice(&v[..i]);
+            self.out.push(b'\n');
+        }
+        while let Some(&b) = v.get(i) {
+            let next = v.get(i + 1).copied();
+            i = match b {
+                b'\n' => { self.space(Ws::Newline); i + 1 }
+                _ if b.is_ascii_whitespace() => { self.space(Ws::Space); i + 1 }
+                b'/' if next == Some(b'/') => { self.space(Ws::Space); line_end(v, i) }
+                b'/' if next == Some(b'*') => self.block_comment(v, i)?,
+                b'#' if self.lang == Lang::ZS => self.zs_hash(v, i),
+                b'/' if self.lang == Lang::JS && self.regex_allowed() => {
+                    let j = regex_end(v, i)?;
+                    self.emit(Tok::Regex, &v[i..j]);
+                    j
+                }
+                b'\'' | b'"' => {
+                    let j = string_end(v, i)?;
+                    self.emit(Tok::Str, &v[i..j]);
+                    j
+                }
+                b'`' if self.lang == Lang::JS => self.template(v, i)?,
+                b'}' if self.tpl.last() == Some(&0) => {
+                    self.tpl.pop();
+                    self.template(v, i)?
+                }
+                b'{' | b'}' => {
+                    if let Some(d) = self.tpl.last_mut() {
+                        if b == b'{' { *d += 1 } else { *d -= 1 }
+                    }
+                    self.emit(Tok::Punct, &v[i..=i]);
+                    i + 1
+                }
+                b'0'..=b'9' => self.number(v, i),
+                b'.' if next.is_some_and(|n| n.is_ascii_digit()) => self.number(v, i),
+                b'<' if self.lang == Lang::ZS => {
+                    let j = bracket_handler_end(v, i);
+                    if j > i {
+                        self.emit(Tok::Word, &v[i..j]);
+                        j
+                    } else {
+                        self.emit(Tok::Punct, b"<");
+                        i + 1
+                    }
+                }
+                b'+' | b'-' if next == Some(b) => {
+                    self.emit(Tok::Punct, &v[i..i + 2]);
+                    i + 2
+                }
+                _ if self.is_word(b) => {
+                    let j = v[i..].iter().position(|&c| !self.is_word(c)).map_or(v.len(), |p| i + p);
+                    self.emit(Tok::Word, &v[i..j]);
+                    j
+                }
+                _ => {
+                    self.emit(Tok::Punct, &v[i..=i]);
+                    i + 1
+                }
+            };
+        }
+        if !self.tpl.is_empty() {
+            return Err(JSError::UnterminatedTemplate.into());
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn is_word(&self, b: u8) -> bool {
+        is_word(b) || (self.lang == Lang::JS && b == b'#')
+    }
+
+    #[inline]
+    fn space(&mut self, ws: Ws) {
+        self.ws = self.ws.max(ws);
+    }
+
+    fn last_text(&self) -> &[u8] {
+        self.last.map_or(&[], |(_, start, end)| &self.out[start..end])
+    }
+
+    fn emit(&mut self, tok: Tok, text: &[u8]) {
+        if let Some((last, ..)) = self.last {
+            let lt = self.last_text();
+            if self.ws == Ws::Newline && self.lang == Lang::JS && needs_newline(last, lt, text) {
+                self.out.push(b'\n');
+            } else if self.ws != Ws::None && needs_space(last, lt, tok, text) {
+                self.out.push(b' ');
+            }
+        } else if self.ws == Ws::Newline && !self.out.is_empty() && !self.out.ends_with(b"\n") {
+            self.out.push(b'\n');
+        }
+        self.ws = Ws::None;
+        let start = self.out.len();
+        self.out.extend_from_slice(text);
+        self.last = Some((tok, start, self.out.len()));
+    }
+
+    fn regex_allowed(&self) -> bool {
+        match self.last {
+            None => true,
+            Some((Tok::Punct, ..)) => !matches!(self.last_text(), b")" | b"]" | b"++" | b"--"),
+            Some((Tok::Word, ..)) => REGEX_KEYWORDS.contains(&self.last_text()),
+            Some((Tok::TemplateOpen, ..)) => true,
+            Some(_) => false
+        }
+    }
+
+    fn block_comment(&mut self, v: &[u8], i: usize) -> crate::Result_<usize> {
+        let j = v[i + 2..].windows(2).position(|w| w == b"*/")
+            .map(|p| i + p + 4)
+            .ok_or(JSError::UnterminatedComment)?;
+        let c = &v[i..j];
+        if self.keep_license && is_license(c) {
+            // A kept comment does not replace the last token, so the spacing rules still apply to real tokens
+            match self.ws {
+                Ws::Newline => self.out.push(b'\n'),
+                Ws::Space => self.out.push(b' '),
+                Ws::None => {}
+            }
+            self.ws = Ws::None;
+            self.out.extend_from_slice(c);
+        }
+        if c.contains(&b'\n') {
+            // A multi-line comment is a line terminator
+            self.space(Ws::Newline);
+        } else {
+            self.space(Ws::Space);
+        }
+        Ok(j)
+    }
+
+    /// ZenScript uses `#` for both comments and preprocessors (like `#priority 10`). Preprocessors are kept in their own lines.
+    /// Addons can add their own preprocessors (like `#packmode`), so every line starting with `#` and a name is kept.
+    fn zs_hash(&mut self, v: &[u8], i: usize) -> usize {
+        let j = line_end(v, i);
+        let line_start = v[..i].iter().rposition(|&b| b == b'\n').map_or(0, |p| p + 1);
+        let is_preproc = v[line_start..i].iter().all(u8::is_ascii_whitespace) && v.get(i + 1).is_some_and(u8::is_ascii_alphabetic);
+        if is_preproc {
+            if !self.out.is_empty() && !self.out.ends_with(b"\n") {
+                self.out.push(b'\n');
+            }
+            self.out.extend_from_slice(v[i..j].trim_ascii_end());
+            self.out.push(b'\n');
+            self.ws = Ws::None;
+            self.last = None;
+        } else {
+            self.space(Ws::Space);
+        }
+        j
+    }
+
+    fn template(&mut self, v: &[u8], i: usize) -> crate::Result_<usize> {
+        let mut j = i + 1;
+        loop {
+            match v.get(j) {
+                None => return Err(JSError::UnterminatedTemplate.into()),
+                Some(b'\\') => j += 2,
+                Some(b'`') => {
+                    self.emit(Tok::Template, &v[i..=j]);
+                    return Ok(j + 1);
+                }
+                Some(b'$') if v.get(j + 1) == Some(&b'{') => {
+                    self.tpl.push(0);
+                    self.emit(Tok::TemplateOpen, &v[i..j + 2]);
+                    return Ok(j + 2);
+                }
+                Some(_) => j += 1
+            }
+        }
+    }
+
+    fn number(&mut self, v: &[u8], i: usize) -> usize {
+        let hex = v[i..].starts_with(b"0x") || v[i..].starts_with(b"0X");
+        let mut j = i + 1;
+        while let Some(&b) = v.get(j) {
+            let exp_sign = matches!(b, b'+' | b'-') && !hex && matches!(v[j - 1], b'e' | b'E');
+            if !(b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || exp_sign) {
+                break;
+            }
+            j += 1;
+        }
+        self.emit(Tok::Number, &v[i..j]);
+        j
+    }
+}
+
+#[inline]
+const fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'_' | b'$' | b'\\') || b >= 0x80
+}
+
+fn is_license(c: &[u8]) -> bool {
+    c.starts_with(b"/*!")
+        || c.windows(8).any(|w| w == b"@license")
+        || c.windows(9).any(|w| w == b"@preserve")
+}
+
+fn line_end(v: &[u8], i: usize) -> usize {
+    v[i..].iter().position(|&b| b == b'\n').map_or(v.len(), |p| i + p)
+}
+
+fn string_end(v: &[u8], i: usize) -> crate::Result_<usize> {
+    let q = v[i];
+    let mut j = i + 1;
+    loop {
+        match v.get(j) {
+            None | Some(b'\n') => return Err(JSError::UnterminatedString.into()),
+            Some(b'\\') => j += 2,
+            Some(&b) if b == q => return Ok(j + 1),
+            Some(_) => j += 1
+        }
+    }
+}
+
+fn regex_end(v: &[u8], i: usize) -> crate::Result_<usize> {
+    let mut j = i + 1;
+    let mut class = false;
+    loop {
+        match v.get(j) {
+            None | Some(b'\n') => return Err(JSError::UnterminatedRegex.into()),
+            Some(b'\\') => j += 2,
+            Some(b'[') => { class = true; j += 1; }
+            Some(b']') => { class = false; j += 1; }
+            Some(b'/') if !class => break,
+            Some(_) => j += 1
+        }
+    }
+    j += 1;
+    while v.get(j).is_some_and(|&b| is_word(b)) {
+        j += 1;
+    }
+    Ok(j)
+}
+
+/// Finds the end of a ZenScript bracket handler (like `<item:minecraft:stone>`). Returns `i` if there is none.
+fn bracket_handler_end(v: &[u8], i: usize) -> usize {
+    let mut colon = false;
+    for (p, &b) in v[i + 1..].iter().enumerate() {
+        match b {
+            b'>' if colon && p > 0 => return i + p + 2,
+            b':' => colon = true,
+            _ if b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'-' | b'/' | b'*' | b'@' | b'#' | b'=') => {}
+            _ => break
+        }
+    }
+    i
+}
+
+/// Checks if a line break between two tokens can change the meaning of a script (automatic semicolon insertion).
+fn needs_newline(last: Tok, lt: &[u8], next: &[u8]) -> bool {
+    let ends_stmt = match last {
+        Tok::Punct => matches!(lt, b")" | b"]" | b"}" | b"++" | b"--"),
+        Tok::TemplateOpen => false,
+        _ => true
+    };
+    ends_stmt && !matches!(next.first(), Some(b')' | b']' | b'}' | b',' | b';' | b':' | b'?' | b'.' | b'=' | b'*' | b'%' | b'&' | b'|' | b'^' | b'<' | b'>'))
+}
+
+/// Checks if two tokens would be merged (or become a comment) without a space between them.
+fn needs_space(last: Tok, lt: &[u8], tok: Tok, next: &[u8]) -> bool {
+    let (Some(&a), Some(&b)) = (lt.last(), next.first()) else {
+        return false;
+    };
+    ((is_word(a) || last == Tok::Regex) && (is_word(b) || b == b'#'))
+        || (last == Tok::Number && b == b'.')
+        || (tok == Tok::Number && a == b'.')
+        || matches!((a, b), (b'+', b'+') | (b'-', b'-') | (b'/', b'/' | b'*') | (b'<', b'!') | (b'-', b'>'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lang, Minify};
+
+    fn minify(lang: Lang, s: &str) -> String {
+        let mut v = Vec::new();
+        Minify::new(lang, true, &mut v).run(s.as_bytes()).unwrap();
+        String::from_utf8(v).unwrap()
+    }
+
+    #[test]
+    fn asi_after_return() {
+        assert_eq!(minify(Lang::JS, "function f() {\n  return\n  x + 1;\n}\n"), "function f(){return\nx+1;}");
+    }
+
+    #[test]
+    fn asi_around_increments() {
+        assert_eq!(minify(Lang::JS, "a\n++b\nc++\nd\ne--\n--f"), "a\n++b\nc++\nd\ne--\n--f");
+        assert_eq!(minify(Lang::JS, "a + +b; c - -d; e++ + f"), "a+ +b;c- -d;e++ +f");
+    }
+
+    #[test]
+    fn regex_and_division() {
+        assert_eq!(minify(Lang::JS, "x = a / b / c;\ny = /a+ b[/]/g.test(s);\nreturn /x/"), "x=a/b/c;y=/a+ b[/]/g.test(s);return/x/");
+        assert_eq!(minify(Lang::JS, "z = (a) / 2 / (b)"), "z=(a)/2/(b)");
+    }
+
+    #[test]
+    fn template_literals() {
+        let s = "let t = `a ${ {b: 1}.b + `c ${ d } // no` } e`;";
+        assert_eq!(minify(Lang::JS, s), "let t=`a ${{b:1}.b+`c ${d} // no`} e`;");
+    }
+
+    #[test]
+    fn license_comments() {
+        let s = "/*! MIT License */\n/* internal */ var a = 1; /* @license Apache */\n// line\nvar b;";
+        assert_eq!(minify(Lang::JS, s), "/*! MIT License */\nvar a=1; /* @license Apache */var b;");
+    }
+
+    #[test]
+    fn zs_preprocessors_and_comments() {
+        let s = "#priority 10\n#modloaded jei\n# a comment\n#\nval x = <item:minecraft:stone> * 2; # trailing\n";
+        assert_eq!(minify(Lang::ZS, s), "#priority 10\n#modloaded jei\nval x=<item:minecraft:stone>*2;");
+    }
+
+    #[test]
+    fn zs_addon_preprocessors() {
+        let s = "  #packmode expert   \n#ignoreBracketErrors\nval a = 1;\n#norun\n";
+        assert_eq!(minify(Lang::ZS, s), "#packmode expert\n#ignoreBracketErrors\nval a=1;\n#norun\n");
+    }
+}
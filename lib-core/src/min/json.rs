@@ -1,17 +1,44 @@
+use std::{borrow::Cow, collections::HashSet};
+
 use json_comments::StripComments;
 use serde_json::Value;
 
-use crate::cfg::{acfg, ConfigHolder};
+use crate::{cfg::{acfg, ConfigHolder}, errors::{MinifyWarning, VerifyError}, ext::KnownFmt};
 
-use super::{brackets, BracketsError, Result_};
+use super::{brackets, compare, BracketsError, Result_};
 
+/// A maximum depth of nested arrays and objects (the same as in `serde_json`).
+const MAX_DEPTH: u8 = 128;
 
 acfg!(
     /// A JSON minifier that accepts [`JSONConfig`].
     MinifierJSON: JSONConfig
 );
 impl ConfigHolder<MinifierJSON> {
+    /// Copies tokens (including number literals) as they are, removing only whitespace, comments and underscored keys.
+    /// Duplicate keys are kept, but the first one is returned as a warning.
     pub(super) fn minify(&self, b: &[u8], vout: &mut Vec<u8>) -> Result_ {
+        let sb = brackets(b).ok_or(BracketsError)?;
+        let start = sb.as_ptr().addr() - b.as_ptr().addr();
+        let mut st = JSONStream { b: &b[..start + sb.len()], i: start, vout, duplicate: None };
+        st.vout.reserve(sb.len());
+        st.value(0, true, self.remove_underscored)?;
+        st.skip_ws()?;
+        if st.i < st.b.len() {
+            return Err(JSONError::Unexpected(st.i).into());
+        }
+        if let Some(k) = st.duplicate {
+            return Err(MinifyWarning(JSONError::DuplicateKey(k).into()).into());
+        }
+        Ok(())
+    }
+
+    pub(super) fn verify(&self, b: &[u8], out: &[u8]) -> Result<(), VerifyError> {
+        compare(KnownFmt::Json, self.decode(b), serde_json::from_slice::<Value>(out))
+    }
+
+    /// Parses a whole file as a value (the minifier does not need it, but it is used for verification).
+    fn decode(&self, b: &[u8]) -> anyhow::Result<Value> {
         let b = brackets(b).ok_or(BracketsError)?;
         let mut sv: Value = serde_json::from_reader(StripComments::new(b))?;
         if self.remove_underscored {
@@ -19,8 +46,7 @@ impl ConfigHolder<MinifierJSON> {
                 uncomment_json_recursive(xm);
             }
         }
-        serde_json::to_writer(vout, &sv)?;
-        Ok(())
+        Ok(sv)
     }
 }
 
@@ -28,6 +54,7 @@ impl ConfigHolder<MinifierJSON> {
 #[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize))]
 pub struct JSONConfig {
     /// An optional flag that enables removing underscored keys.
+    /// Only keys in the root object (and objects nested directly in other objects) are removed.
     /// Defaults to `true`.
     pub remove_underscored: bool
 }
@@ -45,4 +72,300 @@ fn uncomment_json_recursive(m: &mut serde_json::Map<String, Value>) {
         }
         true
     });
-}
\ No newline at end of file
+}
+
+/// A streaming JSON tokenizer which writes minified tokens. Skipped values are still checked.
+struct JSONStream<'a, 'o> {
+    b: &'a [u8],
+    i: usize,
+    vout: &'o mut Vec<u8>,
+    /// The first duplicate key found
+    duplicate: Option<Box<str>>
+}
+impl<'a> JSONStream<'a, '_> {
+    /// Skips whitespace and comments (`//`, `/* */` and `#`).
+    fn skip_ws(&mut self) -> Result<(), JSONError> {
+        while let Some(&c) = self.b.get(self.i) {
+            match (c, self.b.get(self.i + 1)) {
+                (b' ' | b'\t' | b'\n' | b'\r', _) => self.i += 1,
+                (b'#', _) | (b'/', Some(b'/')) => {
+                    self.i = self.b[self.i..].iter().position(|&c| c == b'\n').map_or(self.b.len(), |n| self.i + n);
+                }
+                (b'/', Some(b'*')) => {
+                    let n = self.b[self.i + 2..].windows(2).position(|w| w == b"*/").ok_or(JSONError::Unexpected(self.b.len()))?;
+                    self.i += n + 4;
+                }
+                _ => break
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips whitespace and returns the next byte (without consuming it).
+    fn peek(&mut self) -> Result<u8, JSONError> {
+        self.skip_ws()?;
+        self.b.get(self.i).copied().ok_or(JSONError::Unexpected(self.i))
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), JSONError> {
+        if self.peek()? != c {
+            return Err(JSONError::Unexpected(self.i));
+        }
+        self.i += 1;
+        Ok(())
+    }
+
+    /// Checks a value and writes it if `emit` is `true`. Underscored keys are removed from objects if `strip` is `true`.
+    fn value(&mut self, depth: u8, emit: bool, strip: bool) -> Result<(), JSONError> {
+        let c = self.peek()?;
+        let start = self.i;
+        match c {
+            b'{' => return self.object(depth, emit, strip),
+            b'[' => return self.array(depth, emit),
+            b'"' => { self.string()?; }
+            b'-' | b'0'..=b'9' => self.number()?,
+            b't' => self.literal(b"true")?,
+            b'f' => self.literal(b"false")?,
+            b'n' => self.literal(b"null")?,
+            _ => return Err(JSONError::Unexpected(start))
+        }
+        if emit {
+            self.vout.extend_from_slice(&self.b[start..self.i]);
+        }
+        Ok(())
+    }
+
+    fn object(&mut self, depth: u8, emit: bool, strip: bool) -> Result<(), JSONError> {
+        let depth = depth.checked_add(1).filter(|&d| d <= MAX_DEPTH).ok_or(JSONError::TooDeep)?;
+        self.i += 1;
+        if emit {
+            self.vout.push(b'{');
+        }
+        let mut keys = HashSet::new();
+        let mut first = true;
+        if self.peek()? != b'}' {
+            loop {
+                if self.peek()? != b'"' {
+                    return Err(JSONError::Unexpected(self.i));
+                }
+                let key = self.string()?;
+                let emit_entry = emit && !(strip && is_underscored(key));
+                // Removed entries are not checked (underscored keys like `_comment` are often repeated)
+                if emit_entry {
+                    let k = unescape(key);
+                    if keys.contains(&k) {
+                        self.duplicate.get_or_insert_with(|| String::from_utf8_lossy(&k).into());
+                    } else {
+                        keys.insert(k);
+                    }
+                }
+                self.expect(b':')?;
+                if emit_entry {
+                    if !first {
+                        self.vout.push(b',');
+                    }
+                    first = false;
+                    self.vout.extend_from_slice(key);
+                    self.vout.push(b':');
+                }
+                self.value(depth, emit_entry, strip)?;
+                if !self.next_item(b'}')? {
+                    break;
+                }
+            }
+        }
+        self.i += 1;
+        if emit {
+            self.vout.push(b'}');
+        }
+        Ok(())
+    }
+
+    fn array(&mut self, depth: u8, emit: bool) -> Result<(), JSONError> {
+        let depth = depth.checked_add(1).filter(|&d| d <= MAX_DEPTH).ok_or(JSONError::TooDeep)?;
+        self.i += 1;
+        if emit {
+            self.vout.push(b'[');
+        }
+        if self.peek()? != b']' {
+            loop {
+                // Objects in arrays keep underscored keys
+                self.value(depth, emit, false)?;
+                if !self.next_item(b']')? {
+                    break;
+                }
+                if emit {
+                    self.vout.push(b',');
+                }
+            }
+        }
+        self.i += 1;
+        if emit {
+            self.vout.push(b']');
+        }
+        Ok(())
+    }
+
+    /// Consumes a comma and returns `true` if there is a next item.
+    /// Returns `false` if the closing bracket is found (it is not consumed).
+    fn next_item(&mut self, end: u8) -> Result<bool, JSONError> {
+        match self.peek()? {
+            b',' => {
+                self.i += 1;
+                // Trailing commas are not allowed
+                if self.peek()? == end {
+                    return Err(JSONError::Unexpected(self.i));
+                }
+                Ok(true)
+            }
+            c if c == end => Ok(false),
+            _ => Err(JSONError::Unexpected(self.i))
+        }
+    }
+
+    /// Checks a string and returns it (with quotes).
+    fn string(&mut self) -> Result<&'a [u8], JSONError> {
+        let b = self.b;
+        let start = self.i;
+        let mut i = start + 1;
+        loop {
+            match b.get(i) {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    i += match b.get(i + 1) {
+                        Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => 2,
+                        Some(b'u') if b.get(i + 2..i + 6).is_some_and(|h| h.iter().all(u8::is_ascii_hexdigit)) => 6,
+                        _ => return Err(JSONError::Unexpected(i))
+                    };
+                }
+                Some(0..0x20) | None => return Err(JSONError::Unexpected(i)),
+                Some(_) => i += 1
+            }
+        }
+        i += 1;
+        if std::str::from_utf8(&b[start..i]).is_err() {
+            return Err(JSONError::Unexpected(start));
+        }
+        self.i = i;
+        Ok(&b[start..i])
+    }
+
+    /// Checks a number literal (`-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?`).
+    fn number(&mut self) -> Result<(), JSONError> {
+        let b = self.b;
+        let digits = |i: usize| i + b[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+        let mut i = self.i + usize::from(b[self.i] == b'-');
+        i = match b.get(i) {
+            Some(b'0') => i + 1,
+            Some(b'1'..=b'9') => digits(i),
+            _ => return Err(JSONError::Unexpected(i))
+        };
+        if b.get(i) == Some(&b'.') {
+            let j = digits(i + 1);
+            if j == i + 1 {
+                return Err(JSONError::Unexpected(j));
+            }
+            i = j;
+        }
+        if matches!(b.get(i), Some(b'e' | b'E')) {
+            i += 1 + usize::from(matches!(b.get(i + 1), Some(b'+' | b'-')));
+            let j = digits(i);
+            if j == i {
+                return Err(JSONError::Unexpected(j));
+            }
+            i = j;
+        }
+        self.i = i;
+        Ok(())
+    }
+
+    fn literal(&mut self, lit: &[u8]) -> Result<(), JSONError> {
+        if !self.b[self.i..].starts_with(lit) {
+            return Err(JSONError::Unexpected(self.i));
+        }
+        self.i += lit.len();
+        Ok(())
+    }
+}
+
+/// Returns the content of a checked string (without quotes), with escape sequences decoded.
+fn unescape(s: &[u8]) -> Cow<'_, [u8]> {
+    let inner = &s[1..s.len() - 1];
+    if !inner.contains(&b'\\') {
+        return Cow::Borrowed(inner);
+    }
+    // Strings are checked, but lone surrogates cannot be decoded, so they are compared as they are
+    serde_json::from_slice::<String>(s).map_or(Cow::Borrowed(inner), |x| Cow::Owned(x.into_bytes()))
+}
+
+/// Checks if a key (with quotes) starts with `_` (it can be escaped).
+fn is_underscored(key: &[u8]) -> bool {
+    let k = &key[1..];
+    k.starts_with(b"_") || k.starts_with(b"\\u005f") || k.starts_with(b"\\u005F")
+}
+
+/// An error that occurs when a JSON file cannot be minified
+#[derive(Debug)]
+pub enum JSONError {
+    /// An unexpected character (or the end of data) at a byte position
+    Unexpected(usize),
+    /// A key is defined more than once in an object (JSON readers may choose different values).
+    /// It is returned as a warning, because the data is still minified.
+    DuplicateKey(Box<str>),
+    /// Arrays and objects are nested too deeply
+    TooDeep
+}
+impl std::error::Error for JSONError {}
+impl std::fmt::Display for JSONError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unexpected(i) => write!(f, "unexpected character or end of data at byte {i}"),
+            Self::DuplicateKey(k) => write!(f, "duplicate key \"{k}\""),
+            Self::TooDeep => f.write_str("arrays and objects are nested too deeply")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::ConfigMap;
+
+    fn minify(b: &[u8]) -> (Vec<u8>, Result_) {
+        let mut v = Vec::new();
+        let r = ConfigMap::default().fetch::<MinifierJSON>().minify(b, &mut v);
+        (v, r)
+    }
+
+    fn duplicate_of(r: Result_) -> Option<Box<str>> {
+        match r.err()?.downcast::<MinifyWarning>().ok()?.0.downcast::<JSONError>().ok()? {
+            JSONError::DuplicateKey(k) => Some(k),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn duplicate_keys_are_kept() {
+        let (v, r) = minify(b"{ \"a\": 1, \"b\": { \"a\": 2 }, \"a\": [ 3 ] }");
+        assert_eq!(v, b"{\"a\":1,\"b\":{\"a\":2},\"a\":[3]}");
+        assert_eq!(duplicate_of(r).as_deref(), Some("a"));
+        let (v, r) = minify(b"{ \"_comment\": \"x\", \"_comment\": \"y\", \"a\": { \"_c\": 1, \"_c\": 2 } }");
+        assert_eq!(v, b"{\"a\":{}}");
+        assert!(r.is_ok());
+    }
+
+    #[test]
+    fn escaped_duplicate_keys() {
+        let (v, r) = minify(b"{ \"a\": 1, \"\\u0061\": 2 }");
+        assert_eq!(v, b"{\"a\":1,\"\\u0061\":2}");
+        assert_eq!(duplicate_of(r).as_deref(), Some("a"));
+        let (_, r) = minify(b"{ \"a\\nb\": 1, \"a\\\\nb\": 2, \"\\ud800\": 3 }");
+        assert!(r.is_ok());
+    }
+
+    #[test]
+    fn invalid_data() {
+        assert!(minify(b"{ \"a\": 1, }").1.is_err());
+        assert!(minify(&[b'['; 200]).1.is_err());
+    }
+}
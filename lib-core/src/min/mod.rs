@@ -1,4 +1,8 @@
-use crate::{cfg, ext::KnownFmt};
+use std::fmt::Display;
+
+#[cfg(feature = "nbt")]
+use crate::nbt::Endian;
+use crate::{cfg::{self, acfg}, errors::VerifyError, ext::KnownFmt};
 
 /// Minifier for JSON files
 pub mod json;
@@ -15,11 +19,29 @@ pub mod nbt;
 /// Minifier for OGG files
 pub mod ogg;
 
+/// Optimizer for Anvil region files
+pub mod region;
+
 /// Optimizer for JAR archives
 pub mod jar;
 
+/// Minifier for files with hash (`#`) comments
+pub mod hash;
+
+/// Minifier for JavaScript and ZenScript files
+pub mod js;
+
+/// Minifier for GLSL shaders
+pub mod glsl;
+
+/// Optimizer for Java class files
+pub mod class;
+
+/// Minifiers defined by library users and their registry
+pub mod custom;
+
 #[inline]
-const fn strip_bom(b: &[u8]) -> &[u8] {
+pub(crate) const fn strip_bom(b: &[u8]) -> &[u8] {
     if let [239, 187, 191, x @ ..] = b { x } else { b }
 }
 
@@ -35,27 +57,51 @@ fn brackets(b: &[u8]) -> Option<&[u8]> {
     Some(&b[i..=j])
 }
 
+/// Compares decoded original and minified data.
+fn compare<T: PartialEq, E1: Display, E2: Display>(f: KnownFmt, orig: Result<T, E1>, min: Result<T, E2>) -> Result<(), VerifyError> {
+    let orig = orig.map_err(|e| VerifyError::Original(f, e.to_string().into()))?;
+    let min = min.map_err(|e| VerifyError::Minified(f, e.to_string().into()))?;
+    if orig == min { Ok(()) } else { Err(VerifyError::Mismatch(f)) }
+}
+
+acfg!(
+    /// Enables verifying minified data (see [`Minifier::verify`]). It is disabled (`false`) by default.
+    VerifyMinified: bool
+);
+
 /// A type to determine a minifying method and minimum compress size for file data.
 #[derive(Clone)]
 pub enum Minifier {
     /// A PNG minifier using `oxipng`.
     #[cfg(feature = "png")] PNG,
-    /// A JSON minifier using `serde_json`.
+    /// A streaming JSON minifier which copies tokens as they are (without whitespace and comments).
     JSON,
     /// A TOML minifier using `toml`.
     #[cfg(feature = "toml")] TOML,
     /// A customized NBT minifier
     #[cfg(feature = "nbt")] NBT,
+    /// A customized NBT minifier for little-endian files of Bedrock Edition
+    #[cfg(feature = "nbt")] BedrockNBT,
     /// An OGG minifier using `optivorbis`.
     #[cfg(feature = "ogg")] OGG,
-    /// A simple repacker for embedded JAR archives
+    /// An Anvil region optimizer that recompresses chunks and removes empty ones.
+    #[cfg(feature = "region")] Region,
+    /// A repacker for embedded JAR (and ZIP) archives. Their entries are processed recursively.
     #[cfg(feature = "jar")] JAR,
-    /// A minifier that removes hash (`#`) comment lines (and empty lines)
-    Hash,
-    /// A minifier that removes double-slash (`//`) comment lines (and empty lines)
-    Slash,
+    /// A Java class optimizer that removes unused constants and (optionally) debug attributes.
+    #[cfg(feature = "class")] Class,
+    /// A minifier that removes hash (`#`) comments (and empty lines), following the rules of a file dialect
+    Hash(hash::HashFmt),
+    /// A JavaScript minifier that removes comments and insignificant whitespace.
+    JS,
+    /// A ZenScript (CraftTweaker) minifier that removes comments and insignificant whitespace.
+    ZS,
+    /// A GLSL shader minifier that keeps preprocessor directives intact.
+    GLSL,
     /// A simple Unix line checker
-    UnixLine
+    UnixLine,
+    /// A minifier defined outside of this library (see [`custom::Registry`]).
+    Custom(custom::Custom)
 }
 impl Minifier {
     /// Return a Minifier based on file extension.
@@ -65,11 +111,17 @@ impl Minifier {
             #[cfg(feature = "png")] "png" => Self::PNG,
             "json" | "mcmeta" => Self::JSON,
             #[cfg(feature = "toml")] "toml" => Self::TOML,
-            #[cfg(feature = "nbt")] "nbt" | "blueprint" => Self::NBT,
+            #[cfg(feature = "nbt")] "nbt" | "blueprint" | "schem" | "schematic" | "litematic" => Self::NBT,
+            #[cfg(feature = "nbt")] "mcstructure" => Self::BedrockNBT,
             #[cfg(feature = "ogg")] "ogg" => Self::OGG,
-            #[cfg(feature = "jar")] "jar" => Self::JAR,
-            "cfg" | "obj" | "mtl" => Self::Hash,
-            "zs" | "js" | "fsh" | "vsh" => Self::Slash,
+            #[cfg(feature = "region")] "mca" => Self::Region,
+            #[cfg(feature = "jar")] "jar" | "zip" => Self::JAR,
+            #[cfg(feature = "class")] "class" => Self::Class,
+            "cfg" => Self::Hash(hash::HashFmt::ForgeCfg),
+            "obj" | "mtl" => Self::Hash(hash::HashFmt::Wavefront),
+            "js" => Self::JS,
+            "zs" => Self::ZS,
+            "fsh" | "vsh" | "gsh" | "csh" | "glsl" | "vert" | "frag" => Self::GLSL,
             "mf" => Self::UnixLine,
             _ => return None
         })
@@ -82,10 +134,17 @@ impl Minifier {
             KnownFmt::Json => Self::JSON,
             #[cfg(feature = "toml")] KnownFmt::Toml => Self::TOML,
             #[cfg(feature = "nbt")] KnownFmt::Nbt => Self::NBT,
+            #[cfg(feature = "nbt")] KnownFmt::Mcstructure => Self::BedrockNBT,
             #[cfg(feature = "ogg")] KnownFmt::Ogg => Self::OGG,
-            #[cfg(feature = "jar")] KnownFmt::Jar => Self::JAR,
-            KnownFmt::Cfg | KnownFmt::Obj | KnownFmt::Mtl => Self::Hash,
-            KnownFmt::Fsh | KnownFmt::Vsh | KnownFmt::Js | KnownFmt::Zs => Self::Slash,
+            #[cfg(feature = "region")] KnownFmt::Mca => Self::Region,
+            #[cfg(feature = "jar")] KnownFmt::Jar | KnownFmt::Zip => Self::JAR,
+            #[cfg(feature = "class")] KnownFmt::Class => Self::Class,
+            KnownFmt::Cfg => Self::Hash(hash::HashFmt::ForgeCfg),
+            KnownFmt::Obj | KnownFmt::Mtl => Self::Hash(hash::HashFmt::Wavefront),
+            KnownFmt::Js => Self::JS,
+            KnownFmt::Zs => Self::ZS,
+            KnownFmt::Fsh | KnownFmt::Vsh | KnownFmt::Gsh | KnownFmt::Csh
+            | KnownFmt::Glsl | KnownFmt::Vert | KnownFmt::Frag => Self::GLSL,
             KnownFmt::Mf => Self::UnixLine,
             _ => return None
         })
@@ -99,23 +158,79 @@ impl Minifier {
             #[cfg(feature = "png")] Self::PNG => cfgmap.fetch::<png::MinifierPNG>().minify(v, vout),
             Self::JSON => cfgmap.fetch::<json::MinifierJSON>().minify(v, vout),
             #[cfg(feature = "toml")] Self::TOML => cfgmap.fetch::<toml::MinifierTOML>().minify(strip_bom(v), vout),
-            #[cfg(feature = "nbt")] Self::NBT => cfgmap.fetch::<nbt::MinifierNBT>().minify(v, vout),
+            #[cfg(feature = "nbt")] Self::NBT => cfgmap.fetch::<nbt::MinifierNBT>().minify(v, vout, Endian::Big),
+            #[cfg(feature = "nbt")] Self::BedrockNBT => cfgmap.fetch::<nbt::MinifierNBT>().minify(v, vout, Endian::Little),
             #[cfg(feature = "ogg")] Self::OGG => cfgmap.fetch::<ogg::MinifierOGG>().minify(v, vout),
-            #[cfg(feature = "jar")] Self::JAR => cfgmap.fetch::<jar::MinifierJAR>().minify(v, vout),
-            Self::Hash => remove_line_comments("#", v, vout),
-            Self::Slash => remove_line_comments("//", v, vout),
-            Self::UnixLine => unixify_lines(v, vout)
+            #[cfg(feature = "region")] Self::Region => cfgmap.fetch::<region::MinifierRegion>().minify(v, vout),
+            #[cfg(feature = "jar")] Self::JAR => cfgmap.fetch::<jar::MinifierJAR>().minify(v, vout, *cfgmap.fetch::<jar::NestedMtime>()),
+            #[cfg(feature = "class")] Self::Class => cfgmap.fetch::<class::MinifierClass>().minify(v, vout),
+            Self::Hash(hf) => hf.minify(v, vout),
+            Self::JS => cfgmap.fetch::<js::MinifierJS>().minify_js(v, vout),
+            Self::ZS => cfgmap.fetch::<js::MinifierJS>().minify_zs(v, vout),
+            Self::GLSL => cfgmap.fetch::<glsl::MinifierGLSL>().minify(v, vout),
+            Self::UnixLine => unixify_lines(v, vout),
+            Self::Custom(c) => c.minify(cfgmap, v, vout)
+        }
+    }
+
+    /// Decodes minified data again and checks if it is semantically equal to the original data.
+    /// JSON values, TOML tables, PNG pixels, NBT tags and OGG samples are compared (intended changes, like removed keys, are applied to the original).
+    /// Other formats are not checked.
+    /// # Errors
+    /// Returns an error if data cannot be decoded or decoded data differs
+    pub fn verify(&self, cfgmap: &cfg::ConfigMap, v: &[u8], vout: &[u8]) -> Result<(), VerifyError> {
+        match self {
+            #[cfg(feature = "png")] Self::PNG => png::verify(v, vout),
+            Self::JSON => cfgmap.fetch::<json::MinifierJSON>().verify(v, vout),
+            #[cfg(feature = "toml")] Self::TOML => cfgmap.fetch::<toml::MinifierTOML>().verify(strip_bom(v), vout),
+            #[cfg(feature = "nbt")] Self::NBT => cfgmap.fetch::<nbt::MinifierNBT>().verify(v, vout, Endian::Big),
+            #[cfg(feature = "nbt")] Self::BedrockNBT => cfgmap.fetch::<nbt::MinifierNBT>().verify(v, vout, Endian::Little),
+            #[cfg(feature = "ogg")] Self::OGG => ogg::verify(v, vout),
+            _ => Ok(())
+        }
+    }
+
+    /// Returns a text which identifies this minifier with its config (used by [`crate::cache`]).
+    /// Minifiers with the same fingerprint produce the same output for the same data.
+    /// It returns `None` for archives, because their entries depend on every config.
+    #[cfg(feature = "cache")]
+    #[must_use]
+    pub fn fingerprint(&self, cfgmap: &cfg::ConfigMap) -> Option<String> {
+        fn fp<AC: cfg::AcceptsConfig>(name: &str, cfgmap: &cfg::ConfigMap) -> Option<String>
+        where AC::Cfg: serde::Serialize {
+            Some(format!("{name}:{}", serde_json::to_string(&*cfgmap.fetch::<AC>()).ok()?))
+        }
+        match self {
+            #[cfg(feature = "png")] Self::PNG => fp::<png::MinifierPNG>("png", cfgmap),
+            Self::JSON => fp::<json::MinifierJSON>("json", cfgmap),
+            #[cfg(feature = "toml")] Self::TOML => fp::<toml::MinifierTOML>("toml", cfgmap),
+            #[cfg(feature = "nbt")] Self::NBT => fp::<nbt::MinifierNBT>("nbt", cfgmap),
+            #[cfg(feature = "nbt")] Self::BedrockNBT => fp::<nbt::MinifierNBT>("nbt-le", cfgmap),
+            #[cfg(feature = "ogg")] Self::OGG => fp::<ogg::MinifierOGG>("ogg", cfgmap),
+            #[cfg(feature = "region")] Self::Region => fp::<region::MinifierRegion>("region", cfgmap),
+            #[cfg(feature = "jar")] Self::JAR => None,
+            #[cfg(feature = "class")] Self::Class => fp::<class::MinifierClass>("class", cfgmap),
+            Self::Hash(hf) => Some(match hf {
+                hash::HashFmt::ForgeCfg => "hash-cfg",
+                hash::HashFmt::Wavefront => "hash-obj"
+            }.into()),
+            Self::JS => fp::<js::MinifierJS>("js", cfgmap),
+            Self::ZS => fp::<js::MinifierJS>("zs", cfgmap),
+            Self::GLSL => fp::<glsl::MinifierGLSL>("glsl", cfgmap),
+            Self::UnixLine => Some("unix-line".into()),
+            Self::Custom(c) => c.fingerprint(cfgmap)
         }
     }
 
     /// Define a minimal size for file compression. Files with lower sizes will be stored as-is.
     #[must_use]
-    pub const fn compress_min(&self) -> u16 {
+    pub fn compress_min(&self) -> u16 {
         match self {
             #[cfg(feature = "png")] Self::PNG => 512,
             Self::JSON => 64,
             #[cfg(feature = "toml")] Self::TOML => 64,
-            #[cfg(feature = "nbt")] Self::NBT => 768,
+            #[cfg(feature = "nbt")] Self::NBT | Self::BedrockNBT => 768,
+            Self::Custom(c) => c.compress_min(),
             _ => 24
         }
     }
@@ -123,25 +238,6 @@ impl Minifier {
 
 type Result_ = anyhow::Result<()>;
 
-fn remove_line_comments(bs: &'static str, v: &[u8], vout: &mut Vec<u8>) -> Result_ {
-    let v = std::str::from_utf8(v)?;
-    for l in v.lines() {
-        let Some(ix) = l.as_bytes().iter().position(|&b| !b.is_ascii_whitespace()) else {
-            continue;
-        };
-        let l = &l[ix..];
-        let nix = l.find(bs);
-        let l = match nix {
-            Some(nix) if nix == ix => "",
-            Some(nix) => &l[..nix],
-            None => l
-        };
-        vout.extend_from_slice(l.trim_end().as_bytes());
-        vout.push(b'\n');
-    }
-    Ok(())
-}
-
 fn unixify_lines(v: &[u8], vout: &mut Vec<u8>) -> Result_ {
     let v = std::str::from_utf8(v)?;
     for l in v.lines() {
@@ -1,9 +1,9 @@
 #![cfg(feature = "nbt")]
-use std::{error::Error, io::{self, copy, Write}};
+use std::{collections::HashMap, error::Error, io::{self, copy, Write}};
 
-use crate::cfg::{acfg, ConfigHolder};
+use crate::{cfg::{acfg, ConfigHolder}, errors::VerifyError, ext::KnownFmt, nbt::{Endian, Tag}};
 
-use super::Result_;
+use super::{compare, Result_};
 
 enum NBTReader<'a> {
     Raw(&'a [u8]),
@@ -13,7 +13,7 @@ enum NBTReader<'a> {
 impl <'a> NBTReader<'a> {
     #[inline]
     fn from_bytes(b: &'a [u8]) -> Option<Self> {
-        Some(match b[0] {
+        Some(match b.first()? {
             0..=12 => Self::Raw(b),
             31 => Self::GZip(flate2::bufread::GzDecoder::new(b)),
             120 => Self::ZLib(flate2::bufread::ZlibDecoder::new(b)),
@@ -21,6 +21,14 @@ impl <'a> NBTReader<'a> {
         })
     }
     #[inline]
+    const fn container(&self) -> NBTContainer {
+        match self {
+            Self::Raw(_) => NBTContainer::Raw,
+            Self::GZip(_) => NBTContainer::Gzip,
+            Self::ZLib(_) => NBTContainer::Zlib
+        }
+    }
+    #[inline]
     fn reader(&mut self) -> &mut dyn io::Read {
         match self {
             Self::Raw(b) => b,
@@ -40,43 +48,309 @@ acfg!(
 );
 
 impl ConfigHolder<MinifierNBT> {
-    pub(super) fn minify(&self, b: &[u8], vout: &mut Vec<u8>) -> Result_ {
+    pub(super) fn minify(&self, b: &[u8], vout: &mut Vec<u8>, e: Endian) -> Result_ {
         let Some(mut nbtr) = NBTReader::from_bytes(b) else {
             return Err(NBTError.into());
         };
-    
+        let container = match self.container {
+            NBTContainer::Keep => nbtr.container(),
+            c => c
+        };
+        if self.is_structural() {
+            let mut data = Vec::new();
+            nbtr.write_to(&mut data)?;
+            return self.compress(container, &mut &self.optimize(data, e)[..], vout);
+        }
+        self.compress(container, nbtr.reader(), vout)
+    }
+
+    /// Compares tag trees of minified data and the original data.
+    /// Only changes made on purpose (see [`Self::normalize`]) are applied to the original tags before comparing them.
+    pub(super) fn verify(&self, b: &[u8], out: &[u8], e: Endian) -> Result<(), VerifyError> {
+        let orig = decode(b, e).map(|(name, mut root)| {
+            self.normalize(&mut root);
+            (name, root)
+        });
+        compare(KnownFmt::Nbt, orig, decode(out, e))
+    }
+
+    fn is_structural(&self) -> bool {
+        self.drop_empty || self.normalize_lists || self.dedupe_palettes || self.remove_defaults
+    }
+
+    /// Parses and optimizes uncompressed tags. Data which cannot be parsed is returned as it is.
+    fn optimize(&self, data: Vec<u8>, e: Endian) -> Vec<u8> {
+        let Ok((name, mut root)) = Tag::read_named_with(&data, e) else {
+            return data;
+        };
+        self.normalize(&mut root);
+        let mut v = Vec::with_capacity(data.len());
+        root.write_named_with(&name, &mut v, e);
+        v
+    }
+
+    /// Applies enabled transforms to tags: structure palettes are deduplicated (with remapped block states),
+    /// empty structure fields and (optionally) other empty entries are removed and empty lists are normalized.
+    fn normalize(&self, root: &mut Tag) {
+        if is_structure(root) {
+            if self.remove_defaults {
+                remove_structure_defaults(root);
+            }
+            if self.dedupe_palettes {
+                dedupe_palettes(root);
+            }
+        }
+        if self.drop_empty {
+            drop_empty(root);
+        }
+        if self.normalize_lists {
+            normalize_lists(root);
+        }
+    }
+
+    fn compress(&self, container: NBTContainer, r: &mut dyn io::Read, vout: &mut Vec<u8>) -> Result_ {
+        let zlib = match container {
+            NBTContainer::Raw => {
+                copy(r, vout)?;
+                return Ok(());
+            }
+            NBTContainer::Zlib => true,
+            NBTContainer::Keep | NBTContainer::Gzip => false
+        };
         #[cfg(feature = "nbt-zopfli")]
         if let Some(ic) = self.use_zopfli.iter_count() {
-            return minify_with_zopfli(vout, &mut nbtr, ic.into())
+            return minify_with_zopfli(vout, r, ic.into(), zlib)
         }
 
-        let mut enc = flate2::write::GzEncoder::new(vout, flate2::Compression::best());
-        nbtr.write_to(&mut enc)?;
-        enc.finish()?;
+        let lvl = flate2::Compression::best();
+        if zlib {
+            let mut enc = flate2::write::ZlibEncoder::new(vout, lvl);
+            copy(r, &mut enc)?;
+            enc.finish()?;
+        } else {
+            let mut enc = flate2::write::GzEncoder::new(vout, lvl);
+            copy(r, &mut enc)?;
+            enc.finish()?;
+        }
         Ok(())
     }
 }
 
+fn decompress(b: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut nbtr = NBTReader::from_bytes(b).ok_or(NBTError)?;
+    let mut v = Vec::new();
+    nbtr.write_to(&mut v)?;
+    Ok(v)
+}
+
+/// Decompresses and parses tags (with the root name).
+fn decode(b: &[u8], e: Endian) -> anyhow::Result<(Box<[u8]>, Tag)> {
+    Ok(Tag::read_named_with(&decompress(b)?, e)?)
+}
+
 #[cfg(feature = "nbt-zopfli")]
-fn minify_with_zopfli(vout: &mut Vec<u8>, nbtr: &mut NBTReader, ic: std::num::NonZeroU64) -> Result_ {
+fn minify_with_zopfli(vout: &mut Vec<u8>, r: &mut dyn io::Read, ic: std::num::NonZeroU64, zlib: bool) -> Result_ {
     let zo = zopfli::Options {
         iteration_count: ic,
         iterations_without_improvement: std::num::NonZeroU64::new(5).unwrap(),
         ..<zopfli::Options as Default>::default()
     };
-    let mut enc = zopfli::GzipEncoder::new(zo, zopfli::BlockType::Dynamic, vout)?;
-    nbtr.write_to(&mut enc)?;
-    enc.finish()?;
+    if zlib {
+        let mut enc = zopfli::ZlibEncoder::new(zo, zopfli::BlockType::Dynamic, vout)?;
+        copy(r, &mut enc)?;
+        enc.finish()?;
+    } else {
+        let mut enc = zopfli::GzipEncoder::new(zo, zopfli::BlockType::Dynamic, vout)?;
+        copy(r, &mut enc)?;
+        enc.finish()?;
+    }
     Ok(())
 }
 
+/// Checks if a root tag is a structure (saved by a structure block).
+fn is_structure(root: &Tag) -> bool {
+    matches!(root.get("blocks"), Some(Tag::List(..))) && root.get("size").is_some()
+        && (root.get("palette").is_some() || root.get("palettes").is_some())
+}
+
+fn is_empty(t: &Tag) -> bool {
+    match t {
+        Tag::Compound(c) => c.is_empty(),
+        Tag::List(_, v) => v.is_empty(),
+        _ => false
+    }
+}
+
+/// Removes an entry if it is an empty compound or list.
+fn remove_empty_entry(t: &mut Tag, name: &str) {
+    if let Some(c) = t.as_compound_mut() {
+        c.retain(|(k, v)| **k != *name.as_bytes() || !is_empty(v));
+    }
+}
+
+/// Returns palette lists of a structure (a structure can have one `palette` or many `palettes` with the same size).
+fn palettes_mut(root: &mut Tag) -> Vec<&mut Vec<Tag>> {
+    let Some(c) = root.as_compound_mut() else {
+        return Vec::new();
+    };
+    let mut v = Vec::new();
+    for (k, t) in c {
+        match (&**k, t) {
+            (b"palette", Tag::List(_, p)) => v.push(p),
+            (b"palettes", Tag::List(_, ps)) => v.extend(ps.iter_mut().filter_map(|p| match p {
+                Tag::List(_, p) => Some(p),
+                _ => None
+            })),
+            _ => {}
+        }
+    }
+    v
+}
+
+/// Removes empty structure fields which the game reads the same way as missing ones (in every data version):
+/// `Properties` of palette entries and the `entities` list.
+/// An empty `nbt` of a block is kept, because a block with it replaces an existing block entity when it is placed.
+fn remove_structure_defaults(root: &mut Tag) {
+    remove_empty_entry(root, "entities");
+    for p in palettes_mut(root) {
+        p.iter_mut().for_each(|e| remove_empty_entry(e, "Properties"));
+    }
+}
+
+/// Removes duplicate palette entries (compared without the order of compound entries) and remaps block states.
+/// Entries are removed only if they are duplicated at the same positions in every palette.
+fn dedupe_palettes(root: &mut Tag) {
+    let mut palettes = palettes_mut(root);
+    let Some(n) = palettes.first().map(|p| p.len()) else {
+        return;
+    };
+    if palettes.iter().any(|p| p.len() != n) {
+        return;
+    }
+    let mut seen = HashMap::new();
+    let mut map = Vec::with_capacity(n);
+    for i in 0..n {
+        let mut key = Vec::new();
+        for p in &palettes {
+            let mut t = p[i].clone();
+            sort_compounds(&mut t);
+            t.write_named(b"", &mut key);
+        }
+        let next = seen.len();
+        map.push(*seen.entry(key).or_insert(next));
+    }
+    if seen.len() == n {
+        return;
+    }
+    for p in &mut palettes {
+        // The first occurrence of each entry is kept, so kept entries have increasing indices
+        let mut next = 0;
+        let mut it = map.iter();
+        p.retain(|_| {
+            let keep = it.next() == Some(&next);
+            next += usize::from(keep);
+            keep
+        });
+    }
+    if let Some(Tag::List(_, blocks)) = root.get_mut("blocks") {
+        for b in blocks {
+            if let Some(Tag::Int(s)) = b.get_mut("state") {
+                if let Some(&m) = usize::try_from(*s).ok().and_then(|s| map.get(s)) {
+                    *s = m as i32;
+                }
+            }
+        }
+    }
+}
+
+fn sort_compounds(t: &mut Tag) {
+    match t {
+        Tag::Compound(c) => {
+            c.sort_by(|a, b| a.0.cmp(&b.0));
+            c.iter_mut().for_each(|(_, t)| sort_compounds(t));
+        }
+        Tag::List(_, v) => v.iter_mut().for_each(sort_compounds),
+        _ => {}
+    }
+}
+
+/// Removes compound entries with empty compounds or lists. List elements are kept, because their indices can be used.
+fn drop_empty(t: &mut Tag) {
+    match t {
+        Tag::Compound(c) => {
+            c.iter_mut().for_each(|(_, t)| drop_empty(t));
+            c.retain(|(_, t)| !is_empty(t));
+        }
+        Tag::List(_, v) => v.iter_mut().for_each(drop_empty),
+        _ => {}
+    }
+}
+
+/// Sets the element type of empty lists to `End` (as the game writes them).
+fn normalize_lists(t: &mut Tag) {
+    match t {
+        Tag::Compound(c) => c.iter_mut().for_each(|(_, t)| normalize_lists(t)),
+        Tag::List(id, v) => {
+            if v.is_empty() {
+                *id = Tag::END;
+            }
+            v.iter_mut().for_each(normalize_lists);
+        }
+        _ => {}
+    }
+}
+
+/// A container (compression) of NBT data written by the minifier.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum NBTContainer {
+    /// Keep the container of the original data.
+    #[default]
+    Keep,
+    /// Write uncompressed data.
+    Raw,
+    /// Compress data with gzip.
+    Gzip,
+    /// Compress data with zlib.
+    Zlib
+}
+
 /// Configuration for the NBT minifier
-#[derive(Default)]
+#[derive(Clone)]
 #[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize))]
 pub struct NBTConfig {
     #[cfg(feature = "nbt-zopfli")]
     /// Enables Zopfli compression (better, but slower)
-    pub use_zopfli: crate::cfg::CfgZopfli
+    pub use_zopfli: crate::cfg::CfgZopfli,
+    /// A container of minified data. Files are usually read with one specific container (for example,
+    /// `NbtIo.readCompressed` reads only gzip), so the original one is kept by default.
+    /// Another container can be set for some paths with rules.
+    pub container: NBTContainer,
+    /// An optional flag that enables removing compound entries with empty compounds or lists.
+    /// Some mods (and the game for `nbt` of structure blocks) may treat missing entries differently, so it is disabled by default.
+    pub drop_empty: bool,
+    /// An optional flag that enables setting the element type of empty lists to `End`.
+    /// Defaults to `true`.
+    pub normalize_lists: bool,
+    /// An optional flag that enables removing duplicate palette entries in structure files (block states are remapped).
+    /// Defaults to `true`.
+    pub dedupe_palettes: bool,
+    /// An optional flag that enables removing empty structure fields which are read the same way as missing ones
+    /// (`Properties` of palette entries and the `entities` list). Defaults to `true`.
+    pub remove_defaults: bool
+}
+impl Default for NBTConfig {
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "nbt-zopfli")]
+            use_zopfli: crate::cfg::CfgZopfli::default(),
+            container: NBTContainer::Keep,
+            drop_empty: false,
+            normalize_lists: true,
+            dedupe_palettes: true,
+            remove_defaults: true
+        }
+    }
 }
 
 /// An error that occurs when a minifier cannot detect the compression type of a NBT entry
@@ -87,4 +361,136 @@ impl std::fmt::Display for NBTError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Invalid NBT entry")
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::{cfg::ConfigMap, nbt::{Endian, Tag}};
+
+    use crate::errors::VerifyError;
+
+    use super::{decompress, MinifierNBT, NBTConfig, NBTContainer};
+
+    fn entry(name: &str, t: Tag) -> (Box<[u8]>, Tag) {
+        (name.as_bytes().into(), t)
+    }
+
+    fn block_state(name: &str, props: &[(&str, &str)]) -> Tag {
+        let props = props.iter().map(|(k, v)| entry(k, Tag::String(v.as_bytes().into()))).collect();
+        Tag::Compound(vec![entry("Name", Tag::String(name.as_bytes().into())), entry("Properties", Tag::Compound(props))])
+    }
+
+    fn structure(palettes: (&str, Tag), states: &[i32]) -> Tag {
+        let blocks = states.iter().enumerate().map(|(i, &s)| Tag::Compound(vec![
+            entry("pos", Tag::List(3, vec![Tag::Int(i as i32), Tag::Int(0), Tag::Int(0)])),
+            entry("state", Tag::Int(s))
+        ])).collect();
+        Tag::Compound(vec![
+            entry("size", Tag::List(3, vec![Tag::Int(states.len() as i32), Tag::Int(1), Tag::Int(1)])),
+            entry("blocks", Tag::List(Tag::COMPOUND, blocks)),
+            entry(palettes.0, palettes.1),
+            entry("entities", Tag::List(Tag::END, Vec::new()))
+        ])
+    }
+
+    fn minify(root: &Tag, container: NBTContainer) -> Vec<u8> {
+        let mut b = Vec::new();
+        root.write_named(b"", &mut b);
+        let cfgmap = ConfigMap::default();
+        cfgmap.set::<MinifierNBT>(NBTConfig { container, ..NBTConfig::default() });
+        let nbtm = cfgmap.fetch::<MinifierNBT>();
+        let mut v = Vec::new();
+        nbtm.minify(&b, &mut v, Endian::Big).unwrap();
+        nbtm.verify(&b, &v, Endian::Big).unwrap();
+        v
+    }
+
+    fn read(b: &[u8]) -> Tag {
+        Tag::read_named(&decompress(b).unwrap()).unwrap().1
+    }
+
+    fn states(root: &Tag) -> Vec<i32> {
+        let Some(Tag::List(_, blocks)) = root.get("blocks") else { panic!("no blocks") };
+        blocks.iter().map(|b| match b.get("state") {
+            Some(Tag::Int(s)) => *s,
+            _ => panic!("no state")
+        }).collect()
+    }
+
+    #[test]
+    fn dedupe_palette() {
+        let palette = Tag::List(Tag::COMPOUND, vec![
+            block_state("minecraft:stone", &[]),
+            block_state("minecraft:dirt", &[]),
+            Tag::Compound(vec![entry("Name", Tag::String((*b"minecraft:stone").into()))]),
+            block_state("minecraft:stairs", &[("facing", "east"), ("half", "top")]),
+            block_state("minecraft:stairs", &[("half", "top"), ("facing", "east")]),
+            block_state("minecraft:stairs", &[("facing", "west"), ("half", "top")])
+        ]);
+        let root = read(&minify(&structure(("palette", palette), &[0, 1, 2, 3, 4, 5, 2]), NBTContainer::Raw));
+        let Some(Tag::List(_, palette)) = root.get("palette") else { panic!("no palette") };
+        assert_eq!(palette.len(), 4);
+        assert_eq!(palette[0], Tag::Compound(vec![entry("Name", Tag::String((*b"minecraft:stone").into()))]));
+        assert_eq!(palette[3], block_state("minecraft:stairs", &[("facing", "west"), ("half", "top")]));
+        assert_eq!(states(&root), [0, 1, 0, 2, 2, 3, 0]);
+        assert!(root.get("entities").is_none());
+    }
+
+    #[test]
+    fn dedupe_palettes_at_same_positions() {
+        let stone = || block_state("minecraft:stone", &[]);
+        let dirt = || block_state("minecraft:dirt", &[]);
+        // Entries 0 and 2 are duplicated in both palettes, entries 0 and 1 only in the first one
+        let palettes = Tag::List(9, vec![
+            Tag::List(Tag::COMPOUND, vec![stone(), stone(), stone()]),
+            Tag::List(Tag::COMPOUND, vec![dirt(), stone(), dirt()])
+        ]);
+        let root = read(&minify(&structure(("palettes", palettes), &[0, 1, 2]), NBTContainer::Raw));
+        let Some(Tag::List(_, palettes)) = root.get("palettes") else { panic!("no palettes") };
+        assert!(palettes.iter().all(|p| matches!(p, Tag::List(_, p) if p.len() == 2)));
+        assert_eq!(states(&root), [0, 1, 0]);
+    }
+
+    #[test]
+    fn containers() {
+        let root = Tag::Compound(vec![entry("list", Tag::List(3, Vec::new()))]);
+        let expected = Tag::Compound(vec![entry("list", Tag::List(Tag::END, Vec::new()))]);
+        for (container, first) in [(NBTContainer::Raw, 10), (NBTContainer::Gzip, 31), (NBTContainer::Zlib, 120)] {
+            let v = minify(&root, container);
+            assert_eq!(v[0], first);
+            assert_eq!(read(&v), expected);
+        }
+    }
+
+    #[test]
+    fn verify_tag_trees() {
+        let nbtm = ConfigMap::default().fetch::<MinifierNBT>();
+        let tag = |x| {
+            let mut b = Vec::new();
+            Tag::Compound(vec![entry("x", Tag::Int(x)), entry("list", Tag::List(3, Vec::new()))]).write_named(b"", &mut b);
+            b
+        };
+        let mut out = Vec::new();
+        nbtm.minify(&tag(1), &mut out, Endian::Big).unwrap();
+        assert!(nbtm.verify(&tag(1), &out, Endian::Big).is_ok());
+        assert!(matches!(nbtm.verify(&tag(2), &out, Endian::Big), Err(VerifyError::Mismatch(_))));
+        // Original data which cannot be parsed is reported
+        let invalid = [10, 0, 0, 3, 0];
+        out.clear();
+        nbtm.minify(&invalid, &mut out, Endian::Big).unwrap();
+        assert!(matches!(nbtm.verify(&invalid, &out, Endian::Big), Err(VerifyError::Original(..))));
+    }
+
+    #[test]
+    fn empty_block_nbt_is_kept() {
+        let mut root = structure(("palette", Tag::List(Tag::COMPOUND, vec![block_state("minecraft:chest", &[])])), &[0, 0]);
+        if let Some(Tag::List(_, blocks)) = root.get_mut("blocks") {
+            blocks[0].as_compound_mut().unwrap().push(entry("nbt", Tag::Compound(Vec::new())));
+        }
+        let root = read(&minify(&root, NBTContainer::Raw));
+        let Some(Tag::List(_, blocks)) = root.get("blocks") else { panic!("no blocks") };
+        assert_eq!(blocks[0].get("nbt"), Some(&Tag::Compound(Vec::new())));
+        assert_eq!(blocks[1].get("nbt"), None);
+        let Some(Tag::List(_, palette)) = root.get("palette") else { panic!("no palette") };
+        assert_eq!(palette[0].get("Properties"), None);
+    }
+}
@@ -3,7 +3,7 @@ use std::io::Cursor;
 
 use optivorbis::{remuxer::ogg_to_ogg::Settings, OggToOgg, Remuxer, VorbisCommentFieldsAction, VorbisOptimizerSettings, VorbisVendorStringAction};
 
-use crate::cfg::{acfg, ConfigHolder};
+use crate::{cfg::{acfg, ConfigHolder}, errors::VerifyError, ext::KnownFmt};
 use super::Result_;
 
 acfg!(
@@ -46,4 +46,52 @@ impl OGGConfig {
         };
         opts
     }
-}
\ No newline at end of file
+}
+
+/// Compares decoded samples of original and minified audio streams.
+/// Samples are compared while they are decoded, so whole streams are not kept in memory.
+pub(super) fn verify(b: &[u8], out: &[u8]) -> Result<(), VerifyError> {
+    use lewton::inside_ogg::OggStreamReader;
+    let f = KnownFmt::Ogg;
+    let err = |i: usize, e: lewton::VorbisError| if i == 0 {
+        VerifyError::Original(f, e.to_string().into())
+    } else {
+        VerifyError::Minified(f, e.to_string().into())
+    };
+    let mut readers = [
+        OggStreamReader::new(Cursor::new(b)).map_err(|e| err(0, e))?,
+        OggStreamReader::new(Cursor::new(out)).map_err(|e| err(1, e))?
+    ];
+    let hdr = |r: &OggStreamReader<_>| (r.ident_hdr.audio_channels, r.ident_hdr.audio_sample_rate);
+    if hdr(&readers[0]) != hdr(&readers[1]) {
+        return Err(VerifyError::Mismatch(f));
+    }
+    let mut bufs = [Vec::new(), Vec::new()];
+    let mut ended = [false, false];
+    let mut compared = 0;
+    loop {
+        let n = bufs[0].len().min(bufs[1].len());
+        if bufs[0][..n] != bufs[1][..n] {
+            return Err(VerifyError::Mismatch(f));
+        }
+        bufs[0].drain(..n);
+        bufs[1].drain(..n);
+        compared += n as u64;
+        // At least one buffer is empty now, so the next packet is read into it (unless its stream has ended)
+        let Some(i) = (0..2).find(|&i| bufs[i].is_empty() && !ended[i]) else {
+            // The last packet may not be trimmed (it depends on how packets are placed in pages),
+            // so samples after the final granule position are ignored
+            let end = readers[0].get_last_absgp().filter(|&g| Some(g) == readers[1].get_last_absgp())
+                .map(|g| g * u64::from(readers[0].ident_hdr.audio_channels));
+            return if (bufs[0].is_empty() && bufs[1].is_empty()) || end.is_some_and(|e| compared >= e) {
+                Ok(())
+            } else {
+                Err(VerifyError::Mismatch(f))
+            };
+        };
+        match readers[i].read_dec_packet_itl().map_err(|e| err(i, e))? {
+            Some(p) => bufs[i].extend(p),
+            None => ended[i] = true
+        }
+    }
+}
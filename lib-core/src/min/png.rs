@@ -2,7 +2,7 @@
 
 use state::InitCell;
 
-use crate::cfg::{acfg, ConfigHolder};
+use crate::{cfg::{acfg, ConfigHolder}, errors::VerifyError, ext::KnownFmt};
 use super::Result_;
 
 acfg!(
@@ -48,4 +48,68 @@ impl PNGConfig {
             popts
         })
     }
-}
\ No newline at end of file
+}
+
+/// Compares pixels of all frames in original and minified images. Colors of fully transparent pixels are ignored.
+pub(super) fn verify(b: &[u8], out: &[u8]) -> Result<(), VerifyError> {
+    let f = KnownFmt::Png;
+    let orig_err = |e: png::DecodingError| VerifyError::Original(f, e.to_string().into());
+    let min_err = |e: png::DecodingError| VerifyError::Minified(f, e.to_string().into());
+    let mut orig = Frames::new(b).map_err(orig_err)?;
+    let mut min = Frames::new(out).map_err(min_err)?;
+    if orig.count != min.count {
+        return Err(VerifyError::Mismatch(f));
+    }
+    for _ in 0..orig.count {
+        let (oa, da) = orig.next().map_err(orig_err)?;
+        let (ob, db) = min.next().map_err(min_err)?;
+        let len = oa.width as usize * oa.height as usize;
+        if (oa.width, oa.height) != (ob.width, ob.height) || !(0..len).all(|i| pixel(&oa, da, i) == pixel(&ob, db, i)) {
+            return Err(VerifyError::Mismatch(f));
+        }
+    }
+    Ok(())
+}
+
+/// A decoder of image frames (PNG images have one frame, APNG images can have more).
+struct Frames<'a> {
+    r: png::Reader<&'a [u8]>,
+    buf: Vec<u8>,
+    count: usize
+}
+impl<'a> Frames<'a> {
+    fn new(b: &'a [u8]) -> Result<Self, png::DecodingError> {
+        let mut dec = png::Decoder::new(b);
+        dec.set_transformations(png::Transformations::EXPAND);
+        let r = dec.read_info()?;
+        let info = r.info();
+        // The default image is an extra frame if it is not a part of the animation
+        let count = info.animation_control.map_or(1, |ac| ac.num_frames as usize + usize::from(info.frame_control.is_none()));
+        Ok(Self { buf: vec![0; r.output_buffer_size()], r, count })
+    }
+
+    fn next(&mut self) -> Result<(png::OutputInfo, &[u8]), png::DecodingError> {
+        let oi = self.r.next_frame(&mut self.buf)?;
+        let len = oi.buffer_size();
+        Ok((oi, &self.buf[..len]))
+    }
+}
+
+/// Returns a pixel of a decoded frame as RGBA with 16-bit channels.
+fn pixel(oi: &png::OutputInfo, data: &[u8], i: usize) -> [u16; 4] {
+    let n = oi.color_type.samples();
+    let sample = |j: usize| if oi.bit_depth == png::BitDepth::Sixteen {
+        let k = 2 * (i * n + j);
+        u16::from_be_bytes([data[k], data[k + 1]])
+    } else {
+        u16::from(data[i * n + j]) * 257
+    };
+    let px = match oi.color_type {
+        png::ColorType::Rgb => [sample(0), sample(1), sample(2), u16::MAX],
+        png::ColorType::Rgba => [sample(0), sample(1), sample(2), sample(3)],
+        png::ColorType::GrayscaleAlpha => [sample(0), sample(0), sample(0), sample(1)],
+        // Indexed colors are expanded by the decoder
+        png::ColorType::Grayscale | png::ColorType::Indexed => [sample(0), sample(0), sample(0), u16::MAX],
+    };
+    if px[3] == 0 { [0; 4] } else { px }
+}
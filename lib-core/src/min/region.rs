@@ -0,0 +1,305 @@
+#![cfg(feature = "region")]
+use std::{error::Error, io::{self, Read, Write}};
+
+use crate::{cfg::{acfg, ConfigHolder}, nbt::Tag};
+
+use super::Result_;
+
+/// A size of a sector. Chunks are aligned to sectors.
+const SECTOR: usize = 4096;
+/// A number of chunks in a region (32×32).
+const CHUNKS: usize = 1024;
+/// A header has two sectors: chunk locations and timestamps.
+const HEADER: usize = 2 * SECTOR;
+
+const GZIP: u8 = 1;
+const ZLIB: u8 = 2;
+const RAW: u8 = 3;
+/// A flag of chunks stored in separate `.mcc` files (they are too large for a region).
+const EXTERNAL: u8 = 128;
+
+acfg!(
+    /// An Anvil region optimizer that accepts [`RegionConfig`].
+    MinifierRegion: RegionConfig
+);
+
+impl ConfigHolder<MinifierRegion> {
+    /// Recompresses chunks and writes them in order, without free sectors between them.
+    /// A recompressed chunk is kept only if it is smaller than the original one.
+    pub(super) fn minify(&self, b: &[u8], vout: &mut Vec<u8>) -> Result_ {
+        if b.is_empty() {
+            return Ok(());
+        }
+        let Some((locs, times)) = b.get(..HEADER).map(|h| h.split_at(SECTOR)) else {
+            return Err(RegionError::Header.into());
+        };
+        let start = vout.len();
+        vout.resize(start + HEADER, 0);
+        for i in 0..CHUNKS {
+            let loc = u32::from_be_bytes([locs[i * 4], locs[i * 4 + 1], locs[i * 4 + 2], locs[i * 4 + 3]]);
+            if loc == 0 {
+                continue;
+            }
+            let (ctype, data) = chunk_at(b, loc).ok_or(RegionError::Chunk(i))?;
+            let new = self.recompress(ctype, data).map_err(|_| RegionError::Chunk(i))?;
+            let (ctype, data) = match &new {
+                Recompressed::Removed => continue,
+                Recompressed::Kept => (ctype, data),
+                Recompressed::Zlib(v) => (ZLIB, &v[..])
+            };
+            let offset = (vout.len() - start) / SECTOR;
+            vout.extend_from_slice(&(data.len() as u32 + 1).to_be_bytes());
+            vout.push(ctype);
+            vout.extend_from_slice(data);
+            vout.resize(vout.len().next_multiple_of(SECTOR), 0);
+            let count = (vout.len() - start) / SECTOR - offset;
+            let h = start + i * 4;
+            vout[h..h + 4].copy_from_slice(&((offset as u32) << 8 | count as u32).to_be_bytes());
+            vout[h + SECTOR..h + SECTOR + 4].copy_from_slice(&times[i * 4..i * 4 + 4]);
+        }
+        Ok(())
+    }
+
+    fn recompress(&self, ctype: u8, data: &[u8]) -> io::Result<Recompressed> {
+        let mut nbt = Vec::new();
+        match ctype {
+            GZIP => { flate2::bufread::GzDecoder::new(data).read_to_end(&mut nbt)?; }
+            ZLIB => { flate2::bufread::ZlibDecoder::new(data).read_to_end(&mut nbt)?; }
+            RAW => nbt.extend_from_slice(data),
+            // External, LZ4 and custom chunks are kept as they are
+            _ => return Ok(Recompressed::Kept)
+        }
+        if self.drop_empty || self.trim_uninhabited {
+            let (_, root) = Tag::read_named(&nbt).map_err(io::Error::other)?;
+            if (self.drop_empty && is_empty_chunk(&root)) || (self.trim_uninhabited && is_uninhabited(&root)) {
+                return Ok(Recompressed::Removed);
+            }
+        }
+        let v = self.compress(&nbt)?;
+        Ok(if v.len() < data.len() { Recompressed::Zlib(v) } else { Recompressed::Kept })
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        #[cfg(feature = "nbt-zopfli")]
+        if let Some(ic) = self.use_zopfli.iter_count() {
+            let zo = zopfli::Options {
+                iteration_count: ic.into(),
+                iterations_without_improvement: std::num::NonZeroU64::new(5).unwrap(),
+                ..<zopfli::Options as Default>::default()
+            };
+            let mut v = Vec::new();
+            zopfli::compress(zo, zopfli::Format::Zlib, data, &mut v)?;
+            return Ok(v);
+        }
+
+        let mut enc = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        enc.write_all(data)?;
+        enc.finish()
+    }
+}
+
+/// A result of recompressing a chunk.
+enum Recompressed {
+    /// A chunk should be removed
+    Removed,
+    /// The original data is kept (new data is not smaller or the compression type is not supported)
+    Kept,
+    /// New data compressed with zlib
+    Zlib(Vec<u8>)
+}
+
+/// Returns a compression type and data of a chunk stored at a location (from the header).
+fn chunk_at(b: &[u8], loc: u32) -> Option<(u8, &[u8])> {
+    let (offset, count) = ((loc >> 8) as usize, (loc & 0xff) as usize);
+    if offset < HEADER / SECTOR || count == 0 {
+        return None;
+    }
+    let start = offset * SECTOR;
+    let len = u32::from_be_bytes(b.get(start..start + 4)?.try_into().ok()?) as usize;
+    if len == 0 || len + 4 > count * SECTOR {
+        return None;
+    }
+    let (&ctype, data) = b.get(start + 4..start + 4 + len)?.split_first()?;
+    Some((ctype, if ctype & EXTERNAL == 0 { data } else { &[] }))
+}
+
+/// Checks if a chunk has no data that the game would keep: it is an entity chunk without entities,
+/// a POI chunk without sections or a chunk which was not generated yet.
+fn is_empty_chunk(root: &Tag) -> bool {
+    let level = root.get("Level").unwrap_or(root);
+    match (root.get("Entities"), root.get("Sections"), level.get("Status")) {
+        (Some(Tag::List(_, v)), ..) => v.is_empty(),
+        (_, Some(Tag::Compound(c)), _) => c.is_empty(),
+        (.., Some(Tag::String(s))) => matches!(&**s, b"empty" | b"minecraft:empty"),
+        _ => false
+    }
+}
+
+fn is_uninhabited(root: &Tag) -> bool {
+    let level = root.get("Level").unwrap_or(root);
+    matches!(level.get("InhabitedTime"), Some(Tag::Long(0)))
+}
+
+/// Configuration for the region optimizer
+#[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegionConfig {
+    #[cfg(feature = "nbt-zopfli")]
+    /// Enables Zopfli compression (better, but slower)
+    pub use_zopfli: crate::cfg::CfgZopfli,
+    /// An optional flag that enables removing empty chunks (entity chunks without entities,
+    /// POI chunks without sections and chunks which were not generated yet).
+    /// Defaults to `true`.
+    pub drop_empty: bool,
+    /// An optional flag that enables removing chunks in which players have not spent any time (`InhabitedTime` is 0).
+    /// The game generates these chunks again, so any changes made in them are lost. It is disabled by default.
+    pub trim_uninhabited: bool
+}
+impl Default for RegionConfig {
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "nbt-zopfli")]
+            use_zopfli: crate::cfg::CfgZopfli::default(),
+            drop_empty: true,
+            trim_uninhabited: false
+        }
+    }
+}
+
+/// An error that occurs when a region file is invalid
+#[derive(Debug)]
+pub enum RegionError {
+    /// A file is too small to contain a header
+    Header,
+    /// A chunk (at an index in the header) has an invalid location or data
+    Chunk(usize)
+}
+impl Error for RegionError {}
+impl std::fmt::Display for RegionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Header => f.write_str("incomplete region header"),
+            Self::Chunk(i) => write!(f, "invalid chunk at {}, {}", i % 32, i / 32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cfg::ConfigMap, nbt::Tag};
+
+    use super::{MinifierRegion, RegionConfig, CHUNKS, EXTERNAL, HEADER, RAW, SECTOR, ZLIB};
+
+    fn entry(name: &str, t: Tag) -> (Box<[u8]>, Tag) {
+        (name.as_bytes().into(), t)
+    }
+
+    fn chunk(entries: Vec<(Box<[u8]>, Tag)>) -> Vec<u8> {
+        let mut v = Vec::new();
+        Tag::Compound(entries).write_named(b"", &mut v);
+        v
+    }
+
+    /// Builds a region with chunks (an index, a compression type and data) placed after a free sector.
+    fn region(chunks: &[(usize, u8, &[u8])]) -> Vec<u8> {
+        let mut b = vec![0; HEADER + SECTOR];
+        for &(i, ctype, data) in chunks {
+            let offset = b.len() / SECTOR;
+            b.extend_from_slice(&(data.len() as u32 + 1).to_be_bytes());
+            b.push(ctype);
+            b.extend_from_slice(data);
+            b.resize(b.len().next_multiple_of(SECTOR), 0);
+            let count = b.len() / SECTOR - offset;
+            b[i * 4..i * 4 + 4].copy_from_slice(&((offset as u32) << 8 | count as u32).to_be_bytes());
+            b[SECTOR + i * 4..SECTOR + i * 4 + 4].copy_from_slice(&(i as u32 + 1000).to_be_bytes());
+        }
+        b
+    }
+
+    fn minify(b: &[u8], cfg: RegionConfig) -> Vec<u8> {
+        let cfgmap = ConfigMap::default();
+        cfgmap.set::<MinifierRegion>(cfg);
+        let mut v = Vec::new();
+        cfgmap.fetch::<MinifierRegion>().minify(b, &mut v).unwrap();
+        v
+    }
+
+    /// Returns a sector offset, a sector count and a timestamp of every chunk in a region.
+    fn locations(b: &[u8]) -> Vec<(usize, u32, u32)> {
+        (0..CHUNKS).filter_map(|i| {
+            let loc = u32::from_be_bytes(b[i * 4..i * 4 + 4].try_into().unwrap());
+            let time = u32::from_be_bytes(b[SECTOR + i * 4..SECTOR + i * 4 + 4].try_into().unwrap());
+            (loc != 0).then_some((i, loc, time))
+        }).collect()
+    }
+
+    fn chunk_data(b: &[u8], loc: u32) -> (u8, &[u8]) {
+        super::chunk_at(b, loc).unwrap()
+    }
+
+    #[test]
+    fn sector_packing() {
+        // Pseudo-random data is not compressible, so it keeps its size (and two sectors)
+        let mut x = 1u32;
+        let noise = (0..6000).map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (x >> 16) as u8
+        }).collect::<Box<[u8]>>();
+        let large = chunk(vec![entry("data", Tag::ByteArray(noise))]);
+        let small = chunk(vec![entry("data", Tag::ByteArray(vec![0; 6000].into()))]);
+        let b = region(&[(40, RAW, &large), (3, RAW, &small)]);
+        let out = minify(&b, RegionConfig::default());
+        assert_eq!(out.len() % SECTOR, 0);
+        let locs = locations(&out);
+        // Chunks are written in the order of the header, without free sectors
+        assert_eq!(locs.iter().map(|&(i, loc, t)| (i, loc >> 8, loc & 0xff, t)).collect::<Vec<_>>(), [
+            (3, 2, 1, 1003),
+            (40, 3, 2, 1040)
+        ]);
+        assert_eq!(out.len(), 5 * SECTOR);
+        let (ctype, data) = chunk_data(&out, locs[0].1);
+        assert_eq!(ctype, ZLIB);
+        let mut nbt = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::bufread::ZlibDecoder::new(data), &mut nbt).unwrap();
+        assert_eq!(nbt, small);
+        assert_eq!(chunk_data(&out, locs[1].1), (RAW, &large[..]));
+    }
+
+    #[test]
+    fn unknown_chunks_are_kept() {
+        let external = [];
+        let lz4 = [1, 2, 3, 4, 5];
+        let b = region(&[(0, EXTERNAL | ZLIB, &external), (1, 4, &lz4), (2, 127, &lz4)]);
+        let out = minify(&b, RegionConfig { trim_uninhabited: true, ..RegionConfig::default() });
+        let locs = locations(&out);
+        assert_eq!(locs.len(), 3);
+        assert_eq!(chunk_data(&out, locs[0].1), (EXTERNAL | ZLIB, &[][..]));
+        assert_eq!(chunk_data(&out, locs[1].1), (4, &lz4[..]));
+        assert_eq!(chunk_data(&out, locs[2].1), (127, &lz4[..]));
+    }
+
+    #[test]
+    fn empty_and_uninhabited_chunks() {
+        let status = |s: &str| entry("Status", Tag::String(s.as_bytes().into()));
+        let level = |v| chunk(vec![entry("Level", Tag::Compound(v)), entry("DataVersion", Tag::Int(2586))]);
+        let chunks = [
+            // Pre-1.18 chunks (with the `Level` compound)
+            level(vec![status("empty"), entry("InhabitedTime", Tag::Long(0))]),
+            level(vec![status("full"), entry("InhabitedTime", Tag::Long(0))]),
+            level(vec![status("full"), entry("InhabitedTime", Tag::Long(500))]),
+            // 1.18+ chunks
+            chunk(vec![status("minecraft:empty"), entry("InhabitedTime", Tag::Long(0))]),
+            chunk(vec![status("minecraft:full"), entry("InhabitedTime", Tag::Long(0))]),
+            chunk(vec![status("minecraft:full"), entry("InhabitedTime", Tag::Long(500))]),
+            // Entity and POI chunks
+            chunk(vec![entry("Entities", Tag::List(Tag::END, Vec::new()))]),
+            chunk(vec![entry("Entities", Tag::List(Tag::COMPOUND, vec![Tag::Compound(Vec::new())]))]),
+            chunk(vec![entry("Sections", Tag::Compound(Vec::new()))]),
+        ];
+        let b = region(&chunks.iter().enumerate().map(|(i, c)| (i, RAW, &c[..])).collect::<Vec<_>>());
+        let kept = |cfg| locations(&minify(&b, cfg)).into_iter().map(|(i, ..)| i).collect::<Vec<_>>();
+        assert_eq!(kept(RegionConfig::default()), [1, 2, 4, 5, 7]);
+        assert_eq!(kept(RegionConfig { trim_uninhabited: true, ..RegionConfig::default() }), [2, 5, 7]);
+        assert_eq!(kept(RegionConfig { drop_empty: false, trim_uninhabited: true, ..RegionConfig::default() }), [2, 5, 6, 7, 8]);
+        assert_eq!(kept(RegionConfig { drop_empty: false, ..RegionConfig::default() }), (0..9).collect::<Vec<_>>());
+    }
+}
@@ -1,8 +1,8 @@
 #![cfg(feature = "toml")]
 
-use crate::cfg::{acfg, ConfigHolder};
+use crate::{cfg::{acfg, ConfigHolder}, errors::VerifyError, ext::KnownFmt};
 
-use super::Result_;
+use super::{compare, Result_};
 
 acfg!(
     /// A TOML minifier that accepts [`TOMLConfig`].
@@ -10,11 +10,7 @@ acfg!(
 );
 impl ConfigHolder<MinifierTOML> {
     pub(super) fn minify(&self, b: &[u8], vout: &mut Vec<u8>) -> Result_ {
-        let fv = std::str::from_utf8(b)?;
-        let mut table: toml::Table = toml::from_str(fv)?;
-        if self.strip_strings {
-            strip_toml_table(&mut table);
-        }
+        let table = self.decode(b)?;
         toml::to_string(&table)?.lines().for_each(|l| {
             match l.split_once(" = ") {
                 Some((k, v)) => {
@@ -28,6 +24,21 @@ impl ConfigHolder<MinifierTOML> {
         });
         Ok(())
     }
+
+    pub(super) fn verify(&self, b: &[u8], out: &[u8]) -> Result<(), VerifyError> {
+        let min = std::str::from_utf8(out).map_err(anyhow::Error::from)
+            .and_then(|s| toml::from_str::<toml::Table>(s).map_err(Into::into));
+        compare(KnownFmt::Toml, self.decode(b), min)
+    }
+
+    fn decode(&self, b: &[u8]) -> anyhow::Result<toml::Table> {
+        let fv = std::str::from_utf8(b)?;
+        let mut table: toml::Table = toml::from_str(fv)?;
+        if self.strip_strings {
+            strip_toml_table(&mut table);
+        }
+        Ok(table)
+    }
 }
 
 /// Configuration for TOML minifier
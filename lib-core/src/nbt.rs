@@ -0,0 +1,393 @@
+use std::{error::Error, fmt::Display};
+
+/// A maximum depth of nested lists and compounds (the same as in Minecraft).
+const MAX_DEPTH: u16 = 512;
+
+/// Entries of a compound tag. Their order is kept, so unchanged data is written back as it was read.
+pub type Compound = Vec<(Box<[u8]>, Tag)>;
+
+/// A byte order of numbers (including lengths) in NBT data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endian {
+    /// Big-endian, used by Java Edition
+    #[default]
+    Big,
+    /// Little-endian, used by files of Bedrock Edition (like `.mcstructure`)
+    Little
+}
+impl Endian {
+    /// Converts big-endian bytes to this byte order (or back).
+    fn swap<const N: usize>(self, mut a: [u8; N]) -> [u8; N] {
+        if self == Self::Little {
+            a.reverse();
+        }
+        a
+    }
+}
+
+/// A NBT tag. Strings and names are kept as bytes (Java uses modified UTF-8).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    /// A signed byte (also used as a boolean)
+    Byte(i8),
+    /// A signed 16-bit integer
+    Short(i16),
+    /// A signed 32-bit integer
+    Int(i32),
+    /// A signed 64-bit integer
+    Long(i64),
+    /// A 32-bit floating point number
+    Float(f32),
+    /// A 64-bit floating point number
+    Double(f64),
+    /// An array of bytes
+    ByteArray(Box<[u8]>),
+    /// A string
+    String(Box<[u8]>),
+    /// A list of tags with the same type (the first value is a type ID, which is kept for empty lists)
+    List(u8, Vec<Self>),
+    /// A compound of named tags
+    Compound(Compound),
+    /// An array of 32-bit integers
+    IntArray(Box<[i32]>),
+    /// An array of 64-bit integers
+    LongArray(Box<[i64]>)
+}
+impl Tag {
+    /// The type ID of the end tag. It is also used by empty lists.
+    pub const END: u8 = 0;
+    /// The type ID of a compound tag.
+    pub const COMPOUND: u8 = 10;
+
+    /// Returns the type ID.
+    #[must_use]
+    pub const fn id(&self) -> u8 {
+        match self {
+            Self::Byte(_) => 1,
+            Self::Short(_) => 2,
+            Self::Int(_) => 3,
+            Self::Long(_) => 4,
+            Self::Float(_) => 5,
+            Self::Double(_) => 6,
+            Self::ByteArray(_) => 7,
+            Self::String(_) => 8,
+            Self::List(..) => 9,
+            Self::Compound(_) => Self::COMPOUND,
+            Self::IntArray(_) => 11,
+            Self::LongArray(_) => 12
+        }
+    }
+
+    /// Returns entries if this is a compound.
+    #[must_use]
+    pub const fn as_compound(&self) -> Option<&Compound> {
+        if let Self::Compound(c) = self { Some(c) } else { None }
+    }
+
+    /// Returns mutable entries if this is a compound.
+    pub const fn as_compound_mut(&mut self) -> Option<&mut Compound> {
+        if let Self::Compound(c) = self { Some(c) } else { None }
+    }
+
+    /// Finds a tag by name if this is a compound.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&Self> {
+        self.as_compound()?.iter().find(|(k, _)| **k == *name.as_bytes()).map(|(_, v)| v)
+    }
+
+    /// Finds a mutable tag by name if this is a compound.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Self> {
+        self.as_compound_mut()?.iter_mut().find(|(k, _)| **k == *name.as_bytes()).map(|(_, v)| v)
+    }
+
+    /// Reads a named tag (usually a root compound) from uncompressed data. All data must be read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if data is invalid or has trailing bytes.
+    pub fn read_named(b: &[u8]) -> Result<(Box<[u8]>, Self), NBTReadError> {
+        Self::read_named_with(b, Endian::Big)
+    }
+
+    /// Reads a named tag from uncompressed data with a byte order. All data must be read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if data is invalid or has trailing bytes.
+    pub fn read_named_with(b: &[u8], e: Endian) -> Result<(Box<[u8]>, Self), NBTReadError> {
+        let mut r = Reader { b, i: 0, e };
+        let id = r.u8()?;
+        if id == Self::END {
+            return Err(NBTReadError::InvalidType(id));
+        }
+        let name = r.string()?;
+        let tag = r.payload(id, 0)?;
+        if r.i != b.len() {
+            return Err(NBTReadError::TrailingData(r.i));
+        }
+        Ok((name, tag))
+    }
+
+    /// Writes a named tag.
+    pub fn write_named(&self, name: &[u8], out: &mut Vec<u8>) {
+        self.write_named_with(name, out, Endian::Big);
+    }
+
+    /// Writes a named tag with a byte order.
+    pub fn write_named_with(&self, name: &[u8], out: &mut Vec<u8>, e: Endian) {
+        out.push(self.id());
+        write_string(name, out, e);
+        self.write_payload(out, e);
+    }
+
+    fn write_payload(&self, out: &mut Vec<u8>, e: Endian) {
+        match self {
+            Self::Byte(x) => out.extend_from_slice(&x.to_be_bytes()),
+            Self::Short(x) => out.extend_from_slice(&e.swap(x.to_be_bytes())),
+            Self::Int(x) => out.extend_from_slice(&e.swap(x.to_be_bytes())),
+            Self::Long(x) => out.extend_from_slice(&e.swap(x.to_be_bytes())),
+            Self::Float(x) => out.extend_from_slice(&e.swap(x.to_be_bytes())),
+            Self::Double(x) => out.extend_from_slice(&e.swap(x.to_be_bytes())),
+            Self::ByteArray(a) => {
+                write_len(a.len(), out, e);
+                out.extend_from_slice(a);
+            }
+            Self::String(s) => write_string(s, out, e),
+            Self::List(id, v) => {
+                out.push(*id);
+                write_len(v.len(), out, e);
+                for t in v {
+                    t.write_payload(out, e);
+                }
+            }
+            Self::Compound(c) => {
+                for (k, t) in c {
+                    t.write_named_with(k, out, e);
+                }
+                out.push(Self::END);
+            }
+            Self::IntArray(a) => {
+                write_len(a.len(), out, e);
+                a.iter().for_each(|x| out.extend_from_slice(&e.swap(x.to_be_bytes())));
+            }
+            Self::LongArray(a) => {
+                write_len(a.len(), out, e);
+                a.iter().for_each(|x| out.extend_from_slice(&e.swap(x.to_be_bytes())));
+            }
+        }
+    }
+}
+
+fn write_len(len: usize, out: &mut Vec<u8>, e: Endian) {
+    out.extend_from_slice(&e.swap((len as i32).to_be_bytes()));
+}
+
+fn write_string(s: &[u8], out: &mut Vec<u8>, e: Endian) {
+    out.extend_from_slice(&e.swap((s.len() as u16).to_be_bytes()));
+    out.extend_from_slice(s);
+}
+
+struct Reader<'a> {
+    b: &'a [u8],
+    i: usize,
+    e: Endian
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], NBTReadError> {
+        let s = self.b.get(self.i..self.i.checked_add(n).ok_or(NBTReadError::Eof)?).ok_or(NBTReadError::Eof)?;
+        self.i += n;
+        Ok(s)
+    }
+    /// Reads a number as big-endian bytes.
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], NBTReadError> {
+        Ok(self.e.swap(self.take(N)?.try_into().unwrap_or([0; N])))
+    }
+    fn u8(&mut self) -> Result<u8, NBTReadError> {
+        Ok(self.array::<1>()?[0])
+    }
+    fn len(&mut self) -> Result<usize, NBTReadError> {
+        let n = i32::from_be_bytes(self.array()?);
+        usize::try_from(n).map_err(|_| NBTReadError::InvalidLength(n))
+    }
+    fn string(&mut self) -> Result<Box<[u8]>, NBTReadError> {
+        let n = u16::from_be_bytes(self.array()?);
+        Ok(self.take(n as usize)?.into())
+    }
+    fn numbers<const N: usize, T>(&mut self, f: fn([u8; N]) -> T) -> Result<Box<[T]>, NBTReadError> {
+        let n = self.len()?;
+        let b = self.take(n.checked_mul(N).ok_or(NBTReadError::Eof)?)?;
+        Ok(b.chunks_exact(N).map(|c| f(self.e.swap(c.try_into().unwrap_or([0; N])))).collect())
+    }
+    fn payload(&mut self, id: u8, depth: u16) -> Result<Tag, NBTReadError> {
+        Ok(match id {
+            1 => Tag::Byte(i8::from_be_bytes(self.array()?)),
+            2 => Tag::Short(i16::from_be_bytes(self.array()?)),
+            3 => Tag::Int(i32::from_be_bytes(self.array()?)),
+            4 => Tag::Long(i64::from_be_bytes(self.array()?)),
+            5 => Tag::Float(f32::from_be_bytes(self.array()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.array()?)),
+            7 => {
+                let n = self.len()?;
+                Tag::ByteArray(self.take(n)?.into())
+            }
+            8 => Tag::String(self.string()?),
+            9 => {
+                let depth = deeper(depth)?;
+                let eid = self.u8()?;
+                let n = self.len()?;
+                if eid == Tag::END && n > 0 {
+                    return Err(NBTReadError::InvalidType(eid));
+                }
+                // Each element takes at least one byte, so the capacity is limited by remaining data
+                let mut v = Vec::with_capacity(n.min(self.b.len() - self.i));
+                for _ in 0..n {
+                    v.push(self.payload(eid, depth)?);
+                }
+                Tag::List(eid, v)
+            }
+            10 => {
+                let depth = deeper(depth)?;
+                let mut c = Compound::new();
+                loop {
+                    let id = self.u8()?;
+                    if id == Tag::END {
+                        break;
+                    }
+                    let name = self.string()?;
+                    c.push((name, self.payload(id, depth)?));
+                }
+                Tag::Compound(c)
+            }
+            11 => Tag::IntArray(self.numbers(i32::from_be_bytes)?),
+            12 => Tag::LongArray(self.numbers(i64::from_be_bytes)?),
+            _ => return Err(NBTReadError::InvalidType(id))
+        })
+    }
+}
+
+const fn deeper(depth: u16) -> Result<u16, NBTReadError> {
+    if depth >= MAX_DEPTH { Err(NBTReadError::TooDeep) } else { Ok(depth + 1) }
+}
+
+/// An error that occurs when NBT data cannot be read
+#[derive(Debug)]
+pub enum NBTReadError {
+    /// Data ends before a tag is complete
+    Eof,
+    /// A tag type is unknown (or not allowed)
+    InvalidType(u8),
+    /// A length of an array or a list is negative
+    InvalidLength(i32),
+    /// Lists and compounds are nested too deeply
+    TooDeep,
+    /// Data has more bytes after the root tag (at a position)
+    TrailingData(usize)
+}
+impl Error for NBTReadError {}
+impl Display for NBTReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Eof => f.write_str("unexpected end of data"),
+            Self::InvalidType(id) => write!(f, "invalid tag type {id}"),
+            Self::InvalidLength(n) => write!(f, "invalid length {n}"),
+            Self::TooDeep => f.write_str("lists and compounds are nested too deeply"),
+            Self::TrailingData(i) => write!(f, "trailing data at byte {i}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, t: Tag) -> (Box<[u8]>, Tag) {
+        (name.as_bytes().into(), t)
+    }
+
+    /// A compound with every tag type (and lists with different element types).
+    fn all_types() -> Tag {
+        Tag::Compound(vec![
+            entry("byte", Tag::Byte(-1)),
+            entry("short", Tag::Short(-300)),
+            entry("int", Tag::Int(70000)),
+            entry("long", Tag::Long(-1 << 40)),
+            entry("float", Tag::Float(1.5)),
+            entry("double", Tag::Double(-0.25)),
+            entry("bytes", Tag::ByteArray([1, 2, 255].into())),
+            entry("string", Tag::String((*b"\xc0\x80text").into())),
+            entry("list", Tag::List(3, vec![Tag::Int(1), Tag::Int(2)])),
+            entry("empty_list", Tag::List(8, Vec::new())),
+            entry("end_list", Tag::List(Tag::END, Vec::new())),
+            entry("compounds", Tag::List(Tag::COMPOUND, vec![Tag::Compound(vec![entry("x", Tag::Byte(1))]), Tag::Compound(Vec::new())])),
+            entry("ints", Tag::IntArray([-1, 0, 1].into())),
+            entry("longs", Tag::LongArray([i64::MIN, i64::MAX].into())),
+        ])
+    }
+
+    #[test]
+    fn read_write_identity() {
+        for e in [Endian::Big, Endian::Little] {
+            let mut b = Vec::new();
+            all_types().write_named_with(b"root", &mut b, e);
+            let (name, tag) = Tag::read_named_with(&b, e).unwrap();
+            assert_eq!(&*name, b"root");
+            assert_eq!(tag, all_types());
+            let mut out = Vec::new();
+            tag.write_named_with(&name, &mut out, e);
+            assert_eq!(out, b);
+        }
+    }
+
+    #[test]
+    fn byte_order() {
+        let b = [10, 0, 0, 3, 0, 1, b'x', 0, 0, 0, 7, 0];
+        let (_, tag) = Tag::read_named(&b).unwrap();
+        assert_eq!(tag.get("x"), Some(&Tag::Int(7)));
+        let b = [10, 0, 0, 3, 1, 0, b'x', 7, 0, 0, 0, 0];
+        let (_, tag) = Tag::read_named_with(&b, Endian::Little).unwrap();
+        assert_eq!(tag.get("x"), Some(&Tag::Int(7)));
+    }
+
+    #[test]
+    fn invalid_lengths_and_types() {
+        // A byte array with a negative length
+        let b = [10, 0, 0, 7, 0, 1, b'a', 255, 255, 255, 255, 0];
+        assert!(matches!(Tag::read_named(&b), Err(NBTReadError::InvalidLength(-1))));
+        // A list of end tags with elements
+        let b = [10, 0, 0, 9, 0, 1, b'a', 0, 0, 0, 0, 2, 0];
+        assert!(matches!(Tag::read_named(&b), Err(NBTReadError::InvalidType(0))));
+        // A list of end tags without elements is valid
+        let b = [10, 0, 0, 9, 0, 1, b'a', 0, 0, 0, 0, 0, 0];
+        assert!(Tag::read_named(&b).is_ok());
+        assert!(matches!(Tag::read_named(&[0]), Err(NBTReadError::InvalidType(0))));
+        assert!(matches!(Tag::read_named(&[10, 0, 0, 13, 0, 0]), Err(NBTReadError::InvalidType(13))));
+        assert!(matches!(Tag::read_named(&[10, 0, 0, 3, 0, 1, b'a', 0]), Err(NBTReadError::Eof)));
+    }
+
+    #[test]
+    fn max_depth() {
+        let nested = |n: u16| {
+            let mut t = Tag::List(Tag::END, Vec::new());
+            for _ in 1..n {
+                t = Tag::List(9, vec![t]);
+            }
+            Tag::Compound(vec![entry("l", t)])
+        };
+        let mut b = Vec::new();
+        // The root compound is also counted
+        nested(MAX_DEPTH - 1).write_named(b"", &mut b);
+        assert!(Tag::read_named(&b).is_ok());
+        b.clear();
+        nested(MAX_DEPTH).write_named(b"", &mut b);
+        assert!(matches!(Tag::read_named(&b), Err(NBTReadError::TooDeep)));
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut b = Vec::new();
+        all_types().write_named(b"", &mut b);
+        let len = b.len();
+        b.push(0);
+        assert!(matches!(Tag::read_named(&b), Err(NBTReadError::TrailingData(i)) if i == len));
+    }
+}
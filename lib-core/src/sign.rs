@@ -0,0 +1,493 @@
+use std::{error::Error, fmt::Display, io::{Read, Seek}};
+
+use crate::{Result_, entry::ZipEntryReader, errors::ErrorCollector};
+
+pub(crate) const MANIFEST: &str = "META-INF/MANIFEST.MF";
+
+/// A policy for signed JAR archives.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-cfg", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum SignedJarPolicy {
+    /// Keep signed archives untouched.
+    Skip,
+    /// Remove signature files and entry digests from the manifest, so the archive becomes unsigned.
+    #[default]
+    Strip,
+    /// Fail repacking signed archives.
+    Fail
+}
+impl SignedJarPolicy {
+    /// Checks signatures of an archive and applies the policy. Signers are reported to `ev`.
+    /// Returns `false` if the archive should be kept untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the signatures are invalid (unless the archive is kept) or if signed archives should fail.
+    pub fn apply<R: Read + Seek>(self, zr: &mut ZipEntryReader<R>, ev: &mut ErrorCollector) -> Result_<bool> {
+        let js = match zr.signature() {
+            Ok(Some(js)) => js,
+            Ok(None) => return Ok(true),
+            // Kept archives do not need valid signatures
+            Err(e) if self == Self::Skip => {
+                ev.collect(MANIFEST, e);
+                return Ok(false);
+            }
+            Err(e) => return Err(e)
+        };
+        for s in js.signers {
+            if let Some(e) = s.unverified {
+                ev.collect(s.name.clone(), e.into());
+            }
+            match self {
+                Self::Skip => ev.collect(s.name, SignError::Kept(s.subject).into()),
+                Self::Strip => ev.collect(s.name, SignError::Stripped(s.subject).into()),
+                Self::Fail => return Err(SignError::Signed(s.subject).into())
+            }
+        }
+        match self {
+            Self::Skip => Ok(false),
+            _ => {
+                zr.strip_signature();
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Signers found in a JAR archive.
+pub struct JarSignature {
+    /// A list of signers
+    pub signers: Vec<JarSigner>
+}
+
+/// A signer of a JAR archive.
+pub struct JarSigner {
+    /// A signature file name (`META-INF/*.SF`)
+    pub name: Box<str>,
+    /// A subject of the signer certificate (if it was verified)
+    pub subject: Option<Box<str>>,
+    /// A reason why the signature block could not be verified (for example, an unsupported algorithm)
+    pub unverified: Option<SignError>
+}
+
+/// Checks if a file name is a part of a JAR signature.
+pub(crate) fn is_signature_file(name: &str) -> bool {
+    name.strip_prefix("META-INF/").is_some_and(|x| {
+        !x.contains('/') && (x.starts_with("SIG-") || [".DSA", ".RSA", ".EC", ".SF"].into_iter().any(|e| x.ends_with(e)))
+    })
+}
+
+/// Finds signers of an archive (by its entry names) and verifies them. Data of entries is loaded by `read`.
+pub(crate) fn find_signers(
+    names: &[&str],
+    mut read: impl FnMut(&str) -> Result_<Option<Vec<u8>>>
+) -> Result_<Option<JarSignature>> {
+    let sfs: Vec<&str> = names.iter().copied()
+        .filter(|n| is_signature_file(n) && n.to_ascii_uppercase().ends_with(".SF"))
+        .collect();
+    if sfs.is_empty() {
+        return Ok(None);
+    }
+    let Some(mf) = read(MANIFEST)? else {
+        return Err(SignError::MissingManifest.into());
+    };
+    let mut signers = Vec::with_capacity(sfs.len());
+    for sf_name in sfs {
+        let Some(sf) = read(sf_name)? else {
+            continue;
+        };
+        let stem = &sf_name[..sf_name.len() - 3];
+        let Some(block_name) = ["RSA", "DSA", "EC"].into_iter()
+            .map(|e| format!("{stem}.{e}"))
+            .find(|n| names.contains(&n.as_str())) else {
+            return Err(SignError::MissingBlock.into());
+        };
+        let Some(block) = read(&block_name)? else {
+            return Err(SignError::MissingBlock.into());
+        };
+        let (subject, unverified) = match verify::verify_block(&block, &sf) {
+            Ok(subject) => (subject, None),
+            Err(e @ SignError::Unsupported(_)) => (None, Some(e)),
+            Err(e) => return Err(e.into())
+        };
+        verify_sf(&sf, &mf)?;
+        signers.push(JarSigner { name: sf_name.into(), subject, unverified });
+    }
+    verify_entries(&mf, read)?;
+    Ok(Some(JarSignature { signers }))
+}
+
+/// Checks digests of the manifest (or its sections) listed in a signature file.
+fn verify_sf(sf: &[u8], mf: &[u8]) -> Result_<()> {
+    let sf_sections = sections(sf);
+    let Some((sf_main, sf_entries)) = sf_sections.split_first() else {
+        return Err(SignError::DigestMismatch(MANIFEST.into()).into());
+    };
+    let whole = sf_main.digests("-Digest-Manifest").collect::<Vec<_>>();
+    if !whole.is_empty() && whole.iter().all(|(alg, v)| digest_eq(alg, mf, v)) {
+        return Ok(());
+    }
+    let mf_sections = sections(mf);
+    for s in sf_entries {
+        let Some(name) = s.name() else {
+            continue;
+        };
+        let Some(ms) = mf_sections.iter().find(|ms| ms.name().as_deref() == Some(&name)) else {
+            return Err(SignError::DigestMismatch(name.into()).into());
+        };
+        if !s.digests("-Digest").all(|(alg, v)| digest_eq(alg, ms.raw, v)) {
+            return Err(SignError::DigestMismatch(name.into()).into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks digests of entries listed in the manifest. Missing entries are skipped.
+fn verify_entries(mf: &[u8], mut read: impl FnMut(&str) -> Result_<Option<Vec<u8>>>) -> Result_<()> {
+    for s in sections(mf).iter().skip(1) {
+        let Some(name) = s.name() else {
+            continue;
+        };
+        let mut digests = s.digests("-Digest").peekable();
+        if digests.peek().is_none() {
+            continue;
+        }
+        let Some(data) = read(&name)? else {
+            continue;
+        };
+        if !digests.all(|(alg, v)| digest_eq(alg, &data, v)) {
+            return Err(SignError::DigestMismatch(name.into()).into());
+        }
+    }
+    Ok(())
+}
+
+/// Removes digests of entries from a manifest. Sections without any other attributes are removed.
+pub(crate) fn strip_manifest(mf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(mf.len());
+    for (i, s) in sections(mf).iter().enumerate() {
+        if i == 0 {
+            out.extend_from_slice(s.raw);
+            continue;
+        }
+        let kept: Vec<&Attr> = s.attrs.iter().filter(|a| !is_digest_attr(&a.name)).collect();
+        if kept.iter().all(|a| a.name.eq_ignore_ascii_case("Name")) {
+            continue;
+        }
+        for a in kept {
+            out.extend_from_slice(a.raw);
+        }
+        out.extend_from_slice(s.end);
+    }
+    out
+}
+
+fn is_digest_attr(name: &str) -> bool {
+    name.len() > 7 && name.is_char_boundary(name.len() - 7) && name[name.len() - 7..].eq_ignore_ascii_case("-Digest")
+}
+
+/// A manifest attribute with its raw lines.
+struct Attr<'a> {
+    name: String,
+    value: Vec<u8>,
+    raw: &'a [u8]
+}
+
+/// A manifest section with its raw bytes (including the ending empty line).
+struct Section<'a> {
+    raw: &'a [u8],
+    attrs: Vec<Attr<'a>>,
+    end: &'a [u8]
+}
+impl Section<'_> {
+    fn name(&self) -> Option<String> {
+        self.attrs.iter()
+            .find(|a| a.name.eq_ignore_ascii_case("Name"))
+            .map(|a| String::from_utf8_lossy(&a.value).into_owned())
+    }
+    /// Returns pairs of algorithms and digests for attribute names with a suffix.
+    fn digests<'s>(&'s self, suffix: &'s str) -> impl Iterator<Item = (&'s str, &'s [u8])> {
+        self.attrs.iter().filter_map(move |a| {
+            let n = a.name.len().checked_sub(suffix.len())?;
+            (a.name.is_char_boundary(n) && a.name[n..].eq_ignore_ascii_case(suffix))
+                .then(|| (&a.name[..n], a.value.trim_ascii()))
+                .filter(|(alg, _)| digest_of(alg, &[]).is_some())
+        })
+    }
+}
+
+fn sections(mf: &[u8]) -> Vec<Section<'_>> {
+    let mut v = Vec::new();
+    let mut start = 0;
+    let mut attrs: Vec<Attr> = Vec::new();
+    let mut pos = 0;
+    for l in mf.split_inclusive(|&b| b == b'\n') {
+        let lstart = pos;
+        pos += l.len();
+        let content = l.strip_suffix(b"\n").unwrap_or(l);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if content.is_empty() {
+            if !attrs.is_empty() {
+                v.push(Section { raw: &mf[start..pos], attrs: std::mem::take(&mut attrs), end: &mf[lstart..pos] });
+            }
+            start = pos;
+        } else if let Some(cont) = content.strip_prefix(b" ") {
+            if let Some(a) = attrs.last_mut() {
+                a.value.extend_from_slice(cont);
+                a.raw = &mf[pos - a.raw.len() - l.len()..pos];
+            }
+        } else {
+            let (name, value) = content.iter().position(|&b| b == b':')
+                .map_or((content, &[][..]), |i| (&content[..i], &content[i + 1..]));
+            attrs.push(Attr {
+                name: String::from_utf8_lossy(name).into_owned(),
+                value: value.strip_prefix(b" ").unwrap_or(value).to_vec(),
+                raw: &mf[lstart..pos]
+            });
+        }
+    }
+    if !attrs.is_empty() {
+        v.push(Section { raw: &mf[start..], attrs, end: b"" });
+    }
+    v
+}
+
+fn digest_eq(alg: &str, data: &[u8], expected: &[u8]) -> bool {
+    digest_of(alg, data).is_some_and(|d| base64(&d).as_bytes() == expected)
+}
+
+/// Computes a digest of data with an algorithm named in a manifest.
+fn digest_of(alg: &str, data: &[u8]) -> Option<Vec<u8>> {
+    use sha1::Digest;
+    Some(match alg.to_ascii_uppercase().as_str() {
+        "SHA1" | "SHA-1" => sha1::Sha1::digest(data).to_vec(),
+        "SHA-256" => sha2::Sha256::digest(data).to_vec(),
+        "SHA-384" => sha2::Sha384::digest(data).to_vec(),
+        "SHA-512" => sha2::Sha512::digest(data).to_vec(),
+        _ => return None
+    })
+}
+
+fn base64(b: &[u8]) -> String {
+    const CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut s = String::with_capacity(b.len().div_ceil(3) * 4);
+    for c in b.chunks(3) {
+        let n = (u32::from(c[0]) << 16) | (u32::from(*c.get(1).unwrap_or(&0)) << 8) | u32::from(*c.get(2).unwrap_or(&0));
+        for i in 0..4 {
+            if i <= c.len() {
+                s.push(CHARS[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+#[cfg(feature = "jar-sign")]
+mod verify {
+    use cms::{
+        cert::{CertificateChoices, x509::der::{Decode, Encode, asn1::ObjectIdentifier}},
+        content_info::ContentInfo,
+        signed_data::{SignedData, SignerIdentifier},
+    };
+    use rsa::{Pkcs1v15Sign, RsaPublicKey, pkcs8::DecodePublicKey};
+    use sha1::Digest;
+
+    use super::SignError;
+
+    const MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+    const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+    const SHA_1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.14.3.2.26");
+    const SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+    const SHA_384: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.2");
+    const SHA_512: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.3");
+
+    /// Verifies a PKCS#7 signature block of a signature file. Returns the subject of the signer certificate.
+    /// The certificate is not checked against any trust store.
+    pub fn verify_block(block: &[u8], sf: &[u8]) -> Result<Option<Box<str>>, SignError> {
+        let ci = ContentInfo::from_der(block).map_err(|_| SignError::BadSignature)?;
+        let sd = ci.content.decode_as::<SignedData>().map_err(|_| SignError::BadSignature)?;
+        let Some(si) = sd.signer_infos.0.iter().next() else {
+            return Err(SignError::BadSignature);
+        };
+        let SignerIdentifier::IssuerAndSerialNumber(isn) = &si.sid else {
+            return Err(SignError::Unsupported("signer identifier"));
+        };
+        let Some(cert) = sd.certificates.iter().flat_map(|cs| cs.0.iter()).find_map(|cc| match cc {
+            CertificateChoices::Certificate(c) if c.tbs_certificate.issuer == isn.issuer
+                && c.tbs_certificate.serial_number == isn.serial_number => Some(c),
+            _ => None
+        }) else {
+            return Err(SignError::BadSignature);
+        };
+        let dalg = si.digest_alg.oid;
+        let sf_digest = digest_by_oid(dalg, sf)?;
+        let msg_digest = match &si.signed_attrs {
+            Some(attrs) => {
+                let Some(md) = attrs.iter().find(|a| a.oid == MESSAGE_DIGEST).and_then(|a| a.values.iter().next()) else {
+                    return Err(SignError::BadSignature);
+                };
+                if md.value() != sf_digest.as_slice() {
+                    return Err(SignError::BadSignature);
+                }
+                digest_by_oid(dalg, &attrs.to_der().map_err(|_| SignError::BadSignature)?)?
+            }
+            None => sf_digest
+        };
+        let spki = &cert.tbs_certificate.subject_public_key_info;
+        if spki.algorithm.oid != RSA_ENCRYPTION {
+            return Err(SignError::Unsupported("signature algorithm"));
+        }
+        let key = spki.to_der().ok()
+            .and_then(|d| RsaPublicKey::from_public_key_der(&d).ok())
+            .ok_or(SignError::BadSignature)?;
+        let scheme = match dalg {
+            SHA_1 => Pkcs1v15Sign::new::<sha1::Sha1>(),
+            SHA_256 => Pkcs1v15Sign::new::<sha2::Sha256>(),
+            SHA_384 => Pkcs1v15Sign::new::<sha2::Sha384>(),
+            _ => Pkcs1v15Sign::new::<sha2::Sha512>(),
+        };
+        key.verify(scheme, &msg_digest, si.signature.as_bytes()).map_err(|_| SignError::BadSignature)?;
+        Ok(Some(cert.tbs_certificate.subject.to_string().into()))
+    }
+
+    fn digest_by_oid(oid: ObjectIdentifier, data: &[u8]) -> Result<Vec<u8>, SignError> {
+        Ok(match oid {
+            SHA_1 => sha1::Sha1::digest(data).to_vec(),
+            SHA_256 => sha2::Sha256::digest(data).to_vec(),
+            SHA_384 => sha2::Sha384::digest(data).to_vec(),
+            SHA_512 => sha2::Sha512::digest(data).to_vec(),
+            _ => return Err(SignError::Unsupported("digest algorithm"))
+        })
+    }
+}
+
+#[cfg(not(feature = "jar-sign"))]
+mod verify {
+    use super::SignError;
+
+    /// Signature blocks cannot be verified without the `jar-sign` feature.
+    pub const fn verify_block(_block: &[u8], _sf: &[u8]) -> Result<Option<Box<str>>, SignError> {
+        Ok(None)
+    }
+}
+
+/// An error (or a notice) about a signed JAR archive.
+#[derive(Debug)]
+pub enum SignError {
+    /// The archive is signed and the policy does not allow repacking it.
+    Signed(Option<Box<str>>),
+    /// The archive is signed and kept untouched.
+    Kept(Option<Box<str>>),
+    /// The signature was removed.
+    Stripped(Option<Box<str>>),
+    /// The archive has signature files, but no manifest.
+    MissingManifest,
+    /// A signature file has no matching signature block.
+    MissingBlock,
+    /// A digest of an entry (or a manifest) is different than expected.
+    DigestMismatch(Box<str>),
+    /// A signature block is invalid or does not match its signature file.
+    BadSignature,
+    /// A signature uses an unsupported format.
+    Unsupported(&'static str)
+}
+impl Error for SignError {}
+impl Display for SignError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn signer(s: Option<&str>) -> &str {
+            s.unwrap_or("unknown signer")
+        }
+        match self {
+            Self::Signed(s) => write!(f, "archive is signed by {}", signer(s.as_deref())),
+            Self::Kept(s) => write!(f, "signed by {}, archive kept untouched", signer(s.as_deref())),
+            Self::Stripped(s) => write!(f, "signed by {}, signature removed", signer(s.as_deref())),
+            Self::MissingManifest => f.write_str("manifest not found"),
+            Self::MissingBlock => f.write_str("signature block not found"),
+            Self::DigestMismatch(n) => write!(f, "digest mismatch for {n}"),
+            Self::BadSignature => f.write_str("invalid signature"),
+            Self::Unsupported(x) => write!(f, "unsupported {x}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MF: &[u8] = b"Manifest-Version: 1.0\r\nCreated-By: test\r\n\r\n\
+        Name: a/x.txt\r\nSHA-256-Digest: AAAA\r\n\r\n\
+        Name: assets/a_very_long_directory_name/with_another_long_directory_name/and_a_texture_name.p\r\n ng\r\n\
+        SHA-256-Digest: BBBB\r\nX-Custom: 1\r\n\r\n";
+
+    fn sha256(data: &[u8]) -> String {
+        base64(&digest_of("SHA-256", data).unwrap())
+    }
+
+    #[test]
+    fn manifest_sections() {
+        let s = sections(MF);
+        assert_eq!(s.len(), 3);
+        assert_eq!(s[0].raw, b"Manifest-Version: 1.0\r\nCreated-By: test\r\n\r\n");
+        assert_eq!(s[1].name().as_deref(), Some("a/x.txt"));
+        assert_eq!(s[2].name().as_deref(), Some("assets/a_very_long_directory_name/with_another_long_directory_name/and_a_texture_name.png"));
+        assert_eq!(s[2].digests("-Digest").collect::<Vec<_>>(), [("SHA-256", &b"BBBB"[..])]);
+        assert_eq!(s[2].end, b"\r\n");
+        assert_eq!(s.iter().map(|s| s.raw.len()).sum::<usize>(), MF.len());
+        // Sections without a trailing empty line (and with LF line endings)
+        let s = sections(b"Manifest-Version: 1.0\n\nName: a\nSHA1-Digest: x");
+        assert_eq!(s.len(), 2);
+        assert_eq!(s[1].end, b"");
+    }
+
+    #[test]
+    fn strip_digests() {
+        let out = strip_manifest(MF);
+        assert_eq!(out, b"Manifest-Version: 1.0\r\nCreated-By: test\r\n\r\n\
+            Name: assets/a_very_long_directory_name/with_another_long_directory_name/and_a_texture_name.p\r\n ng\r\n\
+            X-Custom: 1\r\n\r\n");
+        assert_eq!(strip_manifest(b"Manifest-Version: 1.0\n\nName: a\nSHA1-Digest: x\n\n"), b"Manifest-Version: 1.0\n\n");
+    }
+
+    #[test]
+    fn signature_file_digests() {
+        let sf = format!("Signature-Version: 1.0\r\nSHA-256-Digest-Manifest: {}\r\n\r\n", sha256(MF));
+        assert!(verify_sf(sf.as_bytes(), MF).is_ok());
+        // Digests of sections are checked if the whole manifest does not match
+        let sec = sections(MF)[1].raw;
+        let sf = format!("Signature-Version: 1.0\r\nSHA-256-Digest-Manifest: AAAA\r\n\r\nName: a/x.txt\r\nSHA-256-Digest: {}\r\n\r\n", sha256(sec));
+        assert!(verify_sf(sf.as_bytes(), MF).is_ok());
+        let sf = "Signature-Version: 1.0\r\nSHA-256-Digest-Manifest: AAAA\r\n\r\nName: a/x.txt\r\nSHA-256-Digest: AAAA\r\n\r\n";
+        assert!(verify_sf(sf.as_bytes(), MF).is_err());
+        let sf = format!("Signature-Version: 1.0\r\n\r\nName: b.txt\r\nSHA-256-Digest: {}\r\n\r\n", sha256(sec));
+        assert!(verify_sf(sf.as_bytes(), MF).is_err());
+    }
+
+    #[test]
+    fn signature_files() {
+        for n in ["META-INF/CERT.SF", "META-INF/CERT.RSA", "META-INF/A.DSA", "META-INF/A.EC", "META-INF/SIG-X"] {
+            assert!(is_signature_file(n), "{n}");
+        }
+        for n in ["META-INF/MANIFEST.MF", "META-INF/sub/CERT.SF", "CERT.SF", "META-INF/SF", "assets/META-INF/A.RSA"] {
+            assert!(!is_signature_file(n), "{n}");
+        }
+    }
+
+    #[cfg(feature = "jar-sign")]
+    #[test]
+    fn unverified_signer() {
+        let data = include_bytes!("../tests/jars/signed-ec.jar");
+        let mut zr = ZipEntryReader::new_mem(&data[..]).unwrap();
+        let signers = zr.signature().unwrap().unwrap().signers;
+        assert_eq!(signers.len(), 1);
+        assert!(signers[0].subject.is_none());
+        assert!(matches!(signers[0].unverified, Some(SignError::Unsupported(_))));
+
+        let mut ev = ErrorCollector::new(false);
+        assert!(!SignedJarPolicy::Skip.apply(&mut zr, &mut ev).unwrap());
+        assert_eq!(ev.results().len(), 2);
+        assert!(SignedJarPolicy::Strip.apply(&mut zr, &mut ev).unwrap());
+        assert!(SignedJarPolicy::Fail.apply(&mut zr, &mut ev).is_err());
+    }
+}
@@ -0,0 +1,19 @@
+import java.lang.annotation.*;
+import java.util.function.*;
+
+public class Consts {
+    @Target({ElementType.TYPE_USE}) @Retention(RetentionPolicy.RUNTIME) @interface Tag { String value() default "x"; }
+
+    static final long BIG = 0x123456789ABCDEFL;
+    static final double PI2 = 6.283185307179586;
+    long l = 9_000_000_000L;
+    double d = 2.5e300;
+    float f = 3.5f;
+
+    public @Tag String mix(long a, double b) {
+        long x = a * 1234567890123L + BIG;
+        double y = b / 0.123456789 + PI2;
+        Supplier<@Tag String> s = () -> "lambda " + x + y;
+        return s.get() + l + d + f;
+    }
+}
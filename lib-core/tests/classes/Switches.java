@@ -0,0 +1,9 @@
+public class Switches {
+    static int s0(int k) { switch (k) { case 1: return 10; case 2: return 20; case 3: return 30; case 4: return 45; default: return -1; } }
+    static int s1(int a, int k) { a++; switch (k) { case 1: return a; case 2: return a * 2; case 3: return a * 3; case 4: return a + 7; default: return 0; } }
+    static int s2(int a, int k) { a += 200; switch (k) { case 5: return a; case 6: return a * 2; case 7: return a * 3; case 8: return 9; default: return 0; } }
+    static int s3(int a, int b, int k) { a = a + b; switch (k) { case 1: return a; case 2: return b; case 3: return a * b; case 4: return 11; default: return 0; } }
+    static int s4(int k) { switch (-k) { case 1: return 3; case 2: return 5; case 3: return 7; case 4: return 9; default: return 0; } }
+    static int lookup(int k) { switch (k) { case 1: return 1; case 1000: return 2; case 100000: return 3; default: return 0; } }
+    static String strings(String s) { switch (s) { case "a": return "A"; case "bb": return "B"; default: return "?"; } }
+}
@@ -0,0 +1,110 @@
+//! Repacked archives must be byte-identical for the same input, regardless of the thread count.
+
+use std::io::{Cursor, Read, Write};
+
+use mc_repack_core::{
+    cfg::ConfigMap,
+    entry::{EntryReader, EntrySaver, ZipEntryReader, ZipEntrySaver, zip::EntryMtime},
+    errors::ErrorCollector,
+    fop::Rules,
+    min::jar::NestedMtime,
+};
+use zip::{DateTime, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+const EPOCH: u64 = 1_700_000_000;
+
+fn write_zip(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut zw = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in entries {
+        if name.ends_with('/') {
+            zw.add_directory(*name, SimpleFileOptions::default()).unwrap();
+        } else {
+            zw.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zw.write_all(data).unwrap();
+        }
+    }
+    zw.finish().unwrap().into_inner()
+}
+
+fn sample_jar() -> Vec<u8> {
+    let nested = write_zip(&[
+        ("META-INF/MANIFEST.MF", b"Manifest-Version: 1.0\r\n\r\n".to_vec()),
+        ("assets/nested.json", b"{ \"nested\" : [ 1, 2, 3 ] }".to_vec()),
+    ]);
+    let mut entries = vec![
+        ("assets/", Vec::new()),
+        ("assets/broken.json", b"{ \"a\": 1, }".to_vec()),
+        ("pack.mcmeta", b"{\n  \"pack\": {\n    \"pack_format\": 15\n  }\n}".to_vec()),
+        ("META-INF/jars/nested.jar", nested),
+        ("META-INF/MANIFEST.MF", b"Manifest-Version: 1.0\r\nCreated-By: test\r\n\r\n".to_vec()),
+        ("config.toml", b"# comment\n[section]\nkey = \"value\"\n".to_vec()),
+    ];
+    let names: Vec<String> = (0..200).map(|i| format!("data/{}/entry_{i}.json", i % 7)).collect();
+    for (i, name) in names.iter().enumerate() {
+        entries.push((name, format!("{{ \"index\" : {i}, \"values\" : [ {i}, {}, {} ] }}", i * 2, i * 3).into_bytes()));
+    }
+    write_zip(&entries)
+}
+
+fn repack(input: &[u8], threads: usize) -> Vec<u8> {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+    pool.install(|| {
+        let cfgmap = ConfigMap::default();
+        cfgmap.set::<NestedMtime>(EntryMtime::Fixed(Some(EPOCH)));
+        let rules = Rules::default();
+        let mut ev = ErrorCollector::new(false);
+        let mut entries = Vec::new();
+        ZipEntryReader::new_mem(input)
+            .unwrap()
+            .read_entries(|ne| {
+                entries.push(ne);
+                Ok(())
+            }, &rules)
+            .unwrap();
+        let mut out = Vec::new();
+        ZipEntrySaver::custom_compress(Cursor::new(&mut out), false, 9)
+            .reproducible(Some(EPOCH))
+            .save_entries(entries, &mut ev, &cfgmap, &rules, |_| Ok(()))
+            .unwrap();
+        out
+    })
+}
+
+#[test]
+fn identical_across_runs_and_thread_counts() {
+    let input = sample_jar();
+    let expected = repack(&input, 1);
+    assert!(!expected.is_empty());
+    for threads in [1, 2, 4, 8] {
+        assert!(repack(&input, threads) == expected, "output differs with {threads} threads");
+    }
+}
+
+/// Returns names and modification times of entries in an archive.
+fn entries_of(data: &[u8]) -> Vec<(String, Option<DateTime>)> {
+    let mut za = ZipArchive::new(Cursor::new(data)).unwrap();
+    (0..za.len()).map(|i| {
+        let zf = za.by_index(i).unwrap();
+        (zf.name().to_string(), zf.last_modified())
+    }).collect()
+}
+
+#[test]
+fn sorted_entries_with_fixed_time() {
+    // 2023-11-14 22:13:20 UTC
+    let expected_time = DateTime::from_date_and_time(2023, 11, 14, 22, 13, 20).unwrap();
+    let out = repack(&sample_jar(), 2);
+    let entries = entries_of(&out);
+    let names: Vec<&str> = entries.iter().map(|(n, _)| n.as_str()).collect();
+    assert_eq!(names[0], "META-INF/MANIFEST.MF");
+    assert!(names[1..].is_sorted(), "entries are not sorted: {names:?}");
+    assert_eq!(names.len(), 205);
+    assert!(entries.iter().all(|(_, t)| *t == Some(expected_time)));
+
+    let mut za = ZipArchive::new(Cursor::new(&out)).unwrap();
+    let mut nested = Vec::new();
+    za.by_name("META-INF/jars/nested.jar").unwrap().read_to_end(&mut nested).unwrap();
+    let nested = entries_of(&nested);
+    assert_eq!(nested.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>(), ["META-INF/MANIFEST.MF", "assets/nested.json"]);
+    assert!(nested.iter().all(|(_, t)| *t == Some(expected_time)));
+}
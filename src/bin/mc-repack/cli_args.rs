@@ -1,8 +1,14 @@
 use std::{io, path::PathBuf, sync::Arc};
 
-use mc_repack_core::{fop::TypeBlacklist, min};
+use mc_repack_core::{
+    cfg::ConfigMap,
+    entry::EntryOrder,
+    errors::FileIgnoreError,
+    fop::{FileOp, Rule, Rules, TypeBlacklist},
+    min,
+};
 
-use crate::{config::Config, report};
+use crate::{config::{Config, RuleAction, RuleConfig}, report};
 
 
 #[derive(Debug, clap::Parser)]
@@ -21,8 +27,43 @@ pub enum Cmd {
     /// Optimize files
     Files(FilesArgs),
 
+    /// Optimize a world save (region files and NBT data)
+    World(WorldArgs),
+
+    /// Repack a modpack (Modrinth `.mrpack`, CurseForge export or packwiz directory)
+    Modpack(PackArgs),
+
     /// Check the config file
-    Check(CommonArgs)
+    Check(CommonArgs),
+
+    /// Show statistics of the cache or remove old files from it
+    #[command(subcommand)]
+    Cache(CacheCmd)
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum CacheCmd {
+    /// Show the number and size of cached files
+    Stats {
+        /// Cache directory
+        #[arg(long)]
+        cache_dir: PathBuf
+    },
+
+    /// Remove files which were not used recently
+    Prune {
+        /// Cache directory
+        #[arg(long)]
+        cache_dir: PathBuf,
+
+        /// Remove the least recently used files until the cache size (in MiB) is not larger than this
+        #[arg(long)]
+        max_size: Option<u64>,
+
+        /// Remove files not used for this number of days
+        #[arg(long)]
+        max_age: Option<u64>
+    }
 }
 
 #[derive(Debug, clap::Args)]
@@ -43,6 +84,31 @@ pub struct JarsArgs {
     #[arg(short = 'd', long)]
     pub keep_dirs: bool,
 
+    /// Order of entries in the archive (preserve, manifest-first, sorted, grouped).
+    /// The default is manifest-first (or sorted for reproducible archives)
+    #[arg(long)]
+    pub order: Option<EntryOrder>,
+
+    /// Create reproducible (byte-identical) archives. Timestamps are taken from `SOURCE_DATE_EPOCH` (if set)
+    #[arg(long)]
+    pub reproducible: bool,
+
+    /// Copy unchanged files which are already compressed without compressing them again (faster, but archives may be bigger).
+    /// By default, they are compressed again and the original data is kept only if it is not bigger
+    #[arg(long)]
+    pub raw_copy: bool,
+
+    /// Number of archives processed at once. The default is the number of available CPUs
+    #[arg(short = 'j', long)]
+    pub jobs: Option<std::num::NonZeroUsize>,
+
+    /// Memory limit (in MiB) for data of entries loaded at once. It is shared by all jobs.
+    /// Archives are read from files, so their size does not matter, but an entry larger than the limit is still loaded.
+    /// A quarter of the limit is used by entries waiting to be written in order (see `--order`), the rest of them are moved to a temporary file.
+    /// Entries of nested archives are processed in batches limited the same way, but nested archives are written in memory
+    #[arg(long, default_value_t = 1024)]
+    pub mem_limit: u64,
+
     #[command(flatten)]
     pub common: CommonArgs
 }
@@ -61,6 +127,59 @@ pub struct FilesArgs {
     pub common: CommonArgs
 }
 
+#[derive(Debug, clap::Args)]
+pub struct WorldArgs {
+    /// Path to a world directory (with `level.dat`)
+    #[arg(short = 'i', long = "in")]
+    pub path: PathBuf,
+
+    /// Destination directory. It should not be the same as the source!
+    #[arg(short = 'o', long)]
+    pub out: PathBuf,
+
+    #[command(flatten)]
+    pub common: CommonArgs
+}
+impl WorldArgs {
+    /// Rules for files of a world save. They are checked after rules from the config.
+    pub fn rules(cfgmap: &ConfigMap) -> Vec<Rule> {
+        // `.dat` files are NBT only in world saves (mods use this extension for other data).
+        // The game reads them (and their backups) only with gzip, other NBT configs are inherited
+        let nbt = min::nbt::NBTConfig { container: min::nbt::NBTContainer::Gzip, ..(*cfgmap.fetch::<min::nbt::MinifierNBT>()).clone() };
+        let child = cfgmap.child();
+        child.set::<min::nbt::MinifierNBT>(nbt);
+        ["**/*.dat", "**/*.dat_old"].into_iter()
+            .map(|p| Rule::new(p).with_action(FileOp::Minify(min::Minifier::NBT)).with_config(child.clone()))
+            .collect()
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PackArgs {
+    /// Path to a modpack file (or a packwiz directory)
+    #[arg(short = 'i', long = "in")]
+    pub path: PathBuf,
+
+    /// Destination file (or a directory for packwiz). It should not be the same as the source!
+    #[arg(short = 'o', long)]
+    pub out: PathBuf,
+
+    /// Enable Zopfli compression (better, but much slower) and apply a number of iterations
+    #[arg(short = 'z', long)]
+    pub zopfli: Option<std::num::NonZeroU8>,
+
+    /// Keep directory entries in the archive
+    #[arg(short = 'd', long)]
+    pub keep_dirs: bool,
+
+    /// Create reproducible (byte-identical) archives. Timestamps are taken from `SOURCE_DATE_EPOCH` (if set)
+    #[arg(long)]
+    pub reproducible: bool,
+
+    #[command(flatten)]
+    pub common: CommonArgs
+}
+
 #[derive(Debug, clap::Args)]
 pub struct CommonArgs {
     /// Do not print file errors
@@ -77,7 +196,20 @@ pub struct CommonArgs {
 
     /// (Optional) Path for CSV report
     #[arg(short = 'r', long)]
-    pub report: Option<PathBuf>
+    pub report: Option<PathBuf>,
+
+    /// (Optional) Directory for cached results of optimized files. Files optimized before are not optimized again
+    #[arg(long)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Decode minified files again and compare them with the original ones. Files that do not match are not minified
+    #[arg(long)]
+    pub verify: bool
+}
+
+/// Reads `SOURCE_DATE_EPOCH` for reproducible archives.
+pub fn source_date_epoch() -> Option<u64> {
+    std::env::var("SOURCE_DATE_EPOCH").ok()?.trim().parse().ok()
 }
 
 macro_rules! cfgset {
@@ -85,27 +217,74 @@ macro_rules! cfgset {
         $(if let Some(x) = $cfg.$k { $cmap.set::<$t>(x); })+
     };
 }
+macro_rules! cfgset_all {
+    ($cfg:expr, $cmap:expr) => {
+        cfgset!($cfg, $cmap,
+            json: min::json::MinifierJSON,
+            nbt: min::nbt::MinifierNBT,
+            region: min::region::MinifierRegion,
+            png: min::png::MinifierPNG,
+            toml: min::toml::MinifierTOML,
+            jar: min::jar::MinifierJAR,
+            js: min::js::MinifierJS,
+            glsl: min::glsl::MinifierGLSL,
+            class: min::class::MinifierClass
+        )
+    };
+}
+
+/// Creates a rule from the config. Configs set in the rule are stored in a child of `cfgmap`.
+fn make_rule(rc: RuleConfig, cfgmap: &ConfigMap) -> Result<Rule, String> {
+    let mut rule = Rule::new(rc.pattern.as_str());
+    if let Some(action) = rc.action {
+        rule = rule.with_action(match action {
+            RuleAction::Pass => FileOp::Pass,
+            RuleAction::Ignore => FileOp::Ignore(FileIgnoreError::Blacklisted),
+            RuleAction::Minify => {
+                let name = rc.minifier.as_deref().ok_or("the minify action needs a minifier")?;
+                FileOp::Minify(min::Minifier::by_extension(name).ok_or_else(|| format!("unknown minifier: {name}"))?)
+            }
+            RuleAction::Recompress => FileOp::Recompress(rc.min_size.unwrap_or(24))
+        });
+    }
+    let has_config = rc.json.is_some() || rc.nbt.is_some() || rc.region.is_some() || rc.png.is_some() || rc.toml.is_some()
+        || rc.jar.is_some() || rc.js.is_some() || rc.glsl.is_some() || rc.class.is_some();
+    if has_config {
+        let child = cfgmap.child();
+        cfgset_all!(rc, child);
+        rule = rule.with_config(child);
+    }
+    Ok(rule)
+}
 
 pub struct RepackOpts {
     pub err_collect: mc_repack_core::errors::ErrorCollector,
-    pub blacklist: Arc<TypeBlacklist>,
-    pub cfgmap: mc_repack_core::cfg::ConfigMap,
+    pub rules: Arc<Rules>,
+    pub cfgmap: ConfigMap,
     pub report: Option<report::Report>,
 }
 impl RepackOpts {
     pub fn from_args(args: &CommonArgs) -> Self {
-        let cfgmap = mc_repack_core::cfg::ConfigMap::default();
+        Self::with_rules(args, |_| Vec::new())
+    }
+
+    /// Creates options with additional rules, which are checked after rules from the config.
+    /// Additional rules are created after configs are loaded, so they can use them.
+    pub fn with_rules(args: &CommonArgs, extra: impl FnOnce(&ConfigMap) -> Vec<Rule>) -> Self {
+        let cfgmap = ConfigMap::default();
         let mut blacklist = None;
+        let mut rules = Vec::new();
         match Config::read(args.config.clone()) {
             Ok(c) => {
-                cfgset!(c, cfgmap,
-                    json: min::json::MinifierJSON,
-                    nbt: min::nbt::MinifierNBT,
-                    png: min::png::MinifierPNG,
-                    toml: min::toml::MinifierTOML,
-                    jar: min::jar::MinifierJAR
-                );
+                cfgset_all!(c, cfgmap);
                 blacklist = c.blacklist;
+                for rc in c.rules.unwrap_or_default() {
+                    let pattern = rc.pattern.clone();
+                    match make_rule(rc, &cfgmap) {
+                        Ok(r) => rules.push(r),
+                        Err(e) => eprintln!("Invalid rule for `{pattern}`: {e}")
+                    }
+                }
                 println!("Config loaded successfully!");
             }
             Err(e) if e.kind() != io::ErrorKind::NotFound => {
@@ -113,15 +292,26 @@ impl RepackOpts {
             }
             _ => {}
         }
+        rules.extend(extra(&cfgmap));
+        if args.verify {
+            cfgmap.set::<min::VerifyMinified>(true);
+        }
+        if let Some(dir) = &args.cache_dir {
+            match mc_repack_core::cache::Cache::open(dir) {
+                Ok(c) => cfgmap.set::<mc_repack_core::cache::EntryCache>(Some(c)),
+                Err(e) => eprintln!("Failed to open cache: {e}")
+            }
+        }
+        let blacklist = if args.use_blacklist {
+            TypeBlacklist::Extend(blacklist)
+        } else {
+            TypeBlacklist::Override(blacklist)
+        };
         Self {
             err_collect: mc_repack_core::errors::ErrorCollector::new(args.silent),
-            blacklist: Arc::new(if args.use_blacklist {
-                TypeBlacklist::Extend(blacklist)
-            } else {
-                TypeBlacklist::Override(blacklist)
-            }),
+            rules: Arc::new(rules.into_iter().fold(Rules::new(blacklist), Rules::with_rule)),
             cfgmap,
             report: args.report.as_ref().map(|p| report::Report::new(p.clone().into_boxed_path()))
         }
     }
-}
\ No newline at end of file
+}
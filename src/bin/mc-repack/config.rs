@@ -7,10 +7,45 @@ use crate::Result_;
 pub struct Config {
     pub json: Option<min::json::JSONConfig>,
     pub nbt: Option<min::nbt::NBTConfig>,
+    pub region: Option<min::region::RegionConfig>,
     pub png: Option<min::png::PNGConfig>,
     pub toml: Option<min::toml::TOMLConfig>,
     pub jar: Option<min::jar::JARConfig>,
-    pub blacklist: Option<HashSet<Box<str>>>
+    pub js: Option<min::js::JSConfig>,
+    pub glsl: Option<min::glsl::GLSLConfig>,
+    pub class: Option<min::class::ClassConfig>,
+    pub blacklist: Option<HashSet<Box<str>>>,
+    pub rules: Option<Vec<RuleConfig>>
+}
+
+/// A rule for entries matching a path pattern. Rules are checked in order.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct RuleConfig {
+    #[serde(rename = "match")]
+    pub pattern: String,
+    pub action: Option<RuleAction>,
+    /// A minifier (file extension) used by the `minify` action
+    pub minifier: Option<String>,
+    /// A minimal size for compression used by the `recompress` action
+    pub min_size: Option<u8>,
+    pub json: Option<min::json::JSONConfig>,
+    pub nbt: Option<min::nbt::NBTConfig>,
+    pub region: Option<min::region::RegionConfig>,
+    pub png: Option<min::png::PNGConfig>,
+    pub toml: Option<min::toml::TOMLConfig>,
+    pub jar: Option<min::jar::JARConfig>,
+    pub js: Option<min::js::JSConfig>,
+    pub glsl: Option<min::glsl::GLSLConfig>,
+    pub class: Option<min::class::ClassConfig>
+}
+
+#[derive(Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Pass,
+    Ignore,
+    Minify,
+    Recompress
 }
 impl Config {
     pub fn read(path: Option<PathBuf>) -> io::Result<Self> {
@@ -24,10 +59,15 @@ impl Default for Config {
         Self {
             json: Some(min::json::JSONConfig::default()),
             nbt: Some(min::nbt::NBTConfig::default()),
+            region: Some(min::region::RegionConfig::default()),
             png: Some(min::png::PNGConfig::default()),
             toml: Some(min::toml::TOMLConfig::default()),
             jar: Some(min::jar::JARConfig::default()),
-            blacklist: Some(HashSet::new())
+            js: Some(min::js::JSConfig::default()),
+            glsl: Some(min::glsl::GLSLConfig::default()),
+            class: Some(min::class::ClassConfig::default()),
+            blacklist: Some(HashSet::new()),
+            rules: Some(Vec::new())
         }
     }
 }
@@ -0,0 +1,80 @@
+use std::sync::{
+    Condvar, Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+/// Runs `f` for each item on `jobs` threads. Each thread creates its own state with `init`.
+/// Results are returned in the same order as items.
+pub fn run<T: Sync, S, R: Send>(
+    items: &[T],
+    jobs: usize,
+    init: impl Fn() -> S + Sync,
+    f: impl Fn(&mut S, &T) -> R + Sync,
+) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..jobs.clamp(1, items.len().max(1)))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut state = init();
+                    let mut v = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break;
+                        };
+                        v.push((i, f(&mut state, item)));
+                    }
+                    v
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+    results.sort_unstable_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Limits the total size of entries that are read from one archive, but not yet optimized.
+/// Each call of `optimize_with` has its own budget, created from the memory share of its job.
+pub struct MemBudget {
+    limit: u64,
+    used: Mutex<u64>,
+    cv: Condvar,
+}
+impl MemBudget {
+    pub const fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: Mutex::new(0),
+            cv: Condvar::new(),
+        }
+    }
+
+    /// Waits until `size` bytes can be loaded. Sizes larger than the limit are acquired only when nothing else is loaded.
+    pub fn acquire(&self, size: u64) -> MemLease<'_> {
+        let size = size.min(self.limit);
+        let mut used = self.used.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        while used.saturating_add(size) > self.limit {
+            used = self.cv.wait(used).unwrap_or_else(std::sync::PoisonError::into_inner);
+        }
+        *used += size;
+        drop(used);
+        MemLease { budget: self, size }
+    }
+}
+
+/// A part of [`MemBudget`] that is released when dropped.
+pub struct MemLease<'a> {
+    budget: &'a MemBudget,
+    size: u64,
+}
+impl Drop for MemLease<'_> {
+    fn drop(&mut self) {
+        *self.budget.used.lock().unwrap_or_else(std::sync::PoisonError::into_inner) -= self.size;
+        self.budget.cv.notify_all();
+    }
+}
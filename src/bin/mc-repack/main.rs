@@ -1,19 +1,24 @@
 use clap::Parser;
-use cli_args::{Cmd, FilesArgs, JarsArgs, RepackOpts};
+use cli_args::{CacheCmd, Cmd, JarsArgs, PackArgs, RepackOpts, WorldArgs};
 use crossbeam_channel::{Receiver, Sender};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use iter::Files;
 use mc_repack_core::{
-    ProgressState, cfg,
-    entry::{self, EntryReader, EntrySaver, NamedEntry, ReadEntryIter, process_entry, read_entry},
+    ProgressState,
+    cache::Cache,
+    cfg,
+    entry::{self, EntryReader, EntrySaver, NamedEntry, ReadEntryIter, process_entries_bounded, read_entry},
     errors::ErrorCollector,
-    fop::TypeBlacklist,
+    fop::Rules,
+    min,
 };
-use std::{fs, path::Path, sync::Arc};
+use std::{fs, path::Path, time::Duration};
 
 mod cli_args;
 mod config;
 mod iter;
+mod jobs;
+mod modpack;
 mod report;
 
 type Error_ = anyhow::Error;
@@ -29,7 +34,8 @@ fn main() -> Result_<()> {
             let mut repack_opts = RepackOpts::from_args(&ja.common);
             let (base, fit) = Files::from_path(path)?;
             process_jars(&base, fit, ja, &mut repack_opts)?;
-            if let Some(ref report) = repack_opts.report {
+            if let Some(ref mut report) = repack_opts.report {
+                report.push_mismatches(&repack_opts.err_collect);
                 report.save_csv()?;
             }
             print_entry_errors(&repack_opts.err_collect);
@@ -38,9 +44,36 @@ fn main() -> Result_<()> {
             let path = &fa.path;
             let mut repack_opts = RepackOpts::from_args(&fa.common);
             let (base, fit) = Files::from_path(path)?;
-            process_files(&base, fit, fa, &mut repack_opts)?;
+            process_files(&base, fit, &fa.out, &mut repack_opts)?;
             if let Some(ref mut report) = repack_opts.report {
                 files_report(report, path, &fa.out)?;
+                report.push_mismatches(&repack_opts.err_collect);
+                report.save_csv()?;
+            }
+            print_entry_errors(&repack_opts.err_collect);
+        }
+        Cmd::World(wa) => {
+            let path = &wa.path;
+            let mut repack_opts = RepackOpts::with_rules(&wa.common, WorldArgs::rules);
+            let (base, fit) = Files::from_path(path)?;
+            process_files(&base, fit, &wa.out, &mut repack_opts)?;
+            if let Some(ref mut report) = repack_opts.report {
+                files_report(report, path, &wa.out)?;
+                report.push_mismatches(&repack_opts.err_collect);
+                report.save_csv()?;
+            }
+            print_entry_errors(&repack_opts.err_collect);
+        }
+        Cmd::Modpack(pa) => {
+            let mut repack_opts = RepackOpts::from_args(&pa.common);
+            process_modpack(pa, &mut repack_opts)?;
+            if let Some(ref mut report) = repack_opts.report {
+                if pa.path.is_dir() {
+                    files_report(report, &pa.path, &pa.out)?;
+                } else {
+                    report_sizes(report, &pa.path.to_string_lossy(), &pa.path, &pa.out);
+                }
+                report.push_mismatches(&repack_opts.err_collect);
                 report.save_csv()?;
             }
             print_entry_errors(&repack_opts.err_collect);
@@ -52,12 +85,33 @@ fn main() -> Result_<()> {
                 println!("New config file created!");
             }
         }
+        Cmd::Cache(cc) => cache_cmd(cc)?,
     }
 
     Ok(())
 }
 
+fn cache_cmd(cc: &CacheCmd) -> Result_<()> {
+    const MIB: u64 = 1 << 20;
+    const DAY: u64 = 86400;
+    let st = match cc {
+        CacheCmd::Stats { cache_dir } => Cache::open(cache_dir)?.stats()?,
+        CacheCmd::Prune { cache_dir, max_size, max_age } => {
+            let cache = Cache::open(cache_dir)?;
+            let st = cache.prune(max_size.map(|s| s * MIB), max_age.map(|d| Duration::from_secs(d * DAY)))?;
+            println!("Removed {} files ({:.2} MiB)", st.entries, st.bytes as f64 / MIB as f64);
+            cache.stats()?
+        }
+    };
+    println!("Cached files: {} ({:.2} MiB)", st.entries, st.bytes as f64 / MIB as f64);
+    if let Some(age) = st.oldest.and_then(|t| t.elapsed().ok()) {
+        println!("Least recently used: {} days ago", age.as_secs() / DAY);
+    }
+    Ok(())
+}
+
 const PB_STYLE_ZIP: &str = "# {pos}/{len} {wide_msg}";
+const PB_STYLE_JAR: &str = "{prefix} # {pos}/{len} {wide_msg}";
 
 fn file_progress_bar() -> ProgressBar {
     ProgressBar::new(0).with_style(ProgressStyle::with_template(PB_STYLE_ZIP).unwrap())
@@ -65,80 +119,125 @@ fn file_progress_bar() -> ProgressBar {
 
 fn process_jars(base: &Path, fit: Files, jargs: &JarsArgs, opts: &mut RepackOpts) -> Result_<()> {
     let &mut RepackOpts {
-        ref blacklist,
+        ref rules,
         ref cfgmap,
         ..
     } = opts;
-    let ec = &mut opts.err_collect;
     let clvl = 9 + jargs.zopfli.map_or(0, |x| i64::from(x.get()));
-    let mp = MultiProgress::new();
+    let signed = cfgmap.fetch::<min::jar::MinifierJAR>().signed;
+    if jargs.reproducible {
+        // Nested archives get the same timestamps as their parents
+        cfgmap.set::<min::jar::NestedMtime>(entry::zip::EntryMtime::Fixed(cli_args::source_date_epoch()));
+    }
 
     let mut db = fs::DirBuilder::new();
     db.recursive(true);
 
-    let pb = mp.add(
-        ProgressBar::new_spinner().with_style(ProgressStyle::with_template("{wide_msg}").unwrap()),
-    );
-    let pb2 = mp.add(file_progress_bar());
-    let ps = thread_progress_bar(pb2);
-
+    let mut jars = Vec::new();
     for fp in fit {
         let (ftype, fp) = fp?;
-        let Some(fname) = fp.file_name() else {
-            return invalid_file_name();
-        };
         let Some(relp) = pathdiff::diff_paths(&fp, base) else {
             return invalid_file_name();
         };
-        let relname = relp.to_string_lossy();
-        let fname = fname.to_string_lossy();
         if matches!(ftype, Some(false))
             && matches!(
                 relp.extension().map(std::ffi::OsStr::as_encoded_bytes),
                 Some(b"jar" | b"zip")
             )
         {
-            ec.rename(&relname);
-            pb.set_message(fname.to_string());
             let nfp = jargs.out.join(&relp);
             if let Some(np) = nfp.parent() {
                 db.create(np)?;
             }
-            match optimize_with(
-                &mut entry::ZipEntryReader::new_mem(fs::read(&fp)?)?,
-                &mut entry::ZipEntrySaver::custom_compress(
-                    fs::File::create(&nfp)?,
-                    jargs.keep_dirs,
-                    clvl,
-                ),
-                cfgmap,
-                &ps,
-                ec,
-                blacklist.clone(),
-            ) {
-                Ok(()) => {
-                    if let Some(ref mut report) = opts.report {
-                        report_sizes(report, &relname, &fp, &nfp);
-                    }
+            jars.push((relp.to_string_lossy().into_owned(), fp, nfp));
+        }
+    }
+
+    let jobs = jargs
+        .jobs
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, std::num::NonZeroUsize::get);
+    // Each job gets an equal part of the memory limit
+    let mem = jargs.mem_limit.saturating_mul(1 << 20) / jobs.min(jars.len()).max(1) as u64;
+    let mp = MultiProgress::new();
+    let base_ec = opts.err_collect.fork();
+
+    let results = jobs::run(
+        &jars,
+        jobs,
+        || {
+            let pb = mp.add(ProgressBar::new(0).with_style(ProgressStyle::with_template(PB_STYLE_JAR).unwrap()));
+            (pb.clone(), thread_progress_bar(pb))
+        },
+        |(pb, ps), (relname, fp, nfp)| {
+            let mut ec = base_ec.fork();
+            ec.rename(relname);
+            if let Some(fname) = fp.file_name() {
+                pb.set_prefix(fname.to_string_lossy().into_owned());
+            }
+            let r = (|| {
+                let mut reader = entry::ZipEntryReader::new_buf(fs::File::open(fp)?)?;
+                reader.keep_compressed();
+                if signed.apply(&mut reader, &mut ec)? {
+                    // A quarter of the share is used by entries waiting to be written in order
+                    let mut saver = jar_saver(fs::File::create(nfp)?, jargs, clvl)
+                        .with_manifest(reader.has_manifest())
+                        .with_buffer_limit(usize::try_from(mem / 4).unwrap_or(usize::MAX));
+                    optimize_with(
+                        &mut reader,
+                        &mut saver,
+                        cfgmap,
+                        ps,
+                        &mut ec,
+                        rules,
+                        mem - mem / 4,
+                    )
+                } else {
+                    fs::copy(fp, nfp).map(|_| ()).map_err(Into::into)
                 }
-                Err(e) => {
-                    println!("Cannot repack {}: {}\n\n", fp.display(), e);
-                    if let Err(fe) = fs::remove_file(&nfp) {
-                        println!("Cannot remove {}: {}", nfp.display(), fe);
+            })();
+            if let Err(e) = &r {
+                mp.suspend(|| println!("Cannot repack {}: {}\n\n", fp.display(), e));
+                if nfp.exists() {
+                    if let Err(fe) = fs::remove_file(nfp) {
+                        mp.suspend(|| println!("Cannot remove {}: {}", nfp.display(), fe));
                     }
                 }
             }
+            (r.is_ok(), ec)
+        },
+    );
+    mp.clear()?;
+
+    for ((relname, fp, nfp), (ok, ec)) in jars.iter().zip(results) {
+        opts.err_collect.merge(ec);
+        if ok {
+            if let Some(ref mut report) = opts.report {
+                report_sizes(report, relname, fp, nfp);
+            }
         }
     }
-    mp.clear()?;
-    drop(ps);
 
     Ok(())
 }
 
-fn process_files(base: &Path, fit: Files, fargs: &FilesArgs, opts: &mut RepackOpts) -> Result_<()> {
+fn jar_saver(f: fs::File, jargs: &JarsArgs, clvl: i64) -> entry::ZipEntrySaver<fs::File> {
+    let mut saver = entry::ZipEntrySaver::custom_compress(f, jargs.keep_dirs, clvl);
+    if jargs.raw_copy {
+        saver = saver.with_raw_copy(entry::zip::RawCopy::Always);
+    }
+    if jargs.reproducible {
+        saver = saver.reproducible(cli_args::source_date_epoch());
+    }
+    match jargs.order {
+        Some(order) => saver.with_order(order),
+        None => saver,
+    }
+}
+
+fn process_files(base: &Path, fit: Files, out: &Path, opts: &mut RepackOpts) -> Result_<()> {
     let &mut RepackOpts {
-        ref blacklist,
+        ref rules,
         ref cfgmap,
         ..
     } = opts;
@@ -147,16 +246,44 @@ fn process_files(base: &Path, fit: Files, fargs: &FilesArgs, opts: &mut RepackOp
     let ps = thread_progress_bar(pb2);
     optimize_with(
         &mut entry::FSEntryReader::custom(base.into(), fit),
-        &mut entry::FSEntrySaver::new(fargs.out.clone().into_boxed_path()),
+        &mut entry::FSEntrySaver::new(out.into()),
         cfgmap,
         &ps,
         ec,
-        blacklist.clone(),
+        rules,
+        u64::MAX,
     )?;
     drop(ps);
     Ok(())
 }
 
+fn process_modpack(pargs: &PackArgs, opts: &mut RepackOpts) -> Result_<()> {
+    let fp = &pargs.path;
+    if let Some(fname) = fp.file_name() {
+        opts.err_collect.rename(&fname.to_string_lossy());
+    }
+    // A packwiz output directory is removed only if it was created here
+    let out_existed = pargs.out.exists();
+    let pb2 = file_progress_bar();
+    let ps = thread_progress_bar(pb2);
+    let r = modpack::process_pack(fp, pargs, opts, &ps);
+    drop(ps);
+    if let Err(e) = r {
+        let fr = if pargs.out.is_file() {
+            fs::remove_file(&pargs.out)
+        } else if pargs.out.is_dir() && !out_existed {
+            fs::remove_dir_all(&pargs.out)
+        } else {
+            return Err(e);
+        };
+        if let Err(fe) = fr {
+            println!("Cannot remove {}: {}", pargs.out.display(), fe);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
 fn files_report(report: &mut report::Report, path: &Path, out: &Path) -> Result_<()> {
     let (base, fit) = Files::from_path(path)?;
     for fp in fit {
@@ -192,10 +319,12 @@ fn report_sizes(report: &mut report::Report, relname: &str, fp: &Path, nfp: &Pat
 
 fn thread_progress_bar(pb: ProgressBar) -> Sender<ProgressState> {
     let (ps, pr) = crossbeam_channel::unbounded();
-    rayon::spawn(move || {
+    // A separate thread, so the progress is updated even if all rayon threads are busy
+    std::thread::spawn(move || {
         for st in pr {
             match st {
                 ProgressState::Start(u) => {
+                    pb.reset();
                     pb.set_length(u as u64);
                 }
                 ProgressState::Push(num, msg) => {
@@ -221,23 +350,27 @@ fn print_entry_errors(ec: &ErrorCollector) {
     }
 }
 
-fn optimize_with<R: EntryReader + Send + 'static, S: EntrySaver + Send + 'static>(
+/// Reads and optimizes entries. Data of entries waiting for optimization is limited by half of `mem` (in bytes).
+/// The other half of `mem` is used by a batch of entries being optimized.
+fn optimize_with<R: EntryReader + Send, S: EntrySaver + Sync>(
     reader: &mut R,
     saver: &mut S,
     cfgmap: &cfg::ConfigMap,
     ps: &Sender<ProgressState>,
     errors: &mut ErrorCollector,
-    blacklist: Arc<TypeBlacklist>,
+    rules: &Rules,
+    mem: u64,
 ) -> crate::Result_<()> {
+    let budget = jobs::MemBudget::new(mem / 2);
     let (tx, rx) = crossbeam_channel::unbounded();
     wrap_send(ps, ProgressState::Start(reader.read_len()))?;
-    let mut r1 = anyhow::Ok(());
-    let mut r2 = anyhow::Ok(());
-    rayon::scope_fifo(|s| {
-        let r1 = &mut r1;
-        let r2 = &mut r2;
-        s.spawn_fifo(move |_| *r1 = reading(reader.read_iter(), &tx, &blacklist));
-        s.spawn_fifo(move |_| *r2 = saving(saver, rx, ps, errors, cfgmap));
+    // Reading runs outside rayon threads, because it waits for the budget
+    let (r1, r2) = std::thread::scope(|s| {
+        let budget = &budget;
+        let h = s.spawn(move || reading(reader.read_iter(), &tx, rules, budget));
+        let r2 = saving(saver, rx, ps, errors, cfgmap, rules, mem / 2);
+        let r1 = h.join().unwrap_or_else(|e| std::panic::resume_unwind(e));
+        (r1, r2)
     });
     match (r1, r2) {
         (Ok(()), Ok(())) => Ok(()),
@@ -246,36 +379,33 @@ fn optimize_with<R: EntryReader + Send + 'static, S: EntrySaver + Send + 'static
     }
 }
 
-fn reading<R: EntryReader>(
+fn reading<'a, R: EntryReader>(
     iter: ReadEntryIter<R>,
-    tx: &Sender<NamedEntry>,
-    blacklist: &TypeBlacklist,
+    tx: &Sender<(NamedEntry, jobs::MemLease<'a>)>,
+    rules: &Rules,
+    budget: &'a jobs::MemBudget,
 ) -> Result_<()> {
     for re in iter {
-        if let Some(ne) = read_entry::<R>(re, blacklist)? {
-            wrap_send(tx, ne)?;
+        if let Some(ne) = read_entry::<R>(re, rules)? {
+            let lease = budget.acquire(ne.data_len() as u64);
+            wrap_send(tx, (ne, lease))?;
         };
     }
     Ok(())
 }
-fn saving<S: EntrySaver>(
+fn saving<S: EntrySaver + Sync>(
     saver: &mut S,
-    rx: Receiver<NamedEntry>,
+    rx: Receiver<(NamedEntry, jobs::MemLease)>,
     ps: &Sender<ProgressState>,
     errors: &mut ErrorCollector,
     cfgmap: &cfg::ConfigMap,
+    rules: &Rules,
+    batch_bytes: u64,
 ) -> Result_<()> {
-    let mut cv = Vec::new();
-    for (n, ne) in rx.into_iter().enumerate() {
-        wrap_send(ps, ProgressState::Push(n, ne.0.clone()))?;
-        if let Some(se) = process_entry(&mut cv, &ne, errors, cfgmap) {
-            saver.save(&ne.0, se)?;
-            if !cv.is_empty() {
-                cv.clear();
-            }
-        }
-    }
-    wrap_send(ps, ProgressState::Finish)
+    // The budget is released when an entry is taken into a batch, which is limited separately
+    let rx = rx.into_iter().map(|(ne, _)| ne);
+    let batch_bytes = usize::try_from(batch_bytes).unwrap_or(usize::MAX);
+    process_entries_bounded(saver, rx, errors, cfgmap, rules, |st| wrap_send(ps, st), batch_bytes)
 }
 
 fn wrap_send<T>(s: &Sender<T>, t: T) -> Result_<()> {
@@ -0,0 +1,457 @@
+use std::{collections::HashSet, fs, path::{Component, Path, PathBuf}};
+
+use crossbeam_channel::Sender;
+use mc_repack_core::{
+    ProgressState,
+    entry::{self, EntryReader, EntrySaver, NamedEntry, ReadEntry, SavingEntry, process_entry, read_entry},
+    fop::FileOp,
+    min,
+};
+use sha1::{Digest, Sha1};
+use sha2::{Sha256, Sha512};
+
+use crate::{Result_, cli_args::{PackArgs, RepackOpts}, wrap_send};
+
+const MRPACK_INDEX: &str = "modrinth.index.json";
+const MRPACK_OVERRIDES: [&str; 3] = ["overrides/", "client-overrides/", "server-overrides/"];
+const CURSE_MANIFEST: &str = "manifest.json";
+const PACKWIZ_PACK: &str = "pack.toml";
+const PACKWIZ_IGNORE: &str = ".packwizignore";
+
+/// A modpack format recognized by its layout.
+enum PackFormat {
+    /// Modrinth modpack with `modrinth.index.json`
+    Modrinth,
+    /// CurseForge modpack export with `manifest.json` and an overrides directory
+    CurseForge(Box<str>),
+}
+impl PackFormat {
+    fn detect(fp: &Path) -> Result_<Option<Self>> {
+        let mut reader = entry::ZipEntryReader::new_buf(fs::File::open(fp)?)?;
+        for re in reader.read_iter() {
+            let (_, name) = re.meta();
+            match &*name {
+                MRPACK_INDEX => return Ok(Some(Self::Modrinth)),
+                CURSE_MANIFEST => {
+                    let json: serde_json::Value = serde_json::from_slice(&re.data()?)?;
+                    if json.get("manifestType").and_then(serde_json::Value::as_str) != Some("minecraftModpack") {
+                        return Ok(None);
+                    }
+                    let overrides = json.get("overrides").and_then(serde_json::Value::as_str).unwrap_or("overrides");
+                    return Ok(Some(Self::CurseForge(format!("{}/", overrides.trim_end_matches('/')).into())));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Repacks override files of a modpack archive (Modrinth or CurseForge) or tracked files of a packwiz pack.
+/// Other files are kept intact.
+pub fn process_pack(
+    fp: &Path,
+    pargs: &PackArgs,
+    opts: &mut RepackOpts,
+    ps: &Sender<ProgressState>,
+) -> Result_<()> {
+    if fp.is_dir() || fp.file_name().is_some_and(|n| n == PACKWIZ_PACK) {
+        return process_packwiz(fp, pargs, opts, ps);
+    }
+    let Some(fmt) = PackFormat::detect(fp)? else {
+        anyhow::bail!("Unknown modpack format: {}", fp.display());
+    };
+    let clvl = 9 + pargs.zopfli.map_or(0, |x| i64::from(x.get()));
+    let mut saver = entry::ZipEntrySaver::custom_compress(
+        fs::File::create(&pargs.out)?,
+        pargs.keep_dirs,
+        clvl,
+    )
+    .with_order(entry::EntryOrder::Preserve);
+    if pargs.reproducible {
+        let epoch = crate::cli_args::source_date_epoch();
+        saver = saver.with_mtime(epoch);
+        opts.cfgmap.set::<min::jar::NestedMtime>(entry::zip::EntryMtime::Fixed(epoch));
+    }
+    match fmt {
+        PackFormat::Modrinth => {
+            let mut overridden = Vec::new();
+            let index = repack_overrides(fp, &mut saver, &MRPACK_OVERRIDES, Some(MRPACK_INDEX), opts, ps, |name| {
+                overridden.push(Box::<str>::from(name));
+            })?;
+            let Some(index) = index else {
+                anyhow::bail!("{MRPACK_INDEX} not found");
+            };
+            for name in mrpack_downloads_overridden(&index, overridden)? {
+                opts.err_collect.collect(
+                    name,
+                    anyhow::anyhow!("override replaces a downloaded file, its entry in {MRPACK_INDEX} is kept"),
+                );
+            }
+        }
+        PackFormat::CurseForge(overrides) => {
+            repack_overrides(fp, &mut saver, &[&overrides], None, opts, ps, |_| {})?;
+        }
+    }
+    saver.finish()?;
+    Ok(())
+}
+
+/// Repacks entries within `overrides` directories and copies the rest. Entries are read one by one from the file.
+/// The `held` entry is copied and its data is returned for further processing.
+/// Each override file is passed to `on_override` (with its name relative to the overrides directory).
+fn repack_overrides(
+    fp: &Path,
+    saver: &mut entry::ZipEntrySaver<fs::File>,
+    overrides: &[&str],
+    held: Option<&str>,
+    opts: &mut RepackOpts,
+    ps: &Sender<ProgressState>,
+    mut on_override: impl FnMut(&str),
+) -> Result_<Option<Vec<u8>>> {
+    let &mut RepackOpts {
+        ref rules,
+        ref cfgmap,
+        ..
+    } = opts;
+    let ec = &mut opts.err_collect;
+    let mut reader = entry::ZipEntryReader::new_buf(fs::File::open(fp)?)?;
+    let mut held_data = None;
+    let mut cv = Vec::new();
+    wrap_send(ps, ProgressState::Start(reader.read_len()))?;
+    for (n, re) in reader.read_iter().enumerate() {
+        let (is_dir, name) = re.meta();
+        wrap_send(ps, ProgressState::Push(n, name.clone().into()))?;
+        let Some(relname) = overrides.iter().find_map(|p| name.strip_prefix(p)) else {
+            // Files outside overrides are kept intact
+            if is_dir == Some(false) {
+                let data = re.data()?;
+                saver.save(&name, SavingEntry::File(&data, 24))?;
+                if held == Some(&*name) {
+                    held_data = Some(data.into());
+                }
+            }
+            continue;
+        };
+        if is_dir == Some(false) {
+            on_override(relname);
+        }
+        let Some(ne) = read_entry::<entry::ZipEntryReader<_>>(re, rules)? else {
+            continue;
+        };
+        if let Some(se) = process_entry(&mut cv, &ne, ec, cfgmap, rules) {
+            saver.save(&ne.0, se)?;
+        }
+        cv.clear();
+    }
+    wrap_send(ps, ProgressState::Finish)?;
+    Ok(held_data)
+}
+
+/// Returns override files that replace downloaded files listed in the index.
+/// Hashes in the index belong to downloaded files, so they are never changed.
+fn mrpack_downloads_overridden(index: &[u8], overridden: Vec<Box<str>>) -> Result_<Vec<Box<str>>> {
+    let json: serde_json::Value = serde_json::from_slice(index)?;
+    let Some(files) = json.get("files").and_then(serde_json::Value::as_array) else {
+        return Ok(Vec::new());
+    };
+    let downloads: HashSet<&str> = files.iter().filter_map(|f| f.get("path")?.as_str()).collect();
+    Ok(overridden.into_iter().filter(|name| downloads.contains(&**name)).collect())
+}
+
+/// Repacks files tracked by a packwiz index. Metafiles are copied, because they only describe downloaded files.
+/// Hashes of changed files are updated in the index, and the index hash is updated in `pack.toml`.
+fn process_packwiz(
+    fp: &Path,
+    pargs: &PackArgs,
+    opts: &mut RepackOpts,
+    ps: &Sender<ProgressState>,
+) -> Result_<()> {
+    let &mut RepackOpts {
+        ref rules,
+        ref cfgmap,
+        ..
+    } = opts;
+    let ec = &mut opts.err_collect;
+    let pack_path = if fp.is_dir() { fp.join(PACKWIZ_PACK) } else { fp.to_path_buf() };
+    let base = pack_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut pack: toml::Table = toml::from_str(&fs::read_to_string(&pack_path)?)?;
+    let Some(index_meta) = pack.get("index").and_then(toml::Value::as_table) else {
+        anyhow::bail!("Index not found in {PACKWIZ_PACK}");
+    };
+    let Some(index_file) = index_meta.get("file").and_then(toml::Value::as_str).map(str::to_string) else {
+        anyhow::bail!("Index file not found in {PACKWIZ_PACK}");
+    };
+    let index_fmt = toml_str(index_meta, "hash-format").unwrap_or("sha256").to_string();
+    let Some(index_path) = join_pack_path(base, &index_file) else {
+        anyhow::bail!("Invalid index path: {index_file}");
+    };
+    let mut index: toml::Table = toml::from_str(&fs::read_to_string(&index_path)?)?;
+    let default_fmt = toml_str(&index, "hash-format").unwrap_or("sha256").to_string();
+
+    let mut db = fs::DirBuilder::new();
+    db.recursive(true);
+    let out_index_path = pargs.out.join(&index_file);
+    let src_base = index_path.parent().unwrap_or(base);
+    let dst_base = out_index_path.parent().unwrap_or(&pargs.out);
+
+    let files = index
+        .get_mut("files")
+        .and_then(toml::Value::as_array_mut)
+        .map(std::mem::take)
+        .unwrap_or_default();
+    wrap_send(ps, ProgressState::Start(files.len()))?;
+    let mut kept = Vec::with_capacity(files.len());
+    let mut cv = Vec::new();
+    for (n, mut f) in files.into_iter().enumerate() {
+        let Some(ft) = f.as_table_mut() else {
+            continue;
+        };
+        let Some(name) = toml_str(ft, "file").map(Box::<str>::from) else {
+            continue;
+        };
+        wrap_send(ps, ProgressState::Push(n, name.clone().into()))?;
+        let (Some(src), Some(dst)) = (join_pack_path(src_base, &name), join_pack_path(dst_base, &name)) else {
+            ec.collect(name, anyhow::anyhow!("invalid path in the index"));
+            continue;
+        };
+        if let Some(np) = dst.parent() {
+            db.create(np)?;
+        }
+        let data = fs::read(src)?;
+        let fmt = toml_str(ft, "hash-format").unwrap_or(&default_fmt).to_string();
+        if ft.get("metafile").and_then(toml::Value::as_bool) == Some(true) {
+            fs::write(&dst, &data)?;
+            kept.push(f);
+            continue;
+        }
+        if packwiz_hash(&fmt, &[]).is_none() {
+            ec.collect(name, anyhow::anyhow!("unsupported hash format: {fmt}"));
+            fs::write(&dst, &data)?;
+            kept.push(f);
+            continue;
+        }
+        let fop = rules.file_op(&name);
+        if let FileOp::Ignore(_) = fop {
+            // Ignored files are copied, because the index still points to them
+            fs::write(&dst, &data)?;
+            kept.push(f);
+            continue;
+        }
+        let ne = NamedEntry::file(name, data, fop);
+        cv.clear();
+        let Some(SavingEntry::File(out, _) | SavingEntry::Checked(out, _)) =
+            process_entry(&mut cv, &ne, ec, cfgmap, rules)
+        else {
+            continue;
+        };
+        fs::write(&dst, out)?;
+        if let entry::EntryType::File(old, _) = &ne.1 {
+            if out != &old[..] {
+                if let Some(h) = packwiz_hash(&fmt, out) {
+                    ft.insert("hash".into(), h.into());
+                }
+            }
+        }
+        kept.push(f);
+    }
+    index.insert("files".into(), kept.into());
+
+    let index_data = toml::to_string(&index)?;
+    let Some(index_hash) = packwiz_hash(&index_fmt, index_data.as_bytes()) else {
+        anyhow::bail!("Unsupported hash format of index: {index_fmt}");
+    };
+    if let Some(np) = out_index_path.parent() {
+        db.create(np)?;
+    }
+    fs::write(&out_index_path, index_data)?;
+    if let Some(im) = pack.get_mut("index").and_then(toml::Value::as_table_mut) {
+        im.insert("hash".into(), index_hash.into());
+    }
+    fs::write(pargs.out.join(PACKWIZ_PACK), toml::to_string(&pack)?)?;
+    let ignore = base.join(PACKWIZ_IGNORE);
+    if ignore.is_file() {
+        fs::copy(ignore, pargs.out.join(PACKWIZ_IGNORE))?;
+    }
+    wrap_send(ps, ProgressState::Finish)
+}
+
+/// Joins a relative path from a pack with a base directory.
+/// Absolute paths and paths that may leave the base directory (with `..`) are rejected.
+fn join_pack_path(base: &Path, name: &str) -> Option<PathBuf> {
+    let p = Path::new(name);
+    p.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        .then(|| base.join(p))
+}
+
+#[inline]
+fn toml_str<'a>(t: &'a toml::Table, key: &str) -> Option<&'a str> {
+    t.get(key).and_then(toml::Value::as_str)
+}
+
+/// Computes a hash (as a hex string) in a format supported by packwiz.
+fn packwiz_hash(fmt: &str, data: &[u8]) -> Option<String> {
+    Some(match fmt {
+        "sha1" => format!("{:x}", Sha1::digest(data)),
+        "sha256" => format!("{:x}", Sha256::digest(data)),
+        "sha512" => format!("{:x}", Sha512::digest(data)),
+        "md5" => format!("{:x}", md5::compute(data)),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use mc_repack_core::{cfg::ConfigMap, errors::ErrorCollector, fop::Rules};
+
+    use super::*;
+    use crate::cli_args::CommonArgs;
+
+    /// A temporary directory which is removed when dropped.
+    struct TempDir(PathBuf);
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let p = std::env::temp_dir().join(format!("mc-repack-test-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&p);
+            fs::create_dir_all(&p).unwrap();
+            Self(p)
+        }
+    }
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn pack_args(path: PathBuf, out: PathBuf) -> PackArgs {
+        PackArgs {
+            path,
+            out,
+            zopfli: None,
+            keep_dirs: false,
+            reproducible: false,
+            common: CommonArgs { silent: false, use_blacklist: true, config: None, report: None, cache_dir: None, verify: false },
+        }
+    }
+
+    fn repack(pargs: &PackArgs) -> Vec<(Box<str>, String)> {
+        let mut opts = RepackOpts {
+            err_collect: ErrorCollector::new(false),
+            rules: Arc::new(Rules::default()),
+            cfgmap: ConfigMap::default(),
+            report: None,
+        };
+        let (ps, _rx) = crossbeam_channel::unbounded();
+        process_pack(&pargs.path, pargs, &mut opts, &ps).unwrap();
+        opts.err_collect.results().iter().map(|e| (e.name.as_ref().into(), e.inner_error().to_string())).collect()
+    }
+
+    fn zip_data(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut saver = entry::ZipEntrySaver::new(std::io::Cursor::new(Vec::new()), false);
+        for (name, data) in entries {
+            saver.save(name, SavingEntry::File(data, 24)).unwrap();
+        }
+        saver.finish().unwrap().into_inner()
+    }
+
+    fn read_zip(path: &Path) -> HashMap<Box<str>, Vec<u8>> {
+        let mut reader = entry::ZipEntryReader::new_buf(fs::File::open(path).unwrap()).unwrap();
+        reader.read_iter().map(|re| (re.meta().1, re.data().unwrap().to_vec())).collect()
+    }
+
+    const CONFIG: &[u8] = b"{\n  \"enabled\": true\n}\n";
+
+    #[test]
+    fn mrpack_override_replaces_download() {
+        let dir = TempDir::new("mrpack");
+        let index = br#"{"formatVersion": 1, "game": "minecraft", "files": [
+            {"path": "mods/a.jar", "hashes": {"sha1": "0000000000000000000000000000000000000000"}, "downloads": ["https://example.com/a.jar"], "fileSize": 1}
+        ]}"#;
+        let src = dir.0.join("pack.mrpack");
+        let jar = zip_data(&[("fabric.mod.json", CONFIG)]);
+        fs::write(&src, zip_data(&[(MRPACK_INDEX, index), ("overrides/mods/a.jar", &jar), ("overrides/config/a.json", CONFIG)])).unwrap();
+
+        let out = dir.0.join("out.mrpack");
+        let errors = repack(&pack_args(src, out.clone()));
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert_eq!(&*errors[0].0, "mods/a.jar");
+        assert!(errors[0].1.contains("its entry in modrinth.index.json is kept"));
+
+        let files = read_zip(&out);
+        // The index is not changed, because it only describes downloaded files
+        assert_eq!(files[MRPACK_INDEX], index);
+        assert_eq!(files["overrides/config/a.json"], b"{\"enabled\":true}");
+        let jar = entry::ZipEntryReader::new_mem(&files["overrides/mods/a.jar"]).unwrap().read_iter().map(|re| re.data().unwrap()).collect::<Vec<_>>();
+        assert_eq!(jar, [&b"{\"enabled\":true}"[..]]);
+    }
+
+    #[test]
+    fn packwiz_hashes() {
+        let dir = TempDir::new("packwiz");
+        let (src, out) = (dir.0.join("src"), dir.0.join("out"));
+        for d in ["config", "mods", "textures"] {
+            fs::create_dir_all(src.join(d)).unwrap();
+        }
+        let metafile = b"name = \"A\"\nfilename = \"a.jar\"\n";
+        let files: [(&str, &[u8]); 4] = [
+            ("config/a.json", CONFIG),
+            ("config/b.json", CONFIG),
+            ("mods/a.pw.toml", metafile),
+            ("textures/a.psd", b"8BPS"),
+        ];
+        for (name, data) in files {
+            fs::write(src.join(name), data).unwrap();
+        }
+        let index = format!(r#"hash-format = "sha256"
+
+[[files]]
+file = "config/a.json"
+hash = "{}"
+
+[[files]]
+file = "config/b.json"
+hash = "{}"
+hash-format = "sha1"
+
+[[files]]
+file = "mods/a.pw.toml"
+hash = "{}"
+metafile = true
+
+[[files]]
+file = "textures/a.psd"
+hash = "{}"
+"#, packwiz_hash("sha256", CONFIG).unwrap(), packwiz_hash("sha1", CONFIG).unwrap(),
+            packwiz_hash("sha256", metafile).unwrap(), packwiz_hash("sha256", b"8BPS").unwrap());
+        fs::write(src.join("index.toml"), &index).unwrap();
+        let pack = format!("name = \"Test\"\npack-format = \"packwiz:1.1.0\"\n\n[index]\nfile = \"index.toml\"\nhash-format = \"sha256\"\nhash = \"{}\"\n",
+            packwiz_hash("sha256", index.as_bytes()).unwrap());
+        fs::write(src.join(PACKWIZ_PACK), pack).unwrap();
+
+        let errors = repack(&pack_args(src, out.clone()));
+        assert!(errors.is_empty(), "{errors:?}");
+        let read = |name: &str| fs::read(out.join(name)).unwrap();
+        assert_eq!(read("config/a.json"), b"{\"enabled\":true}");
+        assert_eq!(read("mods/a.pw.toml"), metafile);
+        assert_eq!(read("textures/a.psd"), b"8BPS");
+
+        // Every hash matches the written file (as `packwiz refresh` checks it)
+        let out_index = read("index.toml");
+        let index: toml::Table = toml::from_str(std::str::from_utf8(&out_index).unwrap()).unwrap();
+        let entries = index["files"].as_array().unwrap();
+        assert_eq!(entries.len(), 4);
+        for f in entries {
+            let f = f.as_table().unwrap();
+            let name = toml_str(f, "file").unwrap();
+            let fmt = toml_str(f, "hash-format").unwrap_or("sha256");
+            assert_eq!(toml_str(f, "hash"), packwiz_hash(fmt, &read(name)).as_deref(), "{name}");
+        }
+        assert_eq!(toml_str(entries[1].as_table().unwrap(), "hash").map(str::len), Some(40));
+        let pack: toml::Table = toml::from_str(&fs::read_to_string(out.join(PACKWIZ_PACK)).unwrap()).unwrap();
+        let pack_index = pack["index"].as_table().unwrap();
+        assert_eq!(toml_str(pack_index, "hash"), packwiz_hash("sha256", &out_index).as_deref());
+    }
+}
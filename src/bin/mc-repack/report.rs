@@ -1,27 +1,42 @@
-use std::{fs, io, path};
+use std::{error::Error, fs, io, path};
 
-pub struct Report(Box<path::Path>, Vec<(Box<str>, u64, u64)>);
+use mc_repack_core::errors::{ErrorCollector, FormatMismatch};
+
+pub struct Report(Box<path::Path>, Vec<(Box<str>, u64, u64)>, Vec<(Box<str>, Box<str>)>);
 impl Report {
     pub const fn new(p: Box<path::Path>) -> Self {
-        Self(p, Vec::new())
+        Self(p, Vec::new(), Vec::new())
     }
     pub fn push(&mut self, name: &str, old_size: u64, new_size: u64) {
         self.1.push((name.into(), old_size, new_size));
     }
+    /// Adds notes for entries with content that does not match their extension.
+    pub fn push_mismatches(&mut self, ec: &ErrorCollector) {
+        for ere in ec.results() {
+            let Some(fm) = ere.source().and_then(|e| e.downcast_ref::<FormatMismatch>()) else {
+                continue;
+            };
+            let name = if ere.parent.is_empty() { ere.name.to_string() } else { format!("{}!/{}", ere.parent, ere.name) };
+            self.2.push((name.into(), fm.to_string().into()));
+        }
+    }
     pub fn save_csv(&self) -> io::Result<()> {
         use io::Write;
-        fn write_impl(w: &mut io::BufWriter<fs::File>, v: &[(Box<str>, u64, u64)]) -> io::Result<()> {
-            writeln!(w, "name,old_size,new_size")?;
-            for (name, old_size, new_size) in v {
-                writeln!(w, "{name},{old_size},{new_size}")?;
+        fn write_impl(w: &mut io::BufWriter<fs::File>, r: &Report) -> io::Result<()> {
+            writeln!(w, "name,old_size,new_size,note")?;
+            for (name, old_size, new_size) in &r.1 {
+                writeln!(w, "{name},{old_size},{new_size},")?;
+            }
+            for (name, note) in &r.2 {
+                writeln!(w, "{name},,,{note}")?;
             }
             Ok(())
         }
         let mut w = io::BufWriter::new(fs::File::create(&self.0)?);
-        if let Err(e) = write_impl(&mut w, &self.1) {
+        if let Err(e) = write_impl(&mut w, self) {
             fs::remove_file(&self.0)?;
             return Err(e);
         }
         Ok(())
     }
-}
\ No newline at end of file
+}